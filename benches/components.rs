@@ -0,0 +1,88 @@
+//! Compares walking the fat entity pool against joining sparse component sets.
+//!
+//! Run with `cargo bench --bench components`.
+
+use dth::{
+    collections::{Pool, SparseSet},
+    gfx::Transform,
+    math::Vector3,
+};
+use std::time::{Duration, Instant};
+
+const ENTITIES: usize = 100_000;
+const ITERATIONS: u32 = 200;
+
+#[derive(Default)]
+struct Motion {
+    velocity: Vector3,
+}
+
+// Mirrors the shape of `game::Entity`: every entity carries every (optional) component.
+#[derive(Default)]
+struct FatEntity {
+    transform: Transform,
+    movement: Option<Motion>,
+    _padding: [u64; 16],
+}
+
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    // warm up
+    f();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed: Duration = start.elapsed() / ITERATIONS;
+    println!("{:<40} {:>10.3?}/iter", name, elapsed);
+}
+
+fn main() {
+    for moving_percent in &[1, 10, 50, 100] {
+        let mut pool = Pool::default();
+        let mut transforms = SparseSet::default();
+        let mut motions = SparseSet::default();
+
+        for i in 0..ENTITIES {
+            let moving = i % 100 < *moving_percent;
+            let movement = if moving {
+                Some(Motion {
+                    velocity: Vector3::splat(1.0),
+                })
+            } else {
+                None
+            };
+            let handle = pool.register(FatEntity {
+                movement,
+                ..FatEntity::default()
+            });
+            transforms.insert(handle, Transform::default());
+            if moving {
+                motions.insert(
+                    handle,
+                    Motion {
+                        velocity: Vector3::splat(1.0),
+                    },
+                );
+            }
+        }
+
+        println!("-- {}% of {} entities moving", moving_percent, ENTITIES);
+        bench("pool iter_mut + Option<Motion>", || {
+            for entity in pool.iter_mut() {
+                if let Some(movement) = &entity.movement {
+                    entity.transform.position += movement.velocity * (1.0 / 60.0);
+                }
+            }
+        });
+        bench("sparse set join_mut(transforms, motions)", || {
+            for (_, transform, movement) in transforms.join_mut(&motions) {
+                transform.position += movement.velocity * (1.0 / 60.0);
+            }
+        });
+
+        // Make sure the work above is observable so it can't be optimized away
+        let pool_sum: f32 = pool.iter().map(|e| e.transform.position.x()).sum();
+        let set_sum: f32 = transforms.values().iter().map(|t| t.position.x()).sum();
+        println!("(checksums: {} {})", pool_sum, set_sum);
+    }
+}
@@ -16,7 +16,8 @@ def compile_shader(path: Path):
     out_path: Path = out_path.with_suffix(out_path.suffix + '.spv')
     # build the dir tree down to the output file, if necessary
     out_path.parent.mkdir(parents=True, exist_ok=True)
-    modified_time = os.path.getmtime(path)
+    # included headers count as part of every shader
+    modified_time = max([os.path.getmtime(path)] + [os.path.getmtime(header) for header in cwd.glob('**/*.h')])
     # don't recompile if the modified timestamp hasn't changed
     if not out_path.exists() or modified_time != os.path.getmtime(out_path):
         subprocess.check_call(['glslangValidator', '-V100', '-o', out_path, path])
@@ -0,0 +1,21 @@
+// Generated by uniforms::glsl_header in src/frontend/wgpu/uniforms.rs. Don't edit it, run the
+// uniforms tests with DTH_WRITE_SHADER_HEADERS=1 to update it instead.
+const uint MAX_DIRECTIONAL_LIGHTS = 4;
+const uint MAX_POINT_LIGHTS = 64;
+const uint MAX_SPOT_LIGHTS = 32;
+const uint SHADOW_CASCADES = 4;
+
+// The point and spot light counts of a cluster packed by pack_clusters
+uvec2 unpack_cluster_counts(uint packed) {
+    return uvec2(packed & 65535u, packed >> 16);
+}
+
+// The cluster a view space position is in, like LightClusters::slice and LightClusters::index
+uint cluster_index(vec3 view_space, vec2 max_slope, float near, float far, uvec3 dimensions) {
+    float depth = max(-view_space.z, near);
+    vec2 tile = (view_space.xy / depth + max_slope) / (2.0 * max_slope);
+    // Slices are spaced exponentially
+    float slice = log(depth / near) / log(far / near);
+    uvec3 cluster = uvec3(clamp(vec3(tile, slice) * vec3(dimensions), vec3(0.0), vec3(dimensions - 1u)));
+    return cluster.x + dimensions.x * (cluster.y + dimensions.y * cluster.z);
+}
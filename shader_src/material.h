@@ -0,0 +1,18 @@
+// Generated by material::glsl_header in src/gfx/material.rs. Don't edit it, run the material
+// tests with DTH_WRITE_SHADER_HEADERS=1 to update it instead.
+const uint SHADING_BLINN_PHONG = 0;
+const uint SHADING_METALLIC_ROUGHNESS = 1;
+const float DIELECTRIC_F0 = 0.04;
+const float MIN_ROUGHNESS = 0.04;
+const float BLINN_PHONG_SHININESS = 16.0;
+const uint MAX_TEXTURE_LAYERS = 256;
+
+// The diffuse, specular, emissive and normal texture indices of a material
+uvec4 unpack_texture_indices(uint packed) {
+    return (uvec4(packed) >> uvec4(0, 8, 16, 24)) & 255u;
+}
+
+// The shading model and metallic/roughness texture index of a material
+uvec2 unpack_shading(uint packed) {
+    return uvec2(packed & 255u, (packed >> 8) & 255u);
+}
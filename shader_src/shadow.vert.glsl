@@ -0,0 +1,14 @@
+#version 450
+
+layout(push_constant) uniform LightViewProjection {
+    mat4 light_view_projection;
+};
+
+layout(location = 0) in vec3 position;
+
+// Per-instance
+layout(location = 5) in mat4 model;
+
+void main() {
+    gl_Position = light_view_projection * model * vec4(position, 1.0);
+}
@@ -1,34 +1,38 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
+#extension GL_EXT_nonuniform_qualifier : require
+
+#include "lights.h"
+#include "material.h"
 
 layout(set = 0, binding = 1) uniform View {
     mat4 view;
     vec3 view_position;
 };
 
-layout(push_constant) uniform Model {
-    mat4 model;
-    mat3 inverse_normal;
-    uint tex_indices;
-};
-
 layout(set = 0, binding = 2) uniform sampler sampler0;
 
-layout(set = 1, binding = 0) uniform texture2D diffuse_map[256];
-layout(set = 1, binding = 1) uniform texture2D specular_map[256];
-layout(set = 1, binding = 2) uniform texture2D emissive_map[256];
-layout(set = 1, binding = 3) uniform texture2D normal_map[256];
+layout(set = 1, binding = 0) uniform texture2D diffuse_map[MAX_TEXTURE_LAYERS];
+layout(set = 1, binding = 1) uniform texture2D specular_map[MAX_TEXTURE_LAYERS];
+layout(set = 1, binding = 2) uniform texture2D emissive_map[MAX_TEXTURE_LAYERS];
+layout(set = 1, binding = 3) uniform texture2D normal_map[MAX_TEXTURE_LAYERS];
+// Roughness in green and metalness in blue, like glTF
+layout(set = 1, binding = 4) uniform texture2D metallic_roughness_map[MAX_TEXTURE_LAYERS];
 
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 normal;
 layout(location = 2) in vec2 tex_coord;
 layout(location = 3) in vec4 color;
+layout(location = 4) flat in uint tex_indices;
+layout(location = 5) in vec4 tangent;
+layout(location = 6) flat in uint shading;
 
 layout(location = 0) out vec4 out_color;
 layout(location = 1) out vec4 out_bloom;
 
+// Each vec3 is followed by a float (or padding) so the layout is the same in std140 and std430
 struct DirectionalLight {
     vec3 direction;
-
     vec3 ambient;
     vec3 diffuse;
     vec3 specular;
@@ -36,74 +40,170 @@ struct DirectionalLight {
 
 struct PointLight {
     vec3 position;
-
     float constant;
-    float linear;
-    float quadratic;
-
     vec3 ambient;
+    float linear;
     vec3 diffuse;
+    float quadratic;
     vec3 specular;
 };
 
 struct SpotLight {
     vec3 position;
-    vec3 direction;
-    float cut_off;
-    float outer_cut_off;
-
     float constant;
+    vec3 direction;
     float linear;
-    float quadratic;
-
     vec3 ambient;
+    float quadratic;
     vec3 diffuse;
+    float cut_off;
     vec3 specular;
+    float outer_cut_off;
+};
+
+layout(set = 0, binding = 3) uniform Lights {
+    // directional, point, spot
+    uvec4 light_counts;
+    DirectionalLight directional_lights[MAX_DIRECTIONAL_LIGHTS];
+    PointLight point_lights[MAX_POINT_LIGHTS];
+    SpotLight spot_lights[MAX_SPOT_LIGHTS];
+};
+
+layout(set = 2, binding = 0) uniform ClusterParams {
+    // tan of half the fov across and up
+    vec2 cluster_max_slope;
+    float cluster_near;
+    float cluster_far;
+    uvec4 cluster_dimensions;
+};
+
+// Offset into light_indices, then the point (low 16 bits) and spot (high 16 bits) light counts
+layout(std430, set = 2, binding = 1) readonly buffer Clusters {
+    uvec2 clusters[];
+};
+
+// Each cluster's point light indices followed by its spot light indices
+layout(std430, set = 2, binding = 2) readonly buffer LightIndices {
+    uint light_indices[];
+};
+
+// The cascaded shadow maps of the first directional light
+layout(set = 3, binding = 0) uniform Shadows {
+    mat4 shadow_view_projections[SHADOW_CASCADES];
+    // How far along the view each cascade reaches
+    vec4 shadow_splits;
+    // Zero when there are no shadows
+    uint shadow_cascades;
 };
 
+layout(set = 3, binding = 1) uniform texture2DArray shadow_maps;
+layout(set = 3, binding = 2) uniform samplerShadow shadow_sampler;
+
 const float GAMMA = 2.2;
 
-vec3 directional_light(DirectionalLight light, vec3 normal, vec3 view_direction, vec3 diffuse_sample, float specular_sample) {
+// How much of the first directional light reaches the fragment, from 0.0 to 1.0
+float shadow(vec3 fragment_position) {
+    if (shadow_cascades == 0) {
+        return 1.0;
+    }
+    float depth = -(view * vec4(fragment_position, 1.0)).z;
+    if (depth > shadow_splits[shadow_cascades - 1]) {
+        return 1.0;
+    }
+    uint cascade = 0;
+    while (cascade < shadow_cascades - 1 && depth > shadow_splits[cascade]) {
+        cascade++;
+    }
+
+    vec4 light_clip = shadow_view_projections[cascade] * vec4(fragment_position, 1.0);
+    vec3 ndc = light_clip.xyz / light_clip.w;
+    vec2 uv = vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DArrayShadow(shadow_maps, shadow_sampler), 0).xy);
+
+    // 3x3 percentage closer filtering
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec4 coord = vec4(uv + vec2(x, y) * texel, float(cascade), ndc.z);
+            lit += texture(sampler2DArrayShadow(shadow_maps, shadow_sampler), coord);
+        }
+    }
+    return lit / 9.0;
+}
+
+const float PI = 3.14159265359;
+
+// Everything about the fragment the lights need
+struct Surface {
+    uint shading;
+    vec3 normal;
+    vec3 view_direction;
+    vec3 albedo;
+    // Blinn-Phong only
+    float specular;
+    // Metallic/roughness only
+    float metallic;
+    float roughness;
+};
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+float geometry_schlick_ggx(float n_dot_v, float roughness) {
+    float r = roughness + 1.0;
+    float k = r * r / 8.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// The light reflected towards the viewer from a light shining from light_direction
+// Must match material::cook_torrance
+vec3 reflected(Surface surface, vec3 light_direction, vec3 diffuse_color, vec3 specular_color) {
+    vec3 halfway_dir = normalize(light_direction + surface.view_direction);
+    float n_dot_l = max(dot(surface.normal, light_direction), 0.0);
+    if (surface.shading == SHADING_METALLIC_ROUGHNESS) {
+        float roughness = max(surface.roughness, MIN_ROUGHNESS);
+        float n_dot_v = max(dot(surface.normal, surface.view_direction), 0.0);
+        float n_dot_h = max(dot(surface.normal, halfway_dir), 0.0);
+        // Metals tint their reflections and don't have a diffuse part
+        vec3 f0 = mix(vec3(DIELECTRIC_F0), surface.albedo, surface.metallic);
+        vec3 fresnel = fresnel_schlick(max(dot(halfway_dir, surface.view_direction), 0.0), f0);
+        float geometry = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+        vec3 specular = fresnel * distribution_ggx(n_dot_h, roughness) * geometry / max(4.0 * n_dot_v * n_dot_l, 1e-4);
+        vec3 diffuse = (1.0 - fresnel) * (1.0 - surface.metallic) * surface.albedo / PI;
+        // The light's diffuse color is its radiance
+        return (diffuse + specular) * diffuse_color * n_dot_l;
+    }
+    // blinn-phong
+    float spec = pow(max(dot(surface.normal, halfway_dir), 0.0), BLINN_PHONG_SHININESS);
+    return diffuse_color * n_dot_l * surface.albedo + specular_color * spec * surface.specular;
+}
+
+vec3 directional_light(DirectionalLight light, Surface surface, float lit) {
     vec3 light_direction = normalize(-light.direction);
-    // diffuse shading
-    float diff = max(dot(normal, light_direction), 0.0);
-    // specular shading (blinn-phong)
-    vec3 halfway_dir = normalize(light_direction + view_direction);
-    float spec = pow(max(dot(normal, halfway_dir), 0.0), 16.0);
-    // combine results
-    vec3 ambient = light.ambient * diffuse_sample;
-    vec3 diffuse = light.diffuse * diff * diffuse_sample;
-    vec3 specular = light.specular * spec * specular_sample;
-    return ambient + diffuse + specular;
+    vec3 ambient = light.ambient * surface.albedo;
+    return ambient + reflected(surface, light_direction, light.diffuse, light.specular) * lit;
 }
 
-vec3 point_light(PointLight light, vec3 normal, vec3 fragment_position, vec3 view_direction, vec3 diffuse_sample, float specular_sample) {
+vec3 point_light(PointLight light, Surface surface, vec3 fragment_position) {
     vec3 light_direction = normalize(light.position - fragment_position);
-    // diffuse shading
-    float diff = max(dot(normal, light_direction), 0.0);
-    // specular shading (blinn-phong)
-    vec3 halfway_dir = normalize(light_direction + view_direction);
-    float spec = pow(max(dot(normal, halfway_dir), 0.0), 16.0);
     // attenuation
     float distance = length(light.position - fragment_position);
     float attenuation = 1.0 / (light.constant + light.linear * distance + light.quadratic * (distance * distance));
     // combine results
-    vec3 ambient = light.ambient * diffuse_sample;
-    vec3 diffuse = light.diffuse * diff * diffuse_sample;
-    vec3 specular = light.specular * spec * specular_sample;
-    ambient *= attenuation;
-    diffuse *= attenuation;
-    specular *= attenuation;
-    return ambient + diffuse + specular;
+    vec3 ambient = light.ambient * surface.albedo;
+    return (ambient + reflected(surface, light_direction, light.diffuse, light.specular)) * attenuation;
 }
 
-vec3 spot_light(SpotLight light, vec3 normal, vec3 fragment_position, vec3 view_direction, vec3 diffuse_sample, float specular_sample) {
+vec3 spot_light(SpotLight light, Surface surface, vec3 fragment_position) {
     vec3 light_direction = normalize(light.position - fragment_position);
-    // diffuse shading
-    float diff = max(dot(normal, light_direction), 0.0);
-    // specular shading (blinn-phong)
-    vec3 halfway_dir = normalize(light_direction + view_direction);
-    float spec = pow(max(dot(normal, halfway_dir), 0.0), 16.0);
     // attenuation
     float distance = length(light.position - fragment_position);
     float attenuation = 1.0 / (light.constant + light.linear * distance + light.quadratic * (distance * distance));
@@ -112,43 +212,68 @@ vec3 spot_light(SpotLight light, vec3 normal, vec3 fragment_position, vec3 view_
     float epsilon = light.cut_off - light.outer_cut_off;
     float intensity = clamp((theta - light.outer_cut_off) / epsilon, 0.0, 1.0);
     // combine results
-    vec3 ambient = light.ambient * diffuse_sample;
-    vec3 diffuse = light.diffuse * diff * diffuse_sample;
-    vec3 specular = light.specular * spec * specular_sample;
-    ambient *= attenuation * intensity;
-    diffuse *= attenuation * intensity;
-    specular *= attenuation * intensity;
-    return ambient + diffuse + specular;
+    vec3 ambient = light.ambient * surface.albedo;
+    return (ambient + reflected(surface, light_direction, light.diffuse, light.specular)) * attenuation * intensity;
 }
 
-const DirectionalLight DIRECTIONAL_LIGHT = DirectionalLight(vec3(0.2, -1.0, 0.0), vec3(0.2), vec3(1.0), vec3(1.0));
-const PointLight POINT_LIGHTS[4] = {
-    PointLight(vec3(10.0, 10.0, 10.0), 1.0, 0.09, 0.032, vec3(0.2), vec3(1.0), vec3(1.0)),
-    PointLight(vec3(-10.0, 10.0, -10.0), 1.0, 0.09, 0.032, vec3(0.2), vec3(1.0), vec3(1.0)),
-    PointLight(vec3(10.0, -10.0, 0.0), 1.0, 0.09, 0.032, vec3(0.2), vec3(1.0), vec3(1.0)),
-    PointLight(vec3(-10.0, -10.0, 0.0), 1.0, 0.09, 0.032, vec3(0.2), vec3(1.0), vec3(1.0)),
-};
-
 void main() {
-    uint diffuse_index = tex_indices & 0x000000FF;
-    uint specular_index = tex_indices & 0x0000FF00 >> 8;
-    uint emissive_index = tex_indices & 0x00FF0000 >> 16;
-    uint normal_index = tex_indices & 0xFF000000 >> 24;
+    // Instanced draws mix materials, so the indices can differ between neighbouring fragments
+    uvec4 indices = unpack_texture_indices(tex_indices);
+    uint diffuse_index = indices.x;
+    uint specular_index = indices.y;
+    uint emissive_index = indices.z;
+    uint normal_index = indices.w;
+    uvec2 shading_indices = unpack_shading(shading);
+    uint shading_model = shading_indices.x;
+    uint metallic_roughness_index = shading_indices.y;
 
     // gamma-corrected sampled diffuse
-    vec3 diffuse_sample = pow(texture(sampler2D(diffuse_map[diffuse_index], sampler0), tex_coord).rgb * color.rgb, vec3(GAMMA));
-    float specular_sample = texture(sampler2D(specular_map[specular_index], sampler0), tex_coord).r;
-    float emissive_sample = texture(sampler2D(emissive_map[emissive_index], sampler0), tex_coord).r;
+    vec3 diffuse_sample = pow(texture(sampler2D(diffuse_map[nonuniformEXT(diffuse_index)], sampler0), tex_coord).rgb * color.rgb, vec3(GAMMA));
+    float specular_sample = texture(sampler2D(specular_map[nonuniformEXT(specular_index)], sampler0), tex_coord).r;
+    float emissive_sample = texture(sampler2D(emissive_map[nonuniformEXT(emissive_index)], sampler0), tex_coord).r;
     // convert normal to [-1.0, 1.0]
-    vec3 normal_sample = (texture(sampler2D(normal_map[normal_index], sampler0), tex_coord).rgb * vec3(2.0)) - vec3(1.0);
+    vec3 normal_sample = (texture(sampler2D(normal_map[nonuniformEXT(normal_index)], sampler0), tex_coord).rgb * vec3(2.0)) - vec3(1.0);
 
-    // TODO: perterb normal
     vec3 norm = normalize(normal);
-    vec3 view_direction = normalize(view_position - position);
+    // Perturb the normal in tangent space, unless the mesh came without tangents
+    if (dot(tangent.xyz, tangent.xyz) > 0.0) {
+        vec3 t = normalize(tangent.xyz - norm * dot(norm, tangent.xyz));
+        vec3 b = cross(norm, t) * tangent.w;
+        norm = normalize(mat3(t, b, norm) * normal_sample);
+    }
+
+    Surface surface;
+    surface.shading = shading_model;
+    surface.normal = norm;
+    surface.view_direction = normalize(view_position - position);
+    surface.albedo = diffuse_sample;
+    surface.specular = specular_sample;
+    if (shading_model == SHADING_METALLIC_ROUGHNESS) {
+        vec3 metallic_roughness_sample = texture(sampler2D(metallic_roughness_map[nonuniformEXT(metallic_roughness_index)], sampler0), tex_coord).rgb;
+        surface.roughness = metallic_roughness_sample.g;
+        surface.metallic = metallic_roughness_sample.b;
+    }
 
-    vec3 result = directional_light(DIRECTIONAL_LIGHT, norm, view_direction, diffuse_sample, specular_sample);
-    for (int i = 0; i < 4; i++) {
-        result += point_light(POINT_LIGHTS[i], norm, position, view_direction, diffuse_sample, specular_sample);
+    vec3 result = vec3(0.0);
+    for (uint i = 0; i < light_counts.x; i++) {
+        // Only the first directional light casts shadows
+        float lit = i == 0 ? shadow(position) : 1.0;
+        result += directional_light(directional_lights[i], surface, lit);
+    }
+
+    // Only the point and spot lights touching this fragment's cluster
+    vec3 view_space = (view * vec4(position, 1.0)).xyz;
+    uvec2 cluster = clusters[cluster_index(view_space, cluster_max_slope, cluster_near, cluster_far, cluster_dimensions.xyz)];
+    uvec2 counts = unpack_cluster_counts(cluster.y);
+    uint point_count = counts.x;
+    uint spot_count = counts.y;
+    for (uint i = 0; i < point_count; i++) {
+        PointLight light = point_lights[light_indices[cluster.x + i]];
+        result += point_light(light, surface, position);
+    }
+    for (uint i = 0; i < spot_count; i++) {
+        SpotLight light = spot_lights[light_indices[cluster.x + point_count + i]];
+        result += spot_light(light, surface, position);
     }
 
     // Add emissive
@@ -0,0 +1,46 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform Projection {
+    mat4 projection;
+};
+
+layout(set = 0, binding = 1) uniform View {
+    mat4 view;
+    vec3 view_position;
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 tex_coord;
+layout(location = 3) in vec4 color;
+// xyz along the tex coord's u, w is the handedness of the bitangent
+layout(location = 4) in vec4 tangent;
+
+// Per-instance
+layout(location = 5) in mat4 model;
+layout(location = 9) in mat3 inverse_normal;
+layout(location = 12) in uint tex_indices;
+layout(location = 13) in uint shading;
+
+layout(location = 0) out vec3 out_position;
+layout(location = 1) out vec3 out_normal;
+layout(location = 2) out vec2 out_tex_coord;
+layout(location = 3) out vec4 out_color;
+layout(location = 4) flat out uint out_tex_indices;
+layout(location = 5) out vec4 out_tangent;
+layout(location = 6) flat out uint out_shading;
+
+void main() {
+    vec4 world_position = model * vec4(position, 1.0);
+
+    gl_Position = projection * view * world_position;
+
+    out_position = world_position.xyz;
+    out_normal = inverse_normal * normal;
+    out_tex_coord = tex_coord;
+    out_color = color;
+    out_tex_indices = tex_indices;
+    // Tangents lie along the surface so they transform like positions, unlike normals
+    out_tangent = vec4(mat3(model) * tangent.xyz, tangent.w);
+    out_shading = shading;
+}
@@ -2,10 +2,12 @@ pub use bitmap::*;
 pub use packed_int_vec::*;
 pub use palette_vec::*;
 pub use pool::Pool;
+pub use sparse_set::*;
 pub use xorhash::*;
 
 mod bitmap;
 mod packed_int_vec;
 mod palette_vec;
 pub mod pool;
+mod sparse_set;
 mod xorhash;
@@ -1,4 +1,8 @@
+use crate::util;
 use std::{
+    hash::{Hash, Hasher},
+    io::{self, ErrorKind, Read, Write},
+    iter::Enumerate,
     marker::PhantomData,
     mem::{self, MaybeUninit},
     slice::{Iter, IterMut},
@@ -20,18 +24,71 @@ impl<T> Clone for Handle<T> {
     }
 }
 
+impl<T> PartialEq for Handle<T> {
+    #[inline]
+    fn eq(&self, other: &Handle<T>) -> bool {
+        self.index == other.index && self.epoch == other.epoch
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.epoch.hash(state);
+    }
+}
+
+impl<T> Handle<T> {
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    #[inline]
+    pub fn epoch(&self) -> usize {
+        self.epoch
+    }
+}
+
+/// What a pool knows about a given handle. Mostly useful for diagnosing bad handles.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HandleStatus {
+    /// The handle points at live data.
+    Live,
+    /// The slot exists but the data was removed, or the slot has since been reused
+    /// (i.e. the epochs don't match). `epoch` is the current epoch of the slot.
+    Stale { epoch: usize },
+    /// The pool never allocated a slot at the handle's index.
+    /// Typically this means the handle came from another pool.
+    Unallocated,
+}
+
 #[derive(Debug)]
 struct Entry<T> {
     epoch: usize,
     data: Option<T>,
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct Pool<T> {
     entries: Vec<Entry<T>>,
     free_list: Vec<usize>,
 }
 
+// Derived it would needlessly require `T: Default`
+impl<T> Default for Pool<T> {
+    #[inline]
+    fn default() -> Pool<T> {
+        Pool {
+            entries: Vec::new(),
+            free_list: Vec::new(),
+        }
+    }
+}
+
 impl<T> Pool<T> {
     pub fn register(&mut self, data: T) -> Handle<T> {
         self.register_with_callback(data, |_, _| {})
@@ -76,7 +133,10 @@ impl<T> Pool<T> {
 
     #[inline]
     pub fn remove(&mut self, handle: Handle<T>) -> T {
-        self.try_remove(handle).unwrap()
+        match self.try_remove(handle) {
+            Some(data) => data,
+            None => self.invalid_handle(handle),
+        }
     }
 
     pub fn try_remove(&mut self, handle: Handle<T>) -> Option<T> {
@@ -93,38 +153,36 @@ impl<T> Pool<T> {
 
     #[inline]
     pub fn get(&self, handle: Handle<T>) -> &T {
-        self.try_get(handle).unwrap()
+        match self.try_get(handle) {
+            Some(data) => data,
+            None => self.invalid_handle(handle),
+        }
     }
 
     pub fn try_get(&self, handle: Handle<T>) -> Option<&T> {
-        self.entries
-            .get(handle.index)
-            .and_then(|entry| {
-                if entry.epoch != handle.epoch || entry.data.is_none() {
-                    return None;
-                }
-                Some(&entry.data)
-            })
-            .unwrap()
-            .as_ref()
+        self.entries.get(handle.index).and_then(|entry| {
+            if entry.epoch != handle.epoch {
+                return None;
+            }
+            entry.data.as_ref()
+        })
     }
 
     #[inline]
     pub fn get_mut(&mut self, handle: Handle<T>) -> &mut T {
+        if !self.contains(handle) {
+            self.invalid_handle(handle);
+        }
         self.try_get_mut(handle).unwrap()
     }
 
     pub fn try_get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
-        self.entries
-            .get_mut(handle.index)
-            .and_then(|entry| {
-                if entry.epoch != handle.epoch || entry.data.is_none() {
-                    return None;
-                }
-                Some(&mut entry.data)
-            })
-            .unwrap()
-            .as_mut()
+        self.entries.get_mut(handle.index).and_then(|entry| {
+            if entry.epoch != handle.epoch {
+                return None;
+            }
+            entry.data.as_mut()
+        })
     }
 
     #[inline]
@@ -153,6 +211,90 @@ impl<T> Pool<T> {
         unsafe { mem::transmute_copy(&result) }
     }
 
+    #[inline]
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        self.try_get(handle).is_some()
+    }
+
+    pub fn status(&self, handle: Handle<T>) -> HandleStatus {
+        match self.entries.get(handle.index) {
+            Some(entry) if entry.epoch == handle.epoch && entry.data.is_some() => {
+                HandleStatus::Live
+            }
+            Some(entry) => HandleStatus::Stale { epoch: entry.epoch },
+            None => HandleStatus::Unallocated,
+        }
+    }
+
+    /// The number of live items in the pool.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len() - self.free_list.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of slots in the pool (both live and free).
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Remove every item for which `f` returns `false`.
+    ///
+    /// Removed slots are freed for re-use exactly like `remove` would, so outstanding
+    /// handles to them go stale.
+    pub fn retain<F: FnMut(Handle<T>, &mut T) -> bool>(&mut self, mut f: F) {
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            if let Some(data) = entry.data.as_mut() {
+                let handle = Handle {
+                    index,
+                    epoch: entry.epoch,
+                    marker: PhantomData,
+                };
+                if !f(handle, data) {
+                    entry.data = None;
+                    self.free_list.push(index);
+                }
+            }
+        }
+    }
+
+    /// Remove every item from the pool, yielding them along with their (now stale) handles.
+    ///
+    /// Slot epochs are kept, so handles from before the drain will never resolve again.
+    /// Any items not consumed by the iterator are dropped along with it.
+    pub fn drain(&mut self) -> PoolDrain<T> {
+        // Every slot is free once the drain is done. Reverse so the lowest indices get reused
+        // first.
+        self.free_list.clear();
+        self.free_list.extend((0..self.entries.len()).rev());
+        PoolDrain {
+            inner: self.entries.iter_mut().enumerate(),
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn invalid_handle(&self, handle: Handle<T>) -> ! {
+        if cfg!(debug_assertions) {
+            panic!(
+                "Invalid handle (index: {}, epoch: {}) is {:?}",
+                handle.index,
+                handle.epoch,
+                self.status(handle)
+            );
+        } else {
+            panic!(
+                "Invalid handle (index: {}, epoch: {})",
+                handle.index, handle.epoch
+            );
+        }
+    }
+
     #[inline]
     pub fn iter(&self) -> PoolIter<T> {
         PoolIter {
@@ -166,6 +308,125 @@ impl<T> Pool<T> {
             inner: self.entries.iter_mut(),
         }
     }
+
+    #[inline]
+    pub fn iter_with_handles(&self) -> PoolHandleIter<T> {
+        PoolHandleIter {
+            inner: self.entries.iter().enumerate(),
+        }
+    }
+
+    #[inline]
+    pub fn iter_mut_with_handles(&mut self) -> PoolHandleIterMut<T> {
+        PoolHandleIterMut {
+            inner: self.entries.iter_mut().enumerate(),
+        }
+    }
+}
+
+/// Encodes and decodes individual pool items for pool snapshots.
+pub trait PoolItemCodec<T> {
+    fn write_item<W: Write>(&mut self, writer: &mut W, item: &T) -> io::Result<()>;
+
+    fn read_item<R: Read>(&mut self, reader: &mut R) -> io::Result<T>;
+}
+
+const SNAPSHOT_MAGIC: u32 = u32::from_le_bytes([b'P', b'O', b'O', b'L']);
+const SNAPSHOT_VERSION: u32 = 1;
+
+impl<T> Pool<T> {
+    /// Write a binary snapshot of the pool.
+    ///
+    /// Every slot is written along with its epoch, as well as the free list. This means
+    /// any handle into this pool resolves to the same item in the pool read back from
+    /// `read_snapshot` (and new registrations hand out the same handles too).
+    pub fn write_snapshot<W: Write, C: PoolItemCodec<T>>(
+        &self,
+        writer: &mut W,
+        codec: &mut C,
+    ) -> io::Result<()> {
+        util::write_u32(writer, SNAPSHOT_MAGIC)?;
+        util::write_u32(writer, SNAPSHOT_VERSION)?;
+        util::write_u64(writer, self.entries.len() as u64)?;
+        for entry in &self.entries {
+            util::write_u64(writer, entry.epoch as u64)?;
+            if let Some(data) = &entry.data {
+                util::write_u8(writer, 1)?;
+                codec.write_item(writer, data)?;
+            } else {
+                util::write_u8(writer, 0)?;
+            }
+        }
+        util::write_u64(writer, self.free_list.len() as u64)?;
+        for index in &self.free_list {
+            util::write_u64(writer, *index as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Read a pool back from a snapshot written by `write_snapshot`.
+    pub fn read_snapshot<R: Read, C: PoolItemCodec<T>>(
+        reader: &mut R,
+        codec: &mut C,
+    ) -> io::Result<Pool<T>> {
+        let magic = util::read_u32(reader)?;
+        if magic != SNAPSHOT_MAGIC {
+            return util::io_err(
+                ErrorKind::InvalidData,
+                format!(
+                    "Expected a 'POOL' ({:08X}) instead found {:08X}",
+                    SNAPSHOT_MAGIC, magic
+                ),
+            );
+        }
+        let version = util::read_u32(reader)?;
+        if version != SNAPSHOT_VERSION {
+            return util::io_err(
+                ErrorKind::InvalidData,
+                format!("Unsupported pool snapshot version: {}", version),
+            );
+        }
+
+        let len = util::read_u64(reader)? as usize;
+        let mut entries = Vec::new();
+        for _ in 0..len {
+            let epoch = util::read_u64(reader)? as usize;
+            let data = match util::read_u8(reader)? {
+                0 => None,
+                1 => Some(codec.read_item(reader)?),
+                tag => {
+                    return util::io_err(
+                        ErrorKind::InvalidData,
+                        format!("Invalid pool entry tag: {:02X}", tag),
+                    );
+                }
+            };
+            entries.push(Entry { epoch, data });
+        }
+
+        let free_len = util::read_u64(reader)? as usize;
+        let mut free_list = Vec::new();
+        let mut freed = vec![false; len];
+        for _ in 0..free_len {
+            let index = util::read_u64(reader)? as usize;
+            if index >= len || entries[index].data.is_some() || freed[index] {
+                return util::io_err(
+                    ErrorKind::InvalidData,
+                    format!("Invalid free slot in pool snapshot: {}", index),
+                );
+            }
+            freed[index] = true;
+            free_list.push(index);
+        }
+        if entries.iter().filter(|entry| entry.data.is_none()).count() != free_len {
+            return util::io_err(
+                ErrorKind::InvalidData,
+                "Pool snapshot has empty slots missing from the free list",
+            );
+        }
+
+        Ok(Pool { entries, free_list })
+    }
 }
 
 pub struct PoolIter<'a, T: 'a> {
@@ -209,3 +470,305 @@ impl<'a, T> Iterator for PoolIterMut<'a, T> {
         }
     }
 }
+
+pub struct PoolHandleIter<'a, T: 'a> {
+    inner: Enumerate<Iter<'a, Entry<T>>>,
+}
+
+impl<'a, T> Iterator for PoolHandleIter<'a, T> {
+    type Item = (Handle<T>, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<(Handle<T>, &'a T)> {
+        for (index, next) in &mut self.inner {
+            if let Some(data) = next.data.as_ref() {
+                let handle = Handle {
+                    index,
+                    epoch: next.epoch,
+                    marker: PhantomData,
+                };
+                return Some((handle, data));
+            }
+        }
+        None
+    }
+}
+
+pub struct PoolHandleIterMut<'a, T: 'a> {
+    inner: Enumerate<IterMut<'a, Entry<T>>>,
+}
+
+impl<'a, T> Iterator for PoolHandleIterMut<'a, T> {
+    type Item = (Handle<T>, &'a mut T);
+
+    #[inline]
+    fn next(&mut self) -> Option<(Handle<T>, &'a mut T)> {
+        for (index, next) in &mut self.inner {
+            if let Some(data) = next.data.as_mut() {
+                let handle = Handle {
+                    index,
+                    epoch: next.epoch,
+                    marker: PhantomData,
+                };
+                return Some((handle, data));
+            }
+        }
+        None
+    }
+}
+
+pub struct PoolDrain<'a, T: 'a> {
+    inner: Enumerate<IterMut<'a, Entry<T>>>,
+}
+
+impl<'a, T> Iterator for PoolDrain<'a, T> {
+    type Item = (Handle<T>, T);
+
+    #[inline]
+    fn next(&mut self) -> Option<(Handle<T>, T)> {
+        for (index, next) in &mut self.inner {
+            if let Some(data) = next.data.take() {
+                let handle = Handle {
+                    index,
+                    epoch: next.epoch,
+                    marker: PhantomData,
+                };
+                return Some((handle, data));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T> Drop for PoolDrain<'a, T> {
+    fn drop(&mut self) {
+        for _ in self {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    struct U32Codec;
+
+    impl PoolItemCodec<u32> for U32Codec {
+        fn write_item<W: Write>(&mut self, writer: &mut W, item: &u32) -> io::Result<()> {
+            util::write_u32(writer, *item)
+        }
+
+        fn read_item<R: Read>(&mut self, reader: &mut R) -> io::Result<u32> {
+            util::read_u32(reader)
+        }
+    }
+
+    fn round_trip(p: &Pool<u32>) -> Pool<u32> {
+        let mut bytes = Vec::new();
+        p.write_snapshot(&mut bytes, &mut U32Codec).unwrap();
+        Pool::read_snapshot(&mut Cursor::new(bytes), &mut U32Codec).unwrap()
+    }
+
+    #[test]
+    fn reuses_slots() {
+        let mut p = Pool::default();
+        let a = p.register(1);
+        let b = p.register(2);
+        assert_eq!(2, p.len());
+        assert_eq!(2, p.capacity());
+
+        assert_eq!(1, p.remove(a));
+        assert_eq!(1, p.len());
+        assert!(!p.contains(a));
+        assert!(p.contains(b));
+
+        let c = p.register(3);
+        assert_eq!(a.index(), c.index());
+        assert_eq!(a.epoch() + 1, c.epoch());
+        assert_eq!(2, p.capacity());
+        assert_eq!(None, p.try_get(a));
+        assert_eq!(Some(&3), p.try_get(c));
+    }
+
+    #[test]
+    fn foreign_handles() {
+        let mut p = Pool::default();
+        let mut q = Pool::default();
+        p.register(1);
+        q.register(2);
+        let h = q.register(3);
+
+        assert_eq!(None, p.try_get(h));
+        assert_eq!(None, p.try_get_mut(h));
+        assert_eq!(None, p.try_remove(h));
+        assert_eq!(HandleStatus::Unallocated, p.status(h));
+    }
+
+    #[test]
+    fn statuses() {
+        let mut p = Pool::default();
+        let a = p.register(1);
+        assert_eq!(HandleStatus::Live, p.status(a));
+
+        p.remove(a);
+        assert_eq!(HandleStatus::Stale { epoch: 1 }, p.status(a));
+
+        let b = p.register(2);
+        assert_eq!(HandleStatus::Stale { epoch: 2 }, p.status(a));
+        assert_eq!(HandleStatus::Live, p.status(b));
+    }
+
+    #[test]
+    #[should_panic(expected = "Stale")]
+    #[cfg(debug_assertions)]
+    fn stale_get_panics() {
+        let mut p = Pool::default();
+        let a = p.register(1);
+        p.remove(a);
+        p.register(2);
+        p.get(a);
+    }
+
+    #[test]
+    fn iterates_with_handles() {
+        let mut p = Pool::default();
+        let handles: Vec<_> = (0..4).map(|i| p.register(i)).collect();
+        p.remove(handles[1]);
+
+        let seen: Vec<_> = p
+            .iter_with_handles()
+            .map(|(h, i)| (h.index(), *i))
+            .collect();
+        assert_eq!(vec![(0, 0), (2, 2), (3, 3)], seen);
+
+        for (handle, i) in p.iter_mut_with_handles() {
+            *i += handle.index() * 10;
+        }
+        assert_eq!(22, *p.get(handles[2]));
+        assert_eq!(33, *p.get(handles[3]));
+    }
+
+    #[test]
+    fn retains() {
+        let mut p = Pool::default();
+        let handles: Vec<_> = (0..8).map(|i| p.register(i)).collect();
+        p.retain(|_, i| *i % 2 == 0);
+        assert_eq!(4, p.len());
+        assert_eq!(8, p.capacity());
+        for (i, handle) in handles.iter().enumerate() {
+            assert_eq!(i % 2 == 0, p.contains(*handle));
+        }
+
+        // freed slots get reused before growing
+        for i in 0..4 {
+            p.register(i);
+        }
+        assert_eq!(8, p.len());
+        assert_eq!(8, p.capacity());
+    }
+
+    #[test]
+    fn drains() {
+        let mut p = Pool::default();
+        let handles: Vec<_> = (0..4).map(|i| p.register(i)).collect();
+        p.remove(handles[2]);
+
+        let drained: Vec<_> = p.drain().map(|(h, i)| (h.index(), i)).collect();
+        assert_eq!(vec![(0, 0), (1, 1), (3, 3)], drained);
+        assert!(p.is_empty());
+        assert_eq!(4, p.capacity());
+        for handle in &handles {
+            assert!(!p.contains(*handle));
+        }
+
+        let a = p.register(42);
+        assert_eq!(0, a.index());
+        assert_eq!(4, p.capacity());
+    }
+
+    #[test]
+    fn partial_drains() {
+        let mut p = Pool::default();
+        for i in 0..4 {
+            p.register(i);
+        }
+        assert_eq!(Some(0), p.drain().next().map(|(_, i)| i));
+        assert!(p.is_empty());
+        assert_eq!(0, p.iter().count());
+    }
+
+    #[test]
+    fn snapshots_with_holes() {
+        let mut p = Pool::default();
+        let handles: Vec<_> = (0..8).map(|i| p.register(i)).collect();
+        p.remove(handles[1]);
+        p.remove(handles[6]);
+        p.remove(handles[3]);
+
+        let q = round_trip(&p);
+        assert_eq!(p.len(), q.len());
+        assert_eq!(p.capacity(), q.capacity());
+        for handle in &handles {
+            assert_eq!(p.status(*handle), q.status(*handle));
+            assert_eq!(p.try_get(*handle), q.try_get(*handle));
+        }
+    }
+
+    #[test]
+    fn snapshots_reused_slots() {
+        let mut p = Pool::default();
+        let a = p.register(1);
+        let b = p.register(2);
+        p.remove(a);
+        let c = p.register(3);
+        p.remove(c);
+        let d = p.register(4);
+        assert_eq!(3, d.epoch());
+
+        let q = round_trip(&p);
+        assert_eq!(None, q.try_get(a));
+        assert_eq!(None, q.try_get(c));
+        assert_eq!(Some(&2), q.try_get(b));
+        assert_eq!(Some(&4), q.try_get(d));
+        assert_eq!(HandleStatus::Stale { epoch: 3 }, q.status(a));
+    }
+
+    #[test]
+    fn snapshots_free_list() {
+        let mut p = Pool::default();
+        let handles: Vec<_> = (0..4).map(|i| p.register(i)).collect();
+        p.remove(handles[0]);
+        p.remove(handles[2]);
+
+        let mut q = round_trip(&p);
+        for i in 0..3 {
+            let expected = p.register(10 + i);
+            let actual = q.register(10 + i);
+            assert_eq!(expected.index(), actual.index());
+            assert_eq!(expected.epoch(), actual.epoch());
+        }
+    }
+
+    #[test]
+    fn rejects_bad_snapshots() {
+        let mut p = Pool::default();
+        let a = p.register(1);
+        p.register(2);
+        p.remove(a);
+        let mut bytes = Vec::new();
+        p.write_snapshot(&mut bytes, &mut U32Codec).unwrap();
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(Pool::read_snapshot(&mut Cursor::new(bad_magic), &mut U32Codec).is_err());
+
+        // Point the only free slot at the live item
+        let mut bad_free_list = bytes.clone();
+        let last = bad_free_list.len() - 8;
+        bad_free_list[last] = 1;
+        assert!(Pool::read_snapshot(&mut Cursor::new(bad_free_list), &mut U32Codec).is_err());
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(Pool::read_snapshot(&mut Cursor::new(truncated), &mut U32Codec).is_err());
+    }
+}
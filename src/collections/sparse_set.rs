@@ -0,0 +1,376 @@
+use crate::collections::pool::Handle;
+use std::{
+    iter::Zip,
+    slice::{Iter, IterMut},
+};
+
+const EMPTY: usize = usize::MAX;
+
+/// A map from pool handles to `T` that keeps its values tightly packed.
+///
+/// Values live in a dense array (in no particular order) and a sparse array indexed by
+/// `Handle::index` points into it. Iterating only ever touches handles that actually
+/// have a value, which makes it a good fit for components only a few entities have.
+///
+/// A value is only reachable through the exact handle (index *and* epoch) it was inserted
+/// with, so stale handles never see data meant for a re-used slot.
+#[derive(Debug)]
+pub struct SparseSet<K, T> {
+    sparse: Vec<usize>,
+    handles: Vec<Handle<K>>,
+    values: Vec<T>,
+}
+
+impl<K, T> Default for SparseSet<K, T> {
+    #[inline]
+    fn default() -> SparseSet<K, T> {
+        SparseSet {
+            sparse: Vec::new(),
+            handles: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<K, T> SparseSet<K, T> {
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> SparseSet<K, T> {
+        SparseSet {
+            sparse: Vec::with_capacity(capacity),
+            handles: Vec::with_capacity(capacity),
+            values: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Insert a value for a handle, returning the value that was previously stored at the
+    /// handle's index (if any). An older value for a stale handle to the same slot is replaced.
+    pub fn insert(&mut self, handle: Handle<K>, value: T) -> Option<T> {
+        let index = handle.index();
+        if index >= self.sparse.len() {
+            self.sparse.resize(index + 1, EMPTY);
+        }
+        let dense = self.sparse[index];
+        if dense != EMPTY {
+            self.handles[dense] = handle;
+            return Some(std::mem::replace(&mut self.values[dense], value));
+        }
+        self.sparse[index] = self.values.len();
+        self.handles.push(handle);
+        self.values.push(value);
+        None
+    }
+
+    pub fn remove(&mut self, handle: Handle<K>) -> Option<T> {
+        let dense = self.dense_index(handle)?;
+        self.sparse[handle.index()] = EMPTY;
+        self.handles.swap_remove(dense);
+        let value = self.values.swap_remove(dense);
+        // The last value was moved into the hole, so point its slot at the new position
+        if let Some(moved) = self.handles.get(dense) {
+            self.sparse[moved.index()] = dense;
+        }
+        Some(value)
+    }
+
+    #[inline]
+    pub fn get(&self, handle: Handle<K>) -> Option<&T> {
+        self.dense_index(handle).map(|dense| &self.values[dense])
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, handle: Handle<K>) -> Option<&mut T> {
+        match self.dense_index(handle) {
+            Some(dense) => Some(&mut self.values[dense]),
+            None => None,
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, handle: Handle<K>) -> bool {
+        self.dense_index(handle).is_some()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.sparse.clear();
+        self.handles.clear();
+        self.values.clear();
+    }
+
+    /// Remove every value for which `f` returns `false`.
+    pub fn retain<F: FnMut(Handle<K>, &mut T) -> bool>(&mut self, mut f: F) {
+        let mut dense = 0;
+        while dense < self.values.len() {
+            let handle = self.handles[dense];
+            if f(handle, &mut self.values[dense]) {
+                dense += 1;
+            } else {
+                // Don't advance: the value swapped into `dense` still needs a look
+                self.remove(handle);
+            }
+        }
+    }
+
+    /// The handles with values, in the same order as `values`.
+    #[inline]
+    pub fn handles(&self) -> &[Handle<K>] {
+        &self.handles
+    }
+
+    #[inline]
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    #[inline]
+    pub fn values_mut(&mut self) -> &mut [T] {
+        &mut self.values
+    }
+
+    #[inline]
+    pub fn iter(&self) -> Zip<Iter<Handle<K>>, Iter<T>> {
+        self.handles.iter().zip(self.values.iter())
+    }
+
+    #[inline]
+    pub fn iter_mut(&mut self) -> Zip<Iter<Handle<K>>, IterMut<T>> {
+        self.handles.iter().zip(self.values.iter_mut())
+    }
+
+    /// Iterate the handles that have a value in both `self` and `other`.
+    ///
+    /// Walks whichever set is smaller.
+    pub fn join<'a, U>(
+        &'a self,
+        other: &'a SparseSet<K, U>,
+    ) -> impl Iterator<Item = (Handle<K>, &'a T, &'a U)> + 'a {
+        let driver = if self.len() <= other.len() {
+            &self.handles
+        } else {
+            &other.handles
+        };
+        driver
+            .iter()
+            .filter_map(move |handle| Some((*handle, self.get(*handle)?, other.get(*handle)?)))
+    }
+
+    /// Iterate the handles that have a value in `self`, `b` and `c`.
+    ///
+    /// Walks whichever set is smallest.
+    pub fn join3<'a, U, V>(
+        &'a self,
+        b: &'a SparseSet<K, U>,
+        c: &'a SparseSet<K, V>,
+    ) -> impl Iterator<Item = (Handle<K>, &'a T, &'a U, &'a V)> + 'a {
+        let mut driver = &self.handles;
+        if b.len() < driver.len() {
+            driver = &b.handles;
+        }
+        if c.len() < driver.len() {
+            driver = &c.handles;
+        }
+        driver.iter().filter_map(move |handle| {
+            Some((
+                *handle,
+                self.get(*handle)?,
+                b.get(*handle)?,
+                c.get(*handle)?,
+            ))
+        })
+    }
+
+    /// Like `join` but values in `self` are mutable.
+    ///
+    /// Walks whichever set is smaller.
+    pub fn join_mut<'a, U>(
+        &'a mut self,
+        other: &'a SparseSet<K, U>,
+    ) -> impl Iterator<Item = (Handle<K>, &'a mut T, &'a U)> + 'a {
+        let SparseSet {
+            sparse,
+            handles,
+            values,
+        } = self;
+        let (sparse, handles) = (&*sparse, &*handles);
+        let driver = if handles.len() <= other.len() {
+            handles
+        } else {
+            &other.handles
+        };
+        let values = values.as_mut_ptr();
+        driver.iter().filter_map(move |handle| {
+            let dense = dense_index(sparse, handles, *handle)?;
+            let other = other.get(*handle)?;
+            // Safety: Every handle in a set has a unique index, so each dense index is visited
+            // at most once and no two `&mut T` can alias. The values can't be reallocated while
+            // self is mutably borrowed.
+            Some((*handle, unsafe { &mut *values.add(dense) }, other))
+        })
+    }
+
+    /// Like `join3` but values in `self` are mutable.
+    ///
+    /// Walks whichever set is smallest.
+    pub fn join3_mut<'a, U, V>(
+        &'a mut self,
+        b: &'a SparseSet<K, U>,
+        c: &'a SparseSet<K, V>,
+    ) -> impl Iterator<Item = (Handle<K>, &'a mut T, &'a U, &'a V)> + 'a {
+        let SparseSet {
+            sparse,
+            handles,
+            values,
+        } = self;
+        let (sparse, handles) = (&*sparse, &*handles);
+        let mut driver = handles;
+        if b.len() < driver.len() {
+            driver = &b.handles;
+        }
+        if c.len() < driver.len() {
+            driver = &c.handles;
+        }
+        let values = values.as_mut_ptr();
+        driver.iter().filter_map(move |handle| {
+            let dense = dense_index(sparse, handles, *handle)?;
+            let b = b.get(*handle)?;
+            let c = c.get(*handle)?;
+            // Safety: See `join_mut`.
+            Some((*handle, unsafe { &mut *values.add(dense) }, b, c))
+        })
+    }
+
+    #[inline]
+    fn dense_index(&self, handle: Handle<K>) -> Option<usize> {
+        dense_index(&self.sparse, &self.handles, handle)
+    }
+}
+
+#[inline]
+fn dense_index<K>(sparse: &[usize], handles: &[Handle<K>], handle: Handle<K>) -> Option<usize> {
+    match sparse.get(handle.index()) {
+        Some(&dense) if dense != EMPTY && handles[dense].epoch() == handle.epoch() => Some(dense),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::collections::Pool;
+
+    #[test]
+    fn inserts_and_removes() {
+        let mut pool = Pool::default();
+        let handles: Vec<_> = (0..8).map(|i| pool.register(i)).collect();
+
+        let mut set = SparseSet::default();
+        for handle in handles.iter().step_by(2) {
+            assert_eq!(None, set.insert(*handle, *pool.get(*handle) * 10));
+        }
+        assert_eq!(4, set.len());
+        assert_eq!(Some(&20), set.get(handles[2]));
+        assert_eq!(None, set.get(handles[3]));
+
+        assert_eq!(Some(20), set.remove(handles[2]));
+        assert_eq!(None, set.remove(handles[2]));
+        assert_eq!(3, set.len());
+
+        // Everything else should still be reachable after the swap-remove
+        assert_eq!(Some(&0), set.get(handles[0]));
+        assert_eq!(Some(&40), set.get(handles[4]));
+        assert_eq!(Some(&60), set.get(handles[6]));
+    }
+
+    #[test]
+    fn ignores_stale_handles() {
+        let mut pool = Pool::default();
+        let a = pool.register(1);
+        let mut set = SparseSet::default();
+        set.insert(a, "a");
+
+        pool.remove(a);
+        let b = pool.register(2);
+        assert_eq!(a.index(), b.index());
+        assert_eq!(None, set.get(b));
+        assert!(!set.contains(b));
+
+        // Inserting for the new handle replaces the stale value
+        assert_eq!(Some("a"), set.insert(b, "b"));
+        assert_eq!(None, set.get(a));
+        assert_eq!(Some(&"b"), set.get(b));
+    }
+
+    #[test]
+    fn retains() {
+        let mut pool = Pool::default();
+        let mut set = SparseSet::default();
+        for i in 0..16 {
+            set.insert(pool.register(()), i);
+        }
+        set.retain(|_, i| *i % 3 == 0);
+        let mut values: Vec<_> = set.values().to_vec();
+        values.sort_unstable();
+        assert_eq!(vec![0, 3, 6, 9, 12, 15], values);
+        for (handle, value) in set.iter() {
+            assert_eq!(Some(value), set.get(*handle));
+        }
+    }
+
+    #[test]
+    fn joins() {
+        let mut pool = Pool::default();
+        let handles: Vec<_> = (0..12).map(|_| pool.register(())).collect();
+
+        let mut twos = SparseSet::default();
+        let mut threes = SparseSet::default();
+        let mut fours = SparseSet::default();
+        for (i, handle) in handles.iter().enumerate() {
+            if i % 2 == 0 {
+                twos.insert(*handle, i);
+            }
+            if i % 3 == 0 {
+                threes.insert(*handle, i as f32);
+            }
+            if i % 4 == 0 {
+                fours.insert(*handle, i as u8);
+            }
+        }
+
+        let mut joined: Vec<_> = twos.join(&threes).map(|(_, i, _)| *i).collect();
+        joined.sort_unstable();
+        assert_eq!(vec![0, 6], joined);
+
+        let mut joined: Vec<_> = threes.join3(&twos, &fours).map(|(_, _, i, _)| *i).collect();
+        joined.sort_unstable();
+        assert_eq!(vec![0], joined);
+
+        for (_, i, three) in twos.join_mut(&threes) {
+            *i += *three as usize;
+        }
+        assert_eq!(Some(&12), twos.get(handles[6]));
+        assert_eq!(Some(&2), twos.get(handles[2]));
+
+        // Driven by the smaller set this time
+        for (_, three, _) in threes.join_mut(&fours) {
+            *three = -1.0;
+        }
+        assert_eq!(Some(&-1.0), threes.get(handles[0]));
+        assert_eq!(Some(&3.0), threes.get(handles[3]));
+
+        for (_, i, _, four) in twos.join3_mut(&threes, &fours) {
+            *i = *four as usize + 100;
+        }
+        assert_eq!(Some(&100), twos.get(handles[0]));
+        assert_eq!(Some(&12), twos.get(handles[6]));
+    }
+}
@@ -1 +1,2 @@
+pub mod sdl;
 pub mod wgpu;
@@ -0,0 +1,106 @@
+use sdl2::{controller, event::Event, keyboard::Keycode, mouse::MouseButton as SdlMouseButton};
+
+use crate::input::{Binding, GamepadAxis, GamepadButton, InputEvent, Key, MouseButton};
+
+// Both sides mostly agree on names, so map them one-to-one.
+macro_rules! same_names {
+    ($value:expr, $from:ident => $to:ident { $($variant:ident),* $(,)? } $($extra:tt)*) => {
+        match $value {
+            $($from::$variant => Some($to::$variant),)*
+            $($extra)*
+        }
+    };
+}
+
+/// Translate an SDL event for `Input::handle`.
+///
+/// Returns `None` for events (or keys, buttons...) that have no equivalent.
+pub fn input_event(event: &Event) -> Option<InputEvent> {
+    match event {
+        Event::KeyDown {
+            keycode: Some(keycode),
+            repeat: false,
+            ..
+        } => key(*keycode).map(|key| InputEvent::Down(Binding::Key(key))),
+        Event::KeyUp {
+            keycode: Some(keycode),
+            ..
+        } => key(*keycode).map(|key| InputEvent::Up(Binding::Key(key))),
+        Event::MouseButtonDown { mouse_btn, .. } => {
+            mouse_button(*mouse_btn).map(|button| InputEvent::Down(Binding::Mouse(button)))
+        }
+        Event::MouseButtonUp { mouse_btn, .. } => {
+            mouse_button(*mouse_btn).map(|button| InputEvent::Up(Binding::Mouse(button)))
+        }
+        Event::MouseMotion { xrel, yrel, .. } => Some(InputEvent::MouseMotion {
+            dx: *xrel as f32,
+            dy: *yrel as f32,
+        }),
+        Event::MouseWheel { y, .. } => Some(InputEvent::MouseWheel(*y as f32)),
+        Event::ControllerButtonDown { button, .. } => {
+            Some(InputEvent::Down(Binding::Gamepad(gamepad_button(*button))))
+        }
+        Event::ControllerButtonUp { button, .. } => {
+            Some(InputEvent::Up(Binding::Gamepad(gamepad_button(*button))))
+        }
+        Event::ControllerAxisMotion { axis, value, .. } => {
+            // i16 isn't symmetric, so clamp the one extra step on the negative side
+            let value = (*value as f32 / i16::MAX as f32).max(-1.0);
+            Some(InputEvent::GamepadAxis(gamepad_axis(*axis), value))
+        }
+        _ => None,
+    }
+}
+
+pub fn key(keycode: Keycode) -> Option<Key> {
+    same_names!(keycode, Keycode => Key {
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9,
+        F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+        Up, Down, Left, Right,
+        Space, Escape, Tab, Backspace,
+        LShift, RShift, LCtrl, RCtrl, LAlt, RAlt,
+    }
+        Keycode::Return | Keycode::KpEnter => Some(Key::Enter),
+        _ => None,
+    )
+}
+
+pub fn mouse_button(button: SdlMouseButton) -> Option<MouseButton> {
+    same_names!(button, SdlMouseButton => MouseButton { Left, Middle, Right, X1, X2 }
+        SdlMouseButton::Unknown => None,
+    )
+}
+
+pub fn gamepad_button(button: controller::Button) -> GamepadButton {
+    use controller::Button;
+    match button {
+        Button::A => GamepadButton::A,
+        Button::B => GamepadButton::B,
+        Button::X => GamepadButton::X,
+        Button::Y => GamepadButton::Y,
+        Button::Back => GamepadButton::Back,
+        Button::Guide => GamepadButton::Guide,
+        Button::Start => GamepadButton::Start,
+        Button::LeftStick => GamepadButton::LeftStick,
+        Button::RightStick => GamepadButton::RightStick,
+        Button::LeftShoulder => GamepadButton::LeftShoulder,
+        Button::RightShoulder => GamepadButton::RightShoulder,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+    }
+}
+
+pub fn gamepad_axis(axis: controller::Axis) -> GamepadAxis {
+    use controller::Axis;
+    match axis {
+        Axis::LeftX => GamepadAxis::LeftX,
+        Axis::LeftY => GamepadAxis::LeftY,
+        Axis::RightX => GamepadAxis::RightX,
+        Axis::RightY => GamepadAxis::RightY,
+        Axis::TriggerLeft => GamepadAxis::TriggerLeft,
+        Axis::TriggerRight => GamepadAxis::TriggerRight,
+    }
+}
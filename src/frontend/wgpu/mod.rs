@@ -0,0 +1,7 @@
+mod renderer;
+mod target;
+mod texture;
+mod uniforms;
+
+pub use renderer::*;
+pub use texture::{TextureClass, TextureHandle, TextureLayer};
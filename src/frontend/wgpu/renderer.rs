@@ -0,0 +1,1589 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    mem,
+    num::{NonZeroU32, NonZeroU64},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use futures::executor;
+use raw_window_handle::HasRawWindowHandle;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    Adapter, AddressMode, BackendBit, BindGroup, BindGroupDescriptor, BindGroupEntry,
+    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
+    BlendFactor, BlendOperation, BlendState, Buffer, BufferBindingType, BufferDescriptor,
+    BufferUsage, Color, ColorTargetState, ColorWrite, CommandEncoder, CommandEncoderDescriptor,
+    CompareFunction, CullMode, DepthBiasState, DepthStencilState, Device, DeviceDescriptor,
+    Features, FilterMode, FragmentState, FrontFace, IndexFormat, InputStepMode, Instance, Limits,
+    LoadOp, MultisampleState, Operations, PipelineLayout, PipelineLayoutDescriptor, PolygonMode,
+    PowerPreference, PresentMode, PrimitiveState, PrimitiveTopology, PushConstantRange, Queue,
+    RenderPass, RenderPassColorAttachmentDescriptor, RenderPassDepthStencilAttachmentDescriptor,
+    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, RequestAdapterOptions, Sampler,
+    SamplerDescriptor, ShaderFlags, ShaderModule, ShaderModuleDescriptor, ShaderStage,
+    StencilFaceState, StencilState, TextureFormat, TextureSampleType, TextureView,
+    TextureViewDimension, VertexBufferLayout, VertexState,
+};
+
+use crate::{
+    frontend::wgpu::{
+        target::{ShadowTarget, WindowTarget, DEPTH_FORMAT, HDR_FORMAT, OUTPUT_FORMAT},
+        texture::{TextureHandle, TextureManager},
+        uniforms::{
+            self, ClusterParams, Exposure, GaussianBlur, LightViewProjection, Lights,
+            OutputTargetVertex, Projection, Shadows, StaticMaterialMeshModel,
+            StaticMaterialMeshPushConstants, View, MAX_POINT_LIGHTS, MAX_PUSH_CONSTANT_SIZE,
+            MAX_SPOT_LIGHTS, OUTPUT_TARGET_VERTICES, SHADOW_CASCADES,
+        },
+    },
+    game::{camera::Projection as CameraProjection, Camera},
+    gfx::{
+        Bitmap, CascadeSettings, DrawList, DrawStep, Frustum, LightClusters, LightKind, LightList,
+        Material, MeshId, Pass, Pipeline, ShadingModel, ShadowCascade, StaticMaterialMesh,
+        StaticMaterialVertex, MAX_TEXTURE_LAYERS,
+    },
+    math::Matrix4,
+    util::{self, BoxedError},
+};
+
+const BLUR_WEIGHTS: [f32; 5] = [0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216];
+const BLUR_PASSES: usize = 10;
+/// Tiles across and up the view and slices along it.
+const LIGHT_CLUSTER_DIMENSIONS: [usize; 3] = [16, 9, 24];
+/// The diffuse, specular, emissive, normal and metallic/roughness texture bindings.
+const MATERIAL_MAPS: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct RendererOptions {
+    /// Where the compiled (`.spv`) shaders live.
+    pub shader_path: PathBuf,
+    /// The biggest width and height a texture can have.
+    pub texture_resolution: usize,
+    /// How many layers each texture array has. Textures of each size and format get arrays of
+    /// their own, which are added as they fill up.
+    pub texture_layers: usize,
+    pub vsync: bool,
+    pub exposure: f32,
+    /// Draw runs of commands sharing a mesh with one instanced draw, rather than one draw
+    /// (and one set of push constants) per command.
+    pub instancing: bool,
+    /// The cascaded shadow maps of the first directional light. At most `SHADOW_CASCADES`
+    /// cascades are used.
+    pub shadows: CascadeSettings,
+}
+
+impl Default for RendererOptions {
+    #[inline]
+    fn default() -> RendererOptions {
+        RendererOptions {
+            shader_path: PathBuf::from("res/shaders"),
+            texture_resolution: 1024,
+            texture_layers: 16,
+            vsync: false,
+            exposure: 0.8,
+            instancing: true,
+            shadows: CascadeSettings::default(),
+        }
+    }
+}
+
+/// A mesh living on the GPU.
+#[derive(Debug)]
+pub struct Mesh {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    index_count: u32,
+    bounding_radius: f32,
+}
+
+impl Mesh {
+    /// Whether the mesh drawn with `model` could be inside the frustum.
+    #[inline]
+    fn is_visible(&self, frustum: &Frustum, model: &Matrix4) -> bool {
+        let scale = (0..3)
+            .map(|row| model[row].narrowed().length())
+            .fold(0.0, f32::max);
+        frustum.sphere_inside(model[3].narrowed(), self.bounding_radius * scale)
+    }
+}
+
+/// A run of instances in the instance buffer that all use the same mesh.
+#[derive(Debug, Clone)]
+struct InstancedDraw {
+    mesh: MeshId,
+    instances: Range<u32>,
+}
+
+/// The shadow casters of one cascade: a run of `Renderer::shadow_draws`.
+#[derive(Debug, Clone)]
+struct ShadowPass {
+    view_projection: LightViewProjection,
+    draws: Range<usize>,
+}
+
+/// Draws static meshes into an HDR buffer, blooms the bright parts and tone maps the result
+/// onto a window.
+pub struct Renderer {
+    device: Device,
+    queue: Queue,
+    target: WindowTarget,
+    exposure: f32,
+
+    projection_buffer: Buffer,
+    view_buffer: Buffer,
+    lights_buffer: Buffer,
+    output_target_vertex_buffer: Buffer,
+    basic_sampler: Sampler,
+
+    static_material_pipeline: RenderPipeline,
+    static_material_instanced_pipeline: RenderPipeline,
+    static_material_primary_bind_group: BindGroup,
+    static_material_texture_bind_group_layout: BindGroupLayout,
+    static_material_texture_bind_group: BindGroup,
+    static_material_cluster_bind_group_layout: BindGroupLayout,
+    static_material_cluster_bind_group: BindGroup,
+    static_material_shadow_bind_group: BindGroup,
+
+    blur_pipeline: RenderPipeline,
+    blur_primary_bind_group_layout: BindGroupLayout,
+    blur_primary_bind_groups: [BindGroup; 3],
+
+    output_pipeline: RenderPipeline,
+    output_primary_bind_group_layout: BindGroupLayout,
+    output_primary_bind_group: BindGroup,
+
+    texture_manager: TextureManager,
+    meshes: HashMap<MeshId, Mesh>,
+    next_mesh_id: u32,
+    materials: Vec<Material>,
+
+    instancing: bool,
+    instance_buffer: Buffer,
+    instance_capacity: usize,
+    instances: Vec<StaticMaterialMeshModel>,
+    instanced_draws: Vec<InstancedDraw>,
+
+    light_clusters: LightClusters,
+    packed_clusters: Vec<[u32; 2]>,
+    cluster_params_buffer: Buffer,
+    cluster_buffer: Buffer,
+    light_index_buffer: Buffer,
+    light_index_capacity: usize,
+
+    shadow_settings: CascadeSettings,
+    shadow_target: ShadowTarget,
+    shadow_pipeline: RenderPipeline,
+    shadows_buffer: Buffer,
+    shadow_draws: Vec<InstancedDraw>,
+    shadow_passes: Vec<ShadowPass>,
+}
+
+impl Renderer {
+    /// Set up rendering to a window that is currently `size` pixels big.
+    pub fn new<W: HasRawWindowHandle>(
+        window: &W,
+        size: (u32, u32),
+        options: &RendererOptions,
+    ) -> Result<Renderer, BoxedError> {
+        let instance = Instance::new(BackendBit::PRIMARY);
+        let surface = unsafe { instance.create_surface(window) };
+
+        // TODO: convert to plain ? when try_trait it stable
+        let adapter = executor::block_on(instance.request_adapter(&RequestAdapterOptions {
+            power_preference: PowerPreference::HighPerformance,
+            compatible_surface: Some(&surface),
+        }))
+        .ok_or("Failed to request GFX adapter")?;
+
+        let features = Features::PUSH_CONSTANTS
+            | Features::SAMPLED_TEXTURE_BINDING_ARRAY
+            | Features::SAMPLED_TEXTURE_ARRAY_DYNAMIC_INDEXING
+            // Instances of a batch can each pick different textures
+            | Features::SAMPLED_TEXTURE_ARRAY_NON_UNIFORM_INDEXING
+            | Features::TEXTURE_COMPRESSION_BC;
+        let limits = Limits {
+            max_push_constant_size: MAX_PUSH_CONSTANT_SIZE as u32,
+            // Every material map binds every texture, plus the shadow maps
+            max_sampled_textures_per_shader_stage: (MATERIAL_MAPS * MAX_TEXTURE_LAYERS + 1) as u32,
+            ..Limits::default()
+        };
+        check_adapter(&adapter, features, &limits)?;
+
+        let (device, queue) = executor::block_on(adapter.request_device(
+            &DeviceDescriptor {
+                label: None,
+                features,
+                limits,
+            },
+            None,
+        ))?;
+
+        let present_mode = if options.vsync {
+            PresentMode::Fifo
+        } else {
+            PresentMode::Immediate
+        };
+        let target = WindowTarget::new(&device, surface, present_mode, size);
+
+        let projection_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: Projection::default().as_bytes(),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+        });
+
+        let view_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: View::default().as_bytes(),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+        });
+
+        let lights_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: Lights::default().as_bytes(),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+        });
+
+        let output_target_vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&OUTPUT_TARGET_VERTICES),
+            usage: BufferUsage::VERTEX,
+        });
+
+        let basic_sampler = device.create_sampler(&SamplerDescriptor {
+            label: None,
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            lod_min_clamp: 0.0,
+            // Let minified textures use their whole mip chain
+            lod_max_clamp: f32::MAX,
+            border_color: None,
+            compare: None,
+            anisotropy_clamp: None,
+        });
+
+        let shader_path = &options.shader_path;
+        let static_material_vs =
+            load_shader(&device, shader_path.join("static_material.vert.glsl.spv"))?;
+        let static_material_instanced_vs = load_shader(
+            &device,
+            shader_path.join("static_material_instanced.vert.glsl.spv"),
+        )?;
+        let static_material_fs =
+            load_shader(&device, shader_path.join("static_material.frag.glsl.spv"))?;
+        let shadow_vs = load_shader(&device, shader_path.join("shadow.vert.glsl.spv"))?;
+
+        let static_material_primary_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // projection
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStage::VERTEX,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: NonZeroU64::new(mem::size_of::<Projection>() as u64),
+                        },
+                        count: None,
+                    },
+                    // view
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStage::VERTEX | ShaderStage::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: NonZeroU64::new(mem::size_of::<View>() as u64),
+                        },
+                        count: None,
+                    },
+                    // sampler0
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::Sampler {
+                            comparison: false,
+                            filtering: false,
+                        },
+                        count: None,
+                    },
+                    // lights
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: NonZeroU64::new(mem::size_of::<Lights>() as u64),
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let static_material_texture_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // diffuse_map
+                    texture_array_layout_entry(0),
+                    // specular_map
+                    texture_array_layout_entry(1),
+                    // emissive_map
+                    texture_array_layout_entry(2),
+                    // normal_map
+                    texture_array_layout_entry(3),
+                    // metallic_roughness_map
+                    texture_array_layout_entry(4),
+                ],
+            });
+
+        let static_material_cluster_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // cluster params
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: NonZeroU64::new(
+                                mem::size_of::<ClusterParams>() as u64
+                            ),
+                        },
+                        count: None,
+                    },
+                    // clusters
+                    storage_layout_entry(1),
+                    // light indices
+                    storage_layout_entry(2),
+                ],
+            });
+
+        let static_material_shadow_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // shadows
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: NonZeroU64::new(mem::size_of::<Shadows>() as u64),
+                        },
+                        count: None,
+                    },
+                    // shadow_maps
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: TextureViewDimension::D2Array,
+                            sample_type: TextureSampleType::Depth,
+                        },
+                        count: None,
+                    },
+                    // shadow_sampler
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStage::FRAGMENT,
+                        ty: BindingType::Sampler {
+                            comparison: true,
+                            filtering: true,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let static_material_primary_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &static_material_primary_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer {
+                        buffer: &projection_buffer,
+                        offset: 0,
+                        size: None,
+                    },
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Buffer {
+                        buffer: &view_buffer,
+                        offset: 0,
+                        size: None,
+                    },
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(&basic_sampler),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer {
+                        buffer: &lights_buffer,
+                        offset: 0,
+                        size: None,
+                    },
+                },
+            ],
+        });
+
+        let static_material_pipeline_layout =
+            device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &static_material_primary_bind_group_layout,
+                    &static_material_texture_bind_group_layout,
+                    &static_material_cluster_bind_group_layout,
+                    &static_material_shadow_bind_group_layout,
+                ],
+                push_constant_ranges: &[PushConstantRange {
+                    stages: ShaderStage::VERTEX,
+                    range: 0..mem::size_of::<StaticMaterialMeshPushConstants>() as u32,
+                }],
+            });
+
+        let static_material_instanced_pipeline_layout =
+            device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &static_material_primary_bind_group_layout,
+                    &static_material_texture_bind_group_layout,
+                    &static_material_cluster_bind_group_layout,
+                    &static_material_shadow_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let static_material_pipeline = create_static_material_pipeline(
+            &device,
+            &static_material_pipeline_layout,
+            &static_material_vs,
+            &static_material_fs,
+            &[STATIC_MATERIAL_VERTEX_BUFFER],
+        );
+
+        let static_material_instanced_pipeline = create_static_material_pipeline(
+            &device,
+            &static_material_instanced_pipeline_layout,
+            &static_material_instanced_vs,
+            &static_material_fs,
+            &[
+                STATIC_MATERIAL_VERTEX_BUFFER,
+                STATIC_MATERIAL_INSTANCE_BUFFER,
+            ],
+        );
+
+        let shadow_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[],
+            push_constant_ranges: &[PushConstantRange {
+                stages: ShaderStage::VERTEX,
+                range: 0..mem::size_of::<LightViewProjection>() as u32,
+            }],
+        });
+
+        let shadow_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&shadow_pipeline_layout),
+            vertex: VertexState {
+                module: &shadow_vs,
+                entry_point: "main",
+                buffers: &[
+                    STATIC_MATERIAL_VERTEX_BUFFER,
+                    STATIC_MATERIAL_INSTANCE_BUFFER,
+                ],
+            },
+            primitive: primitive_state(),
+            depth_stencil: Some(DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Less,
+                stencil: StencilState {
+                    front: StencilFaceState::IGNORE,
+                    back: StencilFaceState::IGNORE,
+                    read_mask: 0,
+                    write_mask: 0,
+                },
+                // Push the casters back a little so surfaces don't shadow themselves
+                bias: DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+                clamp_depth: false,
+            }),
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: None,
+        });
+
+        let blur_vs = load_shader(&device, shader_path.join("blur.vert.glsl.spv"))?;
+        let blur_fs = load_shader(&device, shader_path.join("blur.frag.glsl.spv"))?;
+
+        let blur_primary_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // sampler0
+                    sampler_layout_entry(0),
+                    // image
+                    texture_layout_entry(1),
+                ],
+            });
+
+        let blur_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&blur_primary_bind_group_layout],
+            push_constant_ranges: &[PushConstantRange {
+                stages: ShaderStage::FRAGMENT,
+                range: 0..mem::size_of::<GaussianBlur>() as u32,
+            }],
+        });
+
+        let blur_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&blur_pipeline_layout),
+            vertex: output_target_vertex_state(&blur_vs),
+            primitive: primitive_state(),
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(FragmentState {
+                module: &blur_fs,
+                entry_point: "main",
+                targets: &[create_color_state(HDR_FORMAT)],
+            }),
+        });
+
+        let hdr_vs = load_shader(&device, shader_path.join("hdr.vert.glsl.spv"))?;
+        let hdr_fs = load_shader(&device, shader_path.join("hdr.frag.glsl.spv"))?;
+
+        let output_primary_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // sampler0
+                    sampler_layout_entry(0),
+                    // hdr_buffer
+                    texture_layout_entry(1),
+                    // blur_buffer
+                    texture_layout_entry(2),
+                ],
+            });
+
+        let output_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&output_primary_bind_group_layout],
+            push_constant_ranges: &[PushConstantRange {
+                stages: ShaderStage::FRAGMENT,
+                range: 0..mem::size_of::<Exposure>() as u32,
+            }],
+        });
+
+        let output_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&output_pipeline_layout),
+            vertex: output_target_vertex_state(&hdr_vs),
+            primitive: primitive_state(),
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(FragmentState {
+                module: &hdr_fs,
+                entry_point: "main",
+                targets: &[create_color_state(OUTPUT_FORMAT)],
+            }),
+        });
+
+        let texture_manager = TextureManager::new(
+            &device,
+            &queue,
+            options.texture_resolution,
+            options.texture_layers,
+        );
+        let static_material_texture_bind_group = create_texture_bind_group(
+            &device,
+            &static_material_texture_bind_group_layout,
+            &texture_manager,
+        );
+
+        let blur_primary_bind_groups = create_blur_primary_bind_groups(
+            &device,
+            &blur_primary_bind_group_layout,
+            &basic_sampler,
+            &target,
+        );
+        let output_primary_bind_group = create_output_primary_bind_group(
+            &device,
+            &output_primary_bind_group_layout,
+            &basic_sampler,
+            &target,
+        );
+
+        let instance_buffer = create_instance_buffer(&device, 0);
+
+        let light_clusters =
+            LightClusters::new(LIGHT_CLUSTER_DIMENSIONS, MAX_POINT_LIGHTS, MAX_SPOT_LIGHTS);
+        let cluster_params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: ClusterParams::unclustered().as_bytes(),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+        });
+        let cluster_buffer = create_storage_buffer(
+            &device,
+            light_clusters.clusters().len() * mem::size_of::<[u32; 2]>(),
+        );
+        let light_index_buffer = create_storage_buffer(&device, 0);
+        let static_material_cluster_bind_group = create_cluster_bind_group(
+            &device,
+            &static_material_cluster_bind_group_layout,
+            &cluster_params_buffer,
+            &cluster_buffer,
+            &light_index_buffer,
+        );
+
+        let shadow_settings = CascadeSettings {
+            cascades: options.shadows.cascades.min(SHADOW_CASCADES),
+            ..options.shadows
+        };
+        let shadow_target =
+            ShadowTarget::new(&device, shadow_settings.resolution, SHADOW_CASCADES as u32);
+        let shadows_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: Shadows::default().as_bytes(),
+            usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
+        });
+        let shadow_sampler = device.create_sampler(&SamplerDescriptor {
+            label: None,
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 1.0,
+            border_color: None,
+            compare: Some(CompareFunction::LessEqual),
+            anisotropy_clamp: None,
+        });
+        let static_material_shadow_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &static_material_shadow_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer {
+                        buffer: &shadows_buffer,
+                        offset: 0,
+                        size: None,
+                    },
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&shadow_target.array),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(&shadow_sampler),
+                },
+            ],
+        });
+
+        Ok(Renderer {
+            device,
+            queue,
+            target,
+            exposure: options.exposure,
+            projection_buffer,
+            view_buffer,
+            lights_buffer,
+            output_target_vertex_buffer,
+            basic_sampler,
+            static_material_pipeline,
+            static_material_instanced_pipeline,
+            static_material_primary_bind_group,
+            static_material_texture_bind_group_layout,
+            static_material_texture_bind_group,
+            static_material_cluster_bind_group_layout,
+            static_material_cluster_bind_group,
+            static_material_shadow_bind_group,
+            blur_pipeline,
+            blur_primary_bind_group_layout,
+            blur_primary_bind_groups,
+            output_pipeline,
+            output_primary_bind_group_layout,
+            output_primary_bind_group,
+            texture_manager,
+            meshes: HashMap::new(),
+            next_mesh_id: 0,
+            materials: Vec::new(),
+            instancing: options.instancing,
+            instance_buffer,
+            instance_capacity: 0,
+            instances: Vec::new(),
+            instanced_draws: Vec::new(),
+            light_clusters,
+            packed_clusters: Vec::new(),
+            cluster_params_buffer,
+            cluster_buffer,
+            light_index_buffer,
+            light_index_capacity: 0,
+            shadow_settings,
+            shadow_target,
+            shadow_pipeline,
+            shadows_buffer,
+            shadow_draws: Vec::new(),
+            shadow_passes: Vec::new(),
+        })
+    }
+
+    #[inline]
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    #[inline]
+    pub fn queue(&self) -> &Queue {
+        &self.queue
+    }
+
+    #[inline]
+    pub fn size(&self) -> (u32, u32) {
+        self.target.size()
+    }
+
+    #[inline]
+    pub fn aspect_ratio(&self) -> f32 {
+        let (width, height) = self.size();
+        width as f32 / height as f32
+    }
+
+    #[inline]
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+
+    /// Call whenever the window changes size.
+    pub fn resize(&mut self, size: (u32, u32)) {
+        self.target.synchronize_size(&self.device, size);
+        // Re-bind the new buffers since the size changed!
+        self.blur_primary_bind_groups = create_blur_primary_bind_groups(
+            &self.device,
+            &self.blur_primary_bind_group_layout,
+            &self.basic_sampler,
+            &self.target,
+        );
+        self.output_primary_bind_group = create_output_primary_bind_group(
+            &self.device,
+            &self.output_primary_bind_group_layout,
+            &self.basic_sampler,
+            &self.target,
+        );
+    }
+
+    /// Upload a mesh, returning the id to draw it with. Ids are never reused.
+    pub fn upload_mesh(&mut self, mesh: &StaticMaterialMesh) -> MeshId {
+        let vertex_buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(mesh.vertices()),
+            usage: BufferUsage::VERTEX,
+        });
+        let index_buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(mesh.indices()),
+            usage: BufferUsage::INDEX,
+        });
+        let id = MeshId(self.next_mesh_id);
+        self.next_mesh_id += 1;
+        self.meshes.insert(
+            id,
+            Mesh {
+                vertex_buffer,
+                index_buffer,
+                index_count: mesh.indices().len() as u32,
+                bounding_radius: mesh.bounding_radius(),
+            },
+        );
+        id
+    }
+
+    /// Free a mesh's buffers. Draw commands still using it are skipped.
+    #[inline]
+    pub fn remove_mesh(&mut self, id: MeshId) {
+        self.meshes.remove(&id);
+    }
+
+    /// Add a material, returning the id for draw commands to use it with. Every texture it
+    /// uses has to be loaded.
+    pub fn add_material(&mut self, material: Material) -> Result<u32, BoxedError> {
+        let metallic_roughness = match material.shading {
+            ShadingModel::BlinnPhong => None,
+            ShadingModel::MetallicRoughness => Some(material.metallic_roughness),
+        };
+        if let Some(index) = material
+            .layers()
+            .iter()
+            .copied()
+            .chain(metallic_roughness)
+            .find(|&index| !self.texture_manager.is_loaded(index))
+        {
+            return util::boxed_err(format!(
+                "Material uses texture {} but no texture is loaded there",
+                index
+            ));
+        }
+        self.materials.push(material);
+        Ok(self.materials.len() as u32 - 1)
+    }
+
+    /// Upload a bitmap and its mip levels as a texture.
+    #[inline]
+    pub fn upload_texture(&mut self, bitmap: &Bitmap) -> Result<TextureHandle, BoxedError> {
+        self.texture_manager.load(&self.device, &self.queue, bitmap)
+    }
+
+    /// The index materials use a texture with, or `None` if it's been unloaded.
+    #[inline]
+    pub fn texture_index(&self, handle: TextureHandle) -> Option<u32> {
+        self.texture_manager.index(handle)
+    }
+
+    /// Free a texture. Its index may be reused by the next upload, and until then materials
+    /// still using it sample black.
+    #[inline]
+    pub fn unload_texture(&mut self, handle: TextureHandle) {
+        self.texture_manager.unload(handle);
+    }
+
+    /// Draw a frame as seen by the camera, lit by `lights`, and present it.
+    ///
+    /// The draws are sorted for the camera first. Commands outside of the camera's frustum are
+    /// skipped, as are commands using meshes or materials that don't exist. Opaque commands cast
+    /// shadows from the first directional light.
+    pub fn render(
+        &mut self,
+        camera: &Camera,
+        draws: &mut DrawList,
+        lights: &LightList,
+    ) -> Result<(), BoxedError> {
+        if self.texture_manager.take_changed() {
+            self.static_material_texture_bind_group = create_texture_bind_group(
+                &self.device,
+                &self.static_material_texture_bind_group_layout,
+                &self.texture_manager,
+            );
+        }
+
+        draws.sort(camera.position(), camera.direction());
+        let cascades = self.fit_shadow_cascades(camera, lights);
+        self.prepare_instances(camera, draws, &cascades);
+
+        self.queue.write_buffer(
+            &self.projection_buffer,
+            0,
+            Projection::new(camera).as_bytes(),
+        );
+        self.queue
+            .write_buffer(&self.view_buffer, 0, View::new(camera).as_bytes());
+        self.queue
+            .write_buffer(&self.lights_buffer, 0, Lights::new(lights).as_bytes());
+        self.cluster_lights(camera, lights);
+        self.queue
+            .write_buffer(&self.shadows_buffer, 0, Shadows::new(&cascades).as_bytes());
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        self.draw_shadows(&mut encoder);
+        if self.instancing {
+            self.draw_scene_instanced(&mut encoder);
+        } else {
+            self.draw_scene(&mut encoder, camera, draws);
+        }
+        self.draw_bloom(&mut encoder);
+
+        // The render buffers will automatically be swapped when this texture drops
+        let current_frame = self.target.swap_chain.get_current_frame()?;
+        self.draw_output(&mut encoder, &current_frame.output.view);
+        self.queue.submit(Some(encoder.finish()));
+        Ok(())
+    }
+
+    // Pass 1: Draw the scene to the HDR buffer and also output the brightest parts to the
+    // bloom buffer.
+    fn draw_scene(&self, encoder: &mut CommandEncoder, camera: &Camera, draws: &DrawList) {
+        let frustum = camera.frustum();
+        let mut render_pass = self.begin_scene_pass(encoder);
+
+        let mut material = None;
+        let mut mesh = None;
+        for step in draws.steps() {
+            match step {
+                // Transparent draws share the blending static material pipeline for now
+                DrawStep::Pass(_) => {}
+                DrawStep::Pipeline(Pipeline::StaticMaterial) => {
+                    render_pass.set_pipeline(&self.static_material_pipeline);
+                    render_pass.set_bind_group(0, &self.static_material_primary_bind_group, &[]);
+                    render_pass.set_bind_group(1, &self.static_material_texture_bind_group, &[]);
+                    render_pass.set_bind_group(2, &self.static_material_cluster_bind_group, &[]);
+                    render_pass.set_bind_group(3, &self.static_material_shadow_bind_group, &[]);
+                }
+                DrawStep::Material(id) => material = self.materials.get(id as usize),
+                DrawStep::Mesh(id) => {
+                    mesh = self.meshes.get(&id);
+                    if let Some(mesh) = mesh {
+                        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                        render_pass
+                            .set_index_buffer(mesh.index_buffer.slice(..), IndexFormat::Uint32);
+                    }
+                }
+                DrawStep::Draw(command) => {
+                    let (mesh, material) = match (mesh, material) {
+                        (Some(mesh), Some(material)) => (mesh, material),
+                        _ => continue,
+                    };
+                    if !mesh.is_visible(&frustum, &command.model) {
+                        continue;
+                    }
+                    let model = StaticMaterialMeshPushConstants::new(command.model, material);
+                    render_pass.set_push_constants(ShaderStage::VERTEX, 0, model.as_bytes());
+                    render_pass.draw_indexed(0..mesh.index_count, 0, 0..1);
+                }
+            }
+        }
+    }
+
+    // Work out which lights touch which parts of the view and upload them.
+    fn cluster_lights(&mut self, camera: &Camera, lights: &LightList) {
+        let params = match camera.projection() {
+            CameraProjection::Perspective(projection) => {
+                let position = camera.position();
+                self.light_clusters.assign(
+                    projection,
+                    position,
+                    position + camera.direction(),
+                    camera.up(),
+                    lights,
+                );
+                ClusterParams::new(&self.light_clusters, projection)
+            }
+            CameraProjection::Orthographic(_) => {
+                self.light_clusters.assign_everywhere(lights);
+                ClusterParams::unclustered()
+            }
+        };
+        self.queue
+            .write_buffer(&self.cluster_params_buffer, 0, params.as_bytes());
+        uniforms::pack_clusters(&self.light_clusters, &mut self.packed_clusters);
+        self.queue.write_buffer(
+            &self.cluster_buffer,
+            0,
+            bytemuck::cast_slice(&self.packed_clusters),
+        );
+
+        let indices = self.light_clusters.indices();
+        if indices.len() > self.light_index_capacity {
+            self.light_index_capacity = indices.len().next_power_of_two();
+            self.light_index_buffer = create_storage_buffer(
+                &self.device,
+                self.light_index_capacity * mem::size_of::<u32>(),
+            );
+            // Re-bind since the buffer changed
+            self.static_material_cluster_bind_group = create_cluster_bind_group(
+                &self.device,
+                &self.static_material_cluster_bind_group_layout,
+                &self.cluster_params_buffer,
+                &self.cluster_buffer,
+                &self.light_index_buffer,
+            );
+        }
+        if !indices.is_empty() {
+            self.queue
+                .write_buffer(&self.light_index_buffer, 0, bytemuck::cast_slice(indices));
+        }
+    }
+
+    // Fit the shadow cascades around the view for the first directional light. Only perspective
+    // views are shadowed for now.
+    fn fit_shadow_cascades(&self, camera: &Camera, lights: &LightList) -> Vec<ShadowCascade> {
+        let sun = lights
+            .lights()
+            .iter()
+            .find(|placed| placed.light.kind == LightKind::Directional);
+        match (camera.projection(), sun) {
+            (CameraProjection::Perspective(projection), Some(sun))
+                if self.shadow_settings.cascades > 0 =>
+            {
+                let position = camera.position();
+                self.shadow_settings.fit(
+                    projection,
+                    position,
+                    position + camera.direction(),
+                    camera.up(),
+                    sun.direction,
+                )
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // Cull the draws and write what's left into the instance buffer: one run per batch for the
+    // scene when instancing, then the shadow casters of each cascade (which are always instanced).
+    fn prepare_instances(&mut self, camera: &Camera, draws: &DrawList, cascades: &[ShadowCascade]) {
+        self.instances.clear();
+        self.instanced_draws.clear();
+        self.shadow_draws.clear();
+        self.shadow_passes.clear();
+        if self.instancing {
+            cull_instances(
+                &self.meshes,
+                &self.materials,
+                draws,
+                &camera.frustum(),
+                false,
+                &mut self.instances,
+                &mut self.instanced_draws,
+            );
+        }
+        for cascade in cascades {
+            let start = self.shadow_draws.len();
+            cull_instances(
+                &self.meshes,
+                &self.materials,
+                draws,
+                &cascade.frustum(),
+                true,
+                &mut self.instances,
+                &mut self.shadow_draws,
+            );
+            self.shadow_passes.push(ShadowPass {
+                view_projection: LightViewProjection::new(cascade),
+                draws: start..self.shadow_draws.len(),
+            });
+        }
+
+        if self.instances.len() > self.instance_capacity {
+            self.instance_capacity = self.instances.len().next_power_of_two();
+            self.instance_buffer = create_instance_buffer(&self.device, self.instance_capacity);
+        }
+        self.queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&self.instances),
+        );
+    }
+
+    // Pass 0: Draw the depth of the shadow casters into each cascade's shadow map.
+    fn draw_shadows(&self, encoder: &mut CommandEncoder) {
+        for (shadow_pass, cascade) in self.shadow_passes.iter().zip(&self.shadow_target.cascades) {
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: None,
+                color_attachments: &[],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: cascade,
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_pipeline(&self.shadow_pipeline);
+            render_pass.set_push_constants(
+                ShaderStage::VERTEX,
+                0,
+                shadow_pass.view_projection.as_bytes(),
+            );
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            for draw in &self.shadow_draws[shadow_pass.draws.clone()] {
+                let mesh = &self.meshes[&draw.mesh];
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.index_count, 0, draw.instances.clone());
+            }
+        }
+    }
+
+    // Pass 1 (instanced): Same as `draw_scene`, but with everything already culled and sitting in
+    // the instance buffer.
+    fn draw_scene_instanced(&self, encoder: &mut CommandEncoder) {
+        let mut render_pass = self.begin_scene_pass(encoder);
+        render_pass.set_pipeline(&self.static_material_instanced_pipeline);
+        render_pass.set_bind_group(0, &self.static_material_primary_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.static_material_texture_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.static_material_cluster_bind_group, &[]);
+        render_pass.set_bind_group(3, &self.static_material_shadow_bind_group, &[]);
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        for draw in &self.instanced_draws {
+            let mesh = &self.meshes[&draw.mesh];
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), IndexFormat::Uint32);
+            render_pass.draw_indexed(0..mesh.index_count, 0, draw.instances.clone());
+        }
+    }
+
+    fn begin_scene_pass<'a>(&'a self, encoder: &'a mut CommandEncoder) -> RenderPass<'a> {
+        encoder.begin_render_pass(&RenderPassDescriptor {
+            label: None,
+            color_attachments: &[
+                clear_color_attachment(&self.target.hdr_buffer),
+                clear_color_attachment(&self.target.bloom_buffer),
+            ],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.target.depth_buffer,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+
+    // Pass 2-N: Gaussian blur the bloom buffer
+    // Bounces "back and forth" blurring the bloom buffer inside the ping-pong buffers
+    fn draw_bloom(&self, encoder: &mut CommandEncoder) {
+        for i in 0..BLUR_PASSES {
+            // The first pass reads the bloom buffer, then the ping-pong buffers take turns
+            let (dst_index, src_index, horizontal) = match i {
+                0 => (0, 0, 0),
+                _ => (i % 2, ((i - 1) % 2) + 1, (i + 1) % 2),
+            };
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: None,
+                color_attachments: &[clear_color_attachment(
+                    &self.target.ping_pong_buffers[dst_index],
+                )],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.blur_pipeline);
+            render_pass.set_bind_group(0, &self.blur_primary_bind_groups[src_index], &[]);
+            render_pass.set_push_constants(
+                ShaderStage::FRAGMENT,
+                0,
+                GaussianBlur {
+                    horizontal: horizontal as u32,
+                    weights: BLUR_WEIGHTS,
+                }
+                .as_bytes(),
+            );
+            render_pass.set_vertex_buffer(0, self.output_target_vertex_buffer.slice(..));
+            render_pass.draw(0..OUTPUT_TARGET_VERTICES.len() as u32, 0..1);
+        }
+    }
+
+    // Final Pass: Merge the HDR and blur buffer
+    fn draw_output(&self, encoder: &mut CommandEncoder, output: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: None,
+            color_attachments: &[clear_color_attachment(output)],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(&self.output_pipeline);
+        render_pass.set_bind_group(0, &self.output_primary_bind_group, &[]);
+        render_pass.set_push_constants(
+            ShaderStage::FRAGMENT,
+            0,
+            Exposure(self.exposure).as_bytes(),
+        );
+        render_pass.set_vertex_buffer(0, self.output_target_vertex_buffer.slice(..));
+        render_pass.draw(0..OUTPUT_TARGET_VERTICES.len() as u32, 0..1);
+    }
+}
+
+/// Add the draws inside the frustum to `instances`, with a run in `instanced_draws` per batch.
+fn cull_instances(
+    meshes: &HashMap<MeshId, Mesh>,
+    materials: &[Material],
+    draws: &DrawList,
+    frustum: &Frustum,
+    opaque_only: bool,
+    instances: &mut Vec<StaticMaterialMeshModel>,
+    instanced_draws: &mut Vec<InstancedDraw>,
+) {
+    for batch in draws.batches() {
+        if opaque_only && batch.pass != Pass::Opaque {
+            continue;
+        }
+        let mesh = match meshes.get(&batch.mesh) {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        let start = instances.len() as u32;
+        instances.extend(batch.commands.iter().filter_map(|command| {
+            let material = materials.get(command.material as usize)?;
+            if mesh.is_visible(frustum, &command.model) {
+                Some(StaticMaterialMeshModel::new(command.model, material))
+            } else {
+                None
+            }
+        }));
+        let end = instances.len() as u32;
+        if start != end {
+            instanced_draws.push(InstancedDraw {
+                mesh: batch.mesh,
+                instances: start..end,
+            });
+        }
+    }
+}
+
+/// Make sure the adapter can give the renderer everything it asks for, so a GPU that can't
+/// fails with what it is missing rather than a generic device error.
+fn check_adapter(adapter: &Adapter, features: Features, limits: &Limits) -> Result<(), BoxedError> {
+    let missing = features - adapter.features();
+    if !missing.is_empty() {
+        return util::boxed_err(format!("The GPU doesn't support {:?}", missing));
+    }
+    let supported = adapter.limits();
+    if supported.max_sampled_textures_per_shader_stage
+        < limits.max_sampled_textures_per_shader_stage
+    {
+        return util::boxed_err(format!(
+            "Materials sample up to {} textures per shader stage ({} per map), but the GPU \
+             only supports {}",
+            limits.max_sampled_textures_per_shader_stage,
+            MAX_TEXTURE_LAYERS,
+            supported.max_sampled_textures_per_shader_stage
+        ));
+    }
+    if supported.max_push_constant_size < limits.max_push_constant_size {
+        return util::boxed_err(format!(
+            "Draws push up to {} bytes of constants, but the GPU only supports {}",
+            limits.max_push_constant_size, supported.max_push_constant_size
+        ));
+    }
+    Ok(())
+}
+
+fn load_shader<P: AsRef<Path>>(device: &Device, path: P) -> Result<ShaderModule, BoxedError> {
+    let mut buffer = Vec::new();
+    util::buf_open(path)?.read_to_end(&mut buffer)?;
+    Ok(device.create_shader_module(&ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::util::make_spirv(&buffer),
+        flags: ShaderFlags::empty(),
+    }))
+}
+
+#[inline]
+fn clear_color_attachment(attachment: &TextureView) -> RenderPassColorAttachmentDescriptor {
+    RenderPassColorAttachmentDescriptor {
+        attachment,
+        resolve_target: None,
+        ops: Operations {
+            load: LoadOp::Clear(Color::BLACK),
+            store: true,
+        },
+    }
+}
+
+#[inline]
+fn sampler_layout_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStage::FRAGMENT,
+        ty: BindingType::Sampler {
+            comparison: false,
+            filtering: false,
+        },
+        count: None,
+    }
+}
+
+#[inline]
+fn texture_layout_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStage::FRAGMENT,
+        ty: BindingType::Texture {
+            multisampled: false,
+            view_dimension: TextureViewDimension::D2,
+            sample_type: TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    }
+}
+
+/// A texture for every texture index.
+#[inline]
+fn texture_array_layout_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        count: NonZeroU32::new(MAX_TEXTURE_LAYERS as u32),
+        ..texture_layout_entry(binding)
+    }
+}
+
+#[inline]
+fn storage_layout_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStage::FRAGMENT,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+#[inline]
+fn primitive_state() -> PrimitiveState {
+    PrimitiveState {
+        topology: PrimitiveTopology::TriangleList,
+        strip_index_format: None,
+        front_face: FrontFace::Ccw,
+        cull_mode: CullMode::Back,
+        polygon_mode: PolygonMode::Fill,
+    }
+}
+
+const STATIC_MATERIAL_VERTEX_BUFFER: VertexBufferLayout<'static> = VertexBufferLayout {
+    array_stride: mem::size_of::<StaticMaterialVertex>() as u64,
+    step_mode: InputStepMode::Vertex,
+    attributes: &wgpu::vertex_attr_array![
+        0 => Float3, 1 => Float3, 2 => Float2, 3 => Float4, 4 => Float4
+    ],
+};
+
+/// A `StaticMaterialMeshModel` per instance. The matrices take up a location per row.
+const STATIC_MATERIAL_INSTANCE_BUFFER: VertexBufferLayout<'static> = VertexBufferLayout {
+    array_stride: mem::size_of::<StaticMaterialMeshModel>() as u64,
+    step_mode: InputStepMode::Instance,
+    attributes: &wgpu::vertex_attr_array![
+        5 => Float4, 6 => Float4, 7 => Float4, 8 => Float4,
+        9 => Float3, 10 => Float3, 11 => Float3,
+        12 => Uint, 13 => Uint
+    ],
+};
+
+const OUTPUT_TARGET_VERTEX_BUFFER: VertexBufferLayout<'static> = VertexBufferLayout {
+    array_stride: mem::size_of::<OutputTargetVertex>() as u64,
+    step_mode: InputStepMode::Vertex,
+    attributes: &wgpu::vertex_attr_array![0 => Float3, 1 => Float2],
+};
+
+#[inline]
+fn output_target_vertex_state(module: &ShaderModule) -> VertexState {
+    VertexState {
+        module,
+        entry_point: "main",
+        buffers: &[OUTPUT_TARGET_VERTEX_BUFFER],
+    }
+}
+
+fn create_color_state(format: TextureFormat) -> ColorTargetState {
+    ColorTargetState {
+        format,
+        color_blend: BlendState {
+            src_factor: BlendFactor::SrcAlpha,
+            dst_factor: BlendFactor::OneMinusSrcAlpha,
+            operation: BlendOperation::Add,
+        },
+        alpha_blend: BlendState {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::OneMinusSrcAlpha,
+            operation: BlendOperation::Add,
+        },
+        write_mask: ColorWrite::ALL,
+    }
+}
+
+fn create_static_material_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    vs: &ShaderModule,
+    fs: &ShaderModule,
+    buffers: &[VertexBufferLayout],
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex: VertexState {
+            module: vs,
+            entry_point: "main",
+            buffers,
+        },
+        primitive: primitive_state(),
+        depth_stencil: Some(DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::Less,
+            stencil: StencilState {
+                front: StencilFaceState::IGNORE,
+                back: StencilFaceState::IGNORE,
+                read_mask: 0,
+                write_mask: 0,
+            },
+            bias: DepthBiasState {
+                constant: 0,
+                slope_scale: 0.0,
+                clamp: 0.0,
+            },
+            clamp_depth: false,
+        }),
+        multisample: MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        fragment: Some(FragmentState {
+            module: fs,
+            entry_point: "main",
+            targets: &[
+                create_color_state(HDR_FORMAT),
+                create_color_state(HDR_FORMAT),
+            ],
+        }),
+    })
+}
+
+#[inline]
+fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: None,
+        // Zero sized buffers aren't allowed
+        size: (capacity.max(1) * mem::size_of::<StaticMaterialMeshModel>()) as u64,
+        usage: BufferUsage::VERTEX | BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+#[inline]
+fn create_storage_buffer(device: &Device, size: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: None,
+        // Zero sized buffers aren't allowed
+        size: size.max(4) as u64,
+        usage: BufferUsage::STORAGE | BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_cluster_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    params: &Buffer,
+    clusters: &Buffer,
+    light_indices: &Buffer,
+) -> BindGroup {
+    let entry = |binding, buffer| BindGroupEntry {
+        binding,
+        resource: BindingResource::Buffer {
+            buffer,
+            offset: 0,
+            size: None,
+        },
+    };
+    device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[
+            entry(0, params),
+            entry(1, clusters),
+            entry(2, light_indices),
+        ],
+    })
+}
+
+/// Every material map binds every texture, so materials can use any texture for any map.
+fn create_texture_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    texture_manager: &TextureManager,
+) -> BindGroup {
+    let views = texture_manager.views();
+    let entries: Vec<_> = (0..MATERIAL_MAPS as u32)
+        .map(|binding| BindGroupEntry {
+            binding,
+            resource: BindingResource::TextureViewArray(&views),
+        })
+        .collect();
+    device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &entries,
+    })
+}
+
+fn create_output_primary_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    sampler: &Sampler,
+    target: &WindowTarget,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Sampler(sampler),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&target.hdr_buffer),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(&target.ping_pong_buffers[1]),
+            },
+        ],
+    })
+}
+
+fn create_blur_primary_bind_groups(
+    device: &Device,
+    layout: &BindGroupLayout,
+    sampler: &Sampler,
+    target: &WindowTarget,
+) -> [BindGroup; 3] {
+    let create = |image: &TextureView| {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Sampler(sampler),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(image),
+                },
+            ],
+        })
+    };
+    [
+        create(&target.bloom_buffer),
+        create(&target.ping_pong_buffers[0]),
+        create(&target.ping_pong_buffers[1]),
+    ]
+}
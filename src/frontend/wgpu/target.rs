@@ -0,0 +1,172 @@
+use std::num::NonZeroU32;
+
+use wgpu::{
+    Device, Extent3d, PresentMode, Surface, SwapChain, SwapChainDescriptor, TextureAspect,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureUsage, TextureView,
+    TextureViewDescriptor, TextureViewDimension,
+};
+
+pub const OUTPUT_FORMAT: TextureFormat = TextureFormat::Bgra8Unorm;
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
+/// The swap chain of a window along with all the off-screen buffers that match its size.
+pub struct WindowTarget {
+    surface: Surface,
+    present_mode: PresentMode,
+    size: (u32, u32),
+    pub swap_chain: SwapChain,
+    pub hdr_buffer: TextureView,
+    pub bloom_buffer: TextureView,
+    pub ping_pong_buffers: [TextureView; 2],
+    pub depth_buffer: TextureView,
+}
+
+impl WindowTarget {
+    pub fn new(
+        device: &Device,
+        surface: Surface,
+        present_mode: PresentMode,
+        size: (u32, u32),
+    ) -> WindowTarget {
+        let swap_chain = WindowTarget::create_swap_chain(device, &surface, present_mode, size);
+        WindowTarget {
+            surface,
+            present_mode,
+            size,
+            swap_chain,
+            hdr_buffer: WindowTarget::create_hdr_frame_buffer(device, size, 1),
+            bloom_buffer: WindowTarget::create_hdr_frame_buffer(device, size, 1),
+            ping_pong_buffers: [
+                WindowTarget::create_hdr_frame_buffer(device, size, 1),
+                WindowTarget::create_hdr_frame_buffer(device, size, 1),
+            ],
+            depth_buffer: WindowTarget::create_depth_buffer(device, size),
+        }
+    }
+
+    #[inline]
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn synchronize_size(&mut self, device: &Device, size: (u32, u32)) {
+        self.size = size;
+        self.swap_chain =
+            WindowTarget::create_swap_chain(device, &self.surface, self.present_mode, size);
+        self.hdr_buffer = WindowTarget::create_hdr_frame_buffer(device, size, 1);
+        self.bloom_buffer = WindowTarget::create_hdr_frame_buffer(device, size, 1);
+        self.ping_pong_buffers = [
+            WindowTarget::create_hdr_frame_buffer(device, size, 1),
+            WindowTarget::create_hdr_frame_buffer(device, size, 1),
+        ];
+        self.depth_buffer = WindowTarget::create_depth_buffer(device, size);
+    }
+
+    fn create_swap_chain(
+        device: &Device,
+        surface: &Surface,
+        present_mode: PresentMode,
+        size: (u32, u32),
+    ) -> SwapChain {
+        device.create_swap_chain(
+            surface,
+            &SwapChainDescriptor {
+                usage: TextureUsage::RENDER_ATTACHMENT,
+                format: OUTPUT_FORMAT,
+                width: size.0,
+                height: size.1,
+                present_mode,
+            },
+        )
+    }
+
+    fn create_hdr_frame_buffer(
+        device: &Device,
+        size: (u32, u32),
+        sample_count: u32,
+    ) -> TextureView {
+        device
+            .create_texture(&TextureDescriptor {
+                label: None,
+                size: Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: TextureUsage::RENDER_ATTACHMENT | TextureUsage::SAMPLED,
+            })
+            .create_view(&TextureViewDescriptor::default())
+    }
+
+    fn create_depth_buffer(device: &Device, size: (u32, u32)) -> TextureView {
+        device
+            .create_texture(&TextureDescriptor {
+                label: None,
+                size: Extent3d {
+                    width: size.0,
+                    height: size.1,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: DEPTH_FORMAT,
+                usage: TextureUsage::RENDER_ATTACHMENT,
+            })
+            .create_view(&TextureViewDescriptor {
+                aspect: TextureAspect::DepthOnly,
+                ..TextureViewDescriptor::default()
+            })
+    }
+}
+
+/// A layer of depth for each shadow cascade.
+pub struct ShadowTarget {
+    /// For rendering each cascade into.
+    pub cascades: Vec<TextureView>,
+    /// For sampling every cascade at once.
+    pub array: TextureView,
+}
+
+impl ShadowTarget {
+    pub fn new(device: &Device, resolution: u32, cascades: u32) -> ShadowTarget {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: resolution,
+                height: resolution,
+                depth: cascades,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: TextureUsage::RENDER_ATTACHMENT | TextureUsage::SAMPLED,
+        });
+        let cascade_views = (0..cascades)
+            .map(|layer| {
+                texture.create_view(&TextureViewDescriptor {
+                    dimension: Some(TextureViewDimension::D2),
+                    aspect: TextureAspect::DepthOnly,
+                    base_array_layer: layer,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..TextureViewDescriptor::default()
+                })
+            })
+            .collect();
+        let array = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            aspect: TextureAspect::DepthOnly,
+            ..TextureViewDescriptor::default()
+        });
+        ShadowTarget {
+            cascades: cascade_views,
+            array,
+        }
+    }
+}
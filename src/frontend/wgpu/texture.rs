@@ -0,0 +1,574 @@
+use std::num::NonZeroU32;
+
+use wgpu::{
+    Device, Extent3d, Origin3d, Queue, Texture, TextureCopyView, TextureDataLayout,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureUsage, TextureView,
+    TextureViewDescriptor, TextureViewDimension,
+};
+
+use crate::{
+    collections::{pool::Handle, Pool},
+    gfx::{block_count, Bitmap, BitmapFormat, MAX_TEXTURE_LAYERS},
+    util::{self, BoxedError},
+};
+
+/// What goes in a texture array: every layer has the same format, size and mip levels.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct TextureClass {
+    pub format: BitmapFormat,
+    pub width: usize,
+    pub height: usize,
+    pub mip_levels: usize,
+}
+
+impl TextureClass {
+    /// The class of a bitmap no wider or higher than `max_resolution`, after checking each of
+    /// its mip levels is the size and length it should be.
+    pub fn of(bitmap: &Bitmap, max_resolution: usize) -> Result<TextureClass, BoxedError> {
+        let format = bitmap.format();
+        let (width, height) = match bitmap.mip_levels().next() {
+            Some(level) => (level.size().x() as usize, level.size().y() as usize),
+            None => return util::boxed_err("The bitmap has no mip levels"),
+        };
+        if bitmap.layer_count() > 1 {
+            return util::boxed_err(format!(
+                "Only single layer bitmaps can be textures, not {} layers",
+                bitmap.layer_count()
+            ));
+        }
+        if width == 0 || height == 0 {
+            return util::boxed_err(format!("A {}x{} bitmap is empty", width, height));
+        }
+        if width > max_resolution || height > max_resolution {
+            return util::boxed_err(format!(
+                "A {}x{} bitmap doesn't fit in {}x{} textures",
+                width, height, max_resolution, max_resolution
+            ));
+        }
+        if format.is_compressed() && (width % 4 != 0 || height % 4 != 0) {
+            return util::boxed_err(format!(
+                "{:?} bitmaps have to be a multiple of 4 pixels wide and high, not {}x{}",
+                format, width, height
+            ));
+        }
+
+        let mip_levels = bitmap.mip_levels().count();
+        let max_mip_levels = mip_chain_length(width, height);
+        if mip_levels > max_mip_levels {
+            return util::boxed_err(format!(
+                "A {}x{} bitmap can have at most {} mip levels, not {}",
+                width, height, max_mip_levels, mip_levels
+            ));
+        }
+        for (i, level) in bitmap.mip_levels().enumerate() {
+            let expected = ((width >> i).max(1), (height >> i).max(1));
+            let size = (level.size().x() as usize, level.size().y() as usize);
+            if size != expected {
+                return util::boxed_err(format!(
+                    "Mip level {} is {}x{} but should be {}x{}",
+                    i, size.0, size.1, expected.0, expected.1
+                ));
+            }
+            let bytes_per_row = format.bytes_per_row(size.0);
+            if level.bytes_per_row() != bytes_per_row {
+                return util::boxed_err(format!(
+                    "Mip level {} has rows of {} bytes but {:?} rows {} pixels wide take {}",
+                    i,
+                    level.bytes_per_row(),
+                    format,
+                    size.0,
+                    bytes_per_row
+                ));
+            }
+            let level_size = format.level_size(size.0, size.1);
+            if level.data().len() != level_size {
+                return util::boxed_err(format!(
+                    "Mip level {} has {} bytes but a {}x{} {:?} level takes {}",
+                    i,
+                    level.data().len(),
+                    size.0,
+                    size.1,
+                    format,
+                    level_size
+                ));
+            }
+        }
+
+        Ok(TextureClass {
+            format,
+            width,
+            height,
+            mip_levels,
+        })
+    }
+}
+
+/// How many levels a full mip chain down to 1x1 has.
+#[inline]
+pub fn mip_chain_length(width: usize, height: usize) -> usize {
+    let size = width.max(height).max(1);
+    (usize::BITS - size.leading_zeros()) as usize
+}
+
+#[derive(Debug)]
+struct ArrayLayers {
+    /// `None` once every layer has been freed, so the array can take another class.
+    class: Option<TextureClass>,
+    free_layers: Vec<u32>,
+    next_layer: u32,
+}
+
+impl ArrayLayers {
+    #[inline]
+    fn used(&self) -> usize {
+        self.next_layer as usize - self.free_layers.len()
+    }
+}
+
+/// Hands out the layers of texture arrays. Textures of the same class share arrays, and arrays
+/// are added as the ones of a class fill up.
+#[derive(Debug)]
+pub struct LayerAllocator {
+    layers_per_array: u32,
+    arrays: Vec<ArrayLayers>,
+}
+
+impl LayerAllocator {
+    #[inline]
+    pub fn new(layers_per_array: usize) -> LayerAllocator {
+        LayerAllocator {
+            layers_per_array: layers_per_array.max(1) as u32,
+            arrays: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn layers_per_array(&self) -> usize {
+        self.layers_per_array as usize
+    }
+
+    /// Take a free layer for a texture of `class`, returning its array and layer. Freed
+    /// layers are used before new ones, and empty arrays before adding another.
+    pub fn allocate(&mut self, class: TextureClass) -> (usize, u32) {
+        let layers_per_array = self.layers_per_array;
+        let array = self
+            .arrays
+            .iter()
+            .position(|array| {
+                array.class == Some(class)
+                    && (!array.free_layers.is_empty() || array.next_layer < layers_per_array)
+            })
+            .or_else(|| self.arrays.iter().position(|array| array.class.is_none()))
+            .unwrap_or_else(|| {
+                self.arrays.push(ArrayLayers {
+                    class: None,
+                    free_layers: Vec::new(),
+                    next_layer: 0,
+                });
+                self.arrays.len() - 1
+            });
+
+        let layers = &mut self.arrays[array];
+        layers.class = Some(class);
+        let layer = layers.free_layers.pop().unwrap_or_else(|| {
+            layers.next_layer += 1;
+            layers.next_layer - 1
+        });
+        (array, layer)
+    }
+
+    /// Give a layer back, returning whether that emptied its array.
+    pub fn free(&mut self, array: usize, layer: u32) -> bool {
+        let layers = &mut self.arrays[array];
+        debug_assert!(layer < layers.next_layer && !layers.free_layers.contains(&layer));
+        layers.free_layers.push(layer);
+        if layers.used() == 0 {
+            layers.class = None;
+            layers.free_layers.clear();
+            layers.next_layer = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A texture living in one layer of one of the `TextureManager`'s arrays.
+#[derive(Debug)]
+pub struct TextureLayer {
+    array: usize,
+    layer: u32,
+    class: TextureClass,
+    view: TextureView,
+}
+
+impl TextureLayer {
+    #[inline]
+    pub fn class(&self) -> TextureClass {
+        self.class
+    }
+}
+
+pub type TextureHandle = Handle<TextureLayer>;
+
+/// Texture arrays for every class of texture that has been loaded.
+///
+/// Each texture is bound on its own, at the index of its handle, so materials can pick any
+/// texture for any of their maps. Indices of unloaded textures are reused.
+#[derive(Debug)]
+pub struct TextureManager {
+    max_resolution: usize,
+    allocator: LayerAllocator,
+    arrays: Vec<Option<Texture>>,
+    textures: Pool<TextureLayer>,
+    /// Bound at the indices with no texture.
+    placeholder: (Texture, TextureView),
+    changed: bool,
+}
+
+impl TextureManager {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        max_resolution: usize,
+        layers_per_array: usize,
+    ) -> TextureManager {
+        let placeholder_class = TextureClass {
+            format: BitmapFormat::BgraU8,
+            width: 1,
+            height: 1,
+            mip_levels: 1,
+        };
+        let placeholder = TextureManager::create_texture(device, placeholder_class, 1);
+        TextureManager::write_texture(
+            queue,
+            &placeholder,
+            0,
+            &Bitmap::new(BitmapFormat::BgraU8, 1, 1, vec![0, 0, 0, 255]),
+        );
+        let placeholder_view = placeholder.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2),
+            ..TextureViewDescriptor::default()
+        });
+
+        TextureManager {
+            max_resolution,
+            allocator: LayerAllocator::new(layers_per_array),
+            arrays: Vec::new(),
+            textures: Pool::default(),
+            placeholder: (placeholder, placeholder_view),
+            changed: false,
+        }
+    }
+
+    /// Copy a bitmap and its mip levels into a free layer of an array of its class.
+    pub fn load(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        bitmap: &Bitmap,
+    ) -> Result<TextureHandle, BoxedError> {
+        let class = TextureClass::of(bitmap, self.max_resolution)?;
+        // Freed indices are reused first, so staying under the limit keeps every index under it
+        if self.textures.len() >= MAX_TEXTURE_LAYERS {
+            return util::boxed_err(format!("All {} textures are in use", MAX_TEXTURE_LAYERS));
+        }
+
+        let (array, layer) = self.allocator.allocate(class);
+        if array >= self.arrays.len() {
+            self.arrays.resize_with(array + 1, || None);
+        }
+        let layers_per_array = self.allocator.layers_per_array();
+        let texture = self.arrays[array]
+            .get_or_insert_with(|| TextureManager::create_texture(device, class, layers_per_array));
+        TextureManager::write_texture(queue, texture, layer, bitmap);
+        let view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: NonZeroU32::new(1),
+            ..TextureViewDescriptor::default()
+        });
+
+        self.changed = true;
+        Ok(self.textures.register(TextureLayer {
+            array,
+            layer,
+            class,
+            view,
+        }))
+    }
+
+    /// Free a texture's layer, and its array once that's empty. Stale handles are ignored.
+    pub fn unload(&mut self, handle: TextureHandle) {
+        if let Some(texture) = self.textures.try_remove(handle) {
+            if self.allocator.free(texture.array, texture.layer) {
+                self.arrays[texture.array] = None;
+            }
+            self.changed = true;
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, handle: TextureHandle) -> Option<&TextureLayer> {
+        self.textures.try_get(handle)
+    }
+
+    /// The index materials pick a texture with.
+    #[inline]
+    pub fn index(&self, handle: TextureHandle) -> Option<u32> {
+        self.get(handle).map(|_| handle.index() as u32)
+    }
+
+    /// Whether a texture is loaded at `index`.
+    #[inline]
+    pub fn is_loaded(&self, index: u32) -> bool {
+        self.textures
+            .iter_with_handles()
+            .any(|(handle, _)| handle.index() == index as usize)
+    }
+
+    /// The texture at each index, or the placeholder where there is none.
+    pub fn views(&self) -> Vec<&TextureView> {
+        let mut views = vec![&self.placeholder.1; MAX_TEXTURE_LAYERS];
+        for (handle, texture) in self.textures.iter_with_handles() {
+            views[handle.index()] = &texture.view;
+        }
+        views
+    }
+
+    /// Whether textures were loaded or unloaded since the last call, so the views need
+    /// binding again.
+    #[inline]
+    pub fn take_changed(&mut self) -> bool {
+        let changed = self.changed;
+        self.changed = false;
+        changed
+    }
+
+    fn create_texture(device: &Device, class: TextureClass, layers: usize) -> Texture {
+        device.create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: class.width as u32,
+                height: class.height as u32,
+                depth: layers as u32,
+            },
+            mip_level_count: class.mip_levels as u32,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: texture_format_from_bitmap_format(class.format),
+            usage: TextureUsage::SAMPLED | TextureUsage::COPY_DST,
+        })
+    }
+
+    fn write_texture(queue: &Queue, texture: &Texture, index: u32, bitmap: &Bitmap) {
+        let format = bitmap.format();
+        for (i, mip_level) in bitmap.mip_levels().enumerate() {
+            let size = mip_level.size();
+            let (mut width, mut height) = (size.x() as u32, size.y() as u32);
+            // Compressed levels are copied in whole blocks, even when they're smaller
+            if format.is_compressed() {
+                width = block_count(width as usize) as u32 * 4;
+                height = block_count(height as usize) as u32 * 4;
+            }
+            queue.write_texture(
+                TextureCopyView {
+                    texture,
+                    mip_level: i as u32,
+                    origin: Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: index,
+                    },
+                },
+                mip_level.data(),
+                TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: mip_level.bytes_per_row() as u32,
+                    rows_per_image: height,
+                },
+                Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+            );
+        }
+    }
+}
+
+#[inline]
+pub fn texture_format_from_bitmap_format(format: BitmapFormat) -> TextureFormat {
+    match format {
+        BitmapFormat::BgraU8 => TextureFormat::Bgra8Unorm,
+        BitmapFormat::BgraU8Srgb => TextureFormat::Bgra8UnormSrgb,
+        BitmapFormat::GrayU8 => TextureFormat::R8Unorm,
+        BitmapFormat::Dxt1 => TextureFormat::Bc1RgbaUnorm,
+        BitmapFormat::Dxt1Srgb => TextureFormat::Bc1RgbaUnormSrgb,
+        BitmapFormat::Dxt3 => TextureFormat::Bc2RgbaUnorm,
+        BitmapFormat::Dxt3Srgb => TextureFormat::Bc2RgbaUnormSrgb,
+        BitmapFormat::Dxt5 => TextureFormat::Bc3RgbaUnorm,
+        BitmapFormat::Dxt5Srgb => TextureFormat::Bc3RgbaUnormSrgb,
+        BitmapFormat::Bc4 => TextureFormat::Bc4RUnorm,
+        BitmapFormat::Bc5 => TextureFormat::Bc5RgUnorm,
+        BitmapFormat::Bc6h => TextureFormat::Bc6hRgbUfloat,
+        BitmapFormat::Bc6hSigned => TextureFormat::Bc6hRgbSfloat,
+        BitmapFormat::Bc7 => TextureFormat::Bc7RgbaUnorm,
+        BitmapFormat::Bc7Srgb => TextureFormat::Bc7RgbaUnormSrgb,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gfx::BitmapReader;
+
+    const GRAY: TextureClass = TextureClass {
+        format: BitmapFormat::GrayU8,
+        width: 64,
+        height: 64,
+        mip_levels: 7,
+    };
+    const DXT1: TextureClass = TextureClass {
+        format: BitmapFormat::Dxt1,
+        width: 64,
+        height: 64,
+        mip_levels: 7,
+    };
+
+    fn gray_bitmap(width: usize, height: usize, mip_levels: usize) -> Bitmap {
+        let mut bitmap = Bitmap::new(BitmapFormat::GrayU8, width, height, vec![0; width * height]);
+        for i in 1..mip_levels {
+            let (width, height) = ((width >> i).max(1), (height >> i).max(1));
+            bitmap.push_mip_level(width, height, &vec![0; width * height]);
+        }
+        bitmap
+    }
+
+    fn error(result: Result<TextureClass, BoxedError>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn classifies_dds_bitmaps() {
+        let mut reader = BitmapReader::default();
+        for (file, format) in &[
+            ("diffuse", BitmapFormat::Dxt5),
+            ("normal", BitmapFormat::Dxt1),
+            ("specular", BitmapFormat::GrayU8),
+            ("emissive", BitmapFormat::GrayU8),
+        ] {
+            let mut bitmap = Bitmap::default();
+            reader
+                .read_into(
+                    &mut util::buf_open(format!("res/bitmaps/frigate/{}.dds", file)).unwrap(),
+                    &mut bitmap,
+                )
+                .unwrap();
+            let class = TextureClass::of(&bitmap, 1024).unwrap();
+            assert_eq!(
+                TextureClass {
+                    format: *format,
+                    width: 1024,
+                    height: 1024,
+                    mip_levels: 11,
+                },
+                class
+            );
+            assert!(TextureClass::of(&bitmap, 512).is_err());
+        }
+    }
+
+    #[test]
+    fn classifies_odd_sizes() {
+        assert_eq!(
+            TextureClass {
+                format: BitmapFormat::GrayU8,
+                width: 12,
+                height: 5,
+                mip_levels: 4,
+            },
+            TextureClass::of(&gray_bitmap(12, 5, 4), 16).unwrap()
+        );
+        assert_eq!(1, mip_chain_length(1, 1));
+        assert_eq!(4, mip_chain_length(12, 5));
+        assert_eq!(11, mip_chain_length(1024, 1024));
+    }
+
+    #[test]
+    fn rejects_mismatched_bitmaps() {
+        assert_eq!(
+            "The bitmap has no mip levels",
+            error(TextureClass::of(&Bitmap::default(), 64))
+        );
+        assert_eq!(
+            "A 128x64 bitmap doesn't fit in 64x64 textures",
+            error(TextureClass::of(&gray_bitmap(128, 64, 1), 64))
+        );
+        assert_eq!(
+            "A 4x4 bitmap can have at most 3 mip levels, not 4",
+            error(TextureClass::of(&gray_bitmap(4, 4, 4), 64))
+        );
+
+        let compressed = Bitmap::new(BitmapFormat::Dxt1, 6, 4, vec![0; 16]);
+        assert_eq!(
+            "Dxt1 bitmaps have to be a multiple of 4 pixels wide and high, not 6x4",
+            error(TextureClass::of(&compressed, 64))
+        );
+
+        let mut wrong_size = gray_bitmap(8, 8, 1);
+        wrong_size.push_mip_level(2, 2, &[0; 4]);
+        assert_eq!(
+            "Mip level 1 is 2x2 but should be 4x4",
+            error(TextureClass::of(&wrong_size, 64))
+        );
+
+        let mut truncated = gray_bitmap(8, 8, 1);
+        truncated.push_mip_level(4, 4, &[0; 15]);
+        assert_eq!(
+            "Mip level 1 has 15 bytes but a 4x4 GrayU8 level takes 16",
+            error(TextureClass::of(&truncated, 64))
+        );
+
+        let mut array = gray_bitmap(4, 4, 1);
+        array.push_layer();
+        array.push_mip_level(4, 4, &[0; 16]);
+        assert_eq!(
+            "Only single layer bitmaps can be textures, not 2 layers",
+            error(TextureClass::of(&array, 64))
+        );
+    }
+
+    #[test]
+    fn allocates_layers_per_class() {
+        let mut allocator = LayerAllocator::new(2);
+        assert_eq!((0, 0), allocator.allocate(GRAY));
+        assert_eq!((1, 0), allocator.allocate(DXT1));
+        assert_eq!((0, 1), allocator.allocate(GRAY));
+        // The first array is full so another one is added
+        assert_eq!((2, 0), allocator.allocate(GRAY));
+        assert_eq!(3, allocator.arrays.len());
+        assert_eq!(Some(GRAY), allocator.arrays[2].class);
+
+        // Freed layers get reused
+        assert!(!allocator.free(0, 0));
+        assert_eq!(1, allocator.arrays[0].used());
+        assert_eq!((0, 0), allocator.allocate(GRAY));
+    }
+
+    #[test]
+    fn reuses_empty_arrays() {
+        let mut allocator = LayerAllocator::new(4);
+        assert_eq!((0, 0), allocator.allocate(GRAY));
+        assert_eq!((0, 1), allocator.allocate(GRAY));
+        assert!(!allocator.free(0, 1));
+        assert!(allocator.free(0, 0));
+        assert_eq!(None, allocator.arrays[0].class);
+        assert_eq!(0, allocator.arrays[0].used());
+
+        // An empty array can take any class
+        assert_eq!((0, 0), allocator.allocate(DXT1));
+        assert_eq!(Some(DXT1), allocator.arrays[0].class);
+        assert_eq!((1, 0), allocator.allocate(GRAY));
+    }
+}
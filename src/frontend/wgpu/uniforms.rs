@@ -0,0 +1,714 @@
+use crate::{
+    game::Camera,
+    gfx::{LightClusters, LightKind, LightList, Material, PerspectiveProjection, ShadowCascade},
+    math::{Matrix3, Matrix4, Vector2, Vector3},
+};
+
+/// The smallest possible push-constant buffer size (in bytes) according to WGPU docs.
+/// This is the lower limit for push-constants on Vulkan.
+pub const MAX_PUSH_CONSTANT_SIZE: usize = 128;
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Projection(Matrix4);
+
+unsafe impl bytemuck::Zeroable for Projection {}
+
+unsafe impl bytemuck::Pod for Projection {}
+
+impl Projection {
+    #[inline]
+    pub fn new(camera: &Camera) -> Projection {
+        Projection(&camera.projection_matrix() * &Matrix4::vulkan_projection_correct())
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct View {
+    view: Matrix4,
+    view_position: Vector3,
+}
+
+unsafe impl bytemuck::Zeroable for View {}
+
+unsafe impl bytemuck::Pod for View {}
+
+impl View {
+    #[inline]
+    pub fn new(camera: &Camera) -> View {
+        View {
+            view: camera.view_matrix(),
+            view_position: camera.position(),
+        }
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Exposure(pub f32);
+
+unsafe impl bytemuck::Zeroable for Exposure {}
+
+unsafe impl bytemuck::Pod for Exposure {}
+
+impl Exposure {
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct GaussianBlur {
+    pub horizontal: u32,
+    pub weights: [f32; 5],
+}
+
+unsafe impl bytemuck::Zeroable for GaussianBlur {}
+
+unsafe impl bytemuck::Pod for GaussianBlur {}
+
+impl GaussianBlur {
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+}
+
+/// A material's texture layers, packed by `Material::pack_texture_indices`.
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct TextureIndices(pub u32);
+
+unsafe impl bytemuck::Zeroable for TextureIndices {}
+
+unsafe impl bytemuck::Pod for TextureIndices {}
+
+impl TextureIndices {
+    #[inline]
+    pub fn new(material: &Material) -> TextureIndices {
+        TextureIndices(material.pack_texture_indices())
+    }
+}
+
+/// Per-draw data for a static material mesh, tightly packed for the instance vertex buffer.
+/// Push constants use the padded `StaticMaterialMeshPushConstants`.
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct StaticMaterialMeshModel {
+    pub model: Matrix4,
+    pub inverse_normal: Matrix3,
+    pub tex_indices: TextureIndices,
+    /// Packed by `Material::pack_shading`.
+    pub shading: u32,
+}
+
+unsafe impl bytemuck::Zeroable for StaticMaterialMeshModel {}
+
+unsafe impl bytemuck::Pod for StaticMaterialMeshModel {}
+
+impl StaticMaterialMeshModel {
+    #[inline]
+    pub fn new(model: Matrix4, material: &Material) -> StaticMaterialMeshModel {
+        StaticMaterialMeshModel {
+            model,
+            inverse_normal: model.inversed().transposed().narrowed(),
+            tex_indices: TextureIndices::new(material),
+            shading: material.pack_shading(),
+        }
+    }
+}
+
+/// Per-draw data for a static material mesh laid out like the std430 `Model` push constant
+/// block in static_material.vert.glsl, where each `mat3` column takes up a `vec4`.
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct StaticMaterialMeshPushConstants {
+    pub model: Matrix4,
+    pub inverse_normal: [[f32; 4]; 3],
+    pub tex_indices: TextureIndices,
+    /// Packed by `Material::pack_shading`.
+    pub shading: u32,
+}
+
+unsafe impl bytemuck::Zeroable for StaticMaterialMeshPushConstants {}
+
+unsafe impl bytemuck::Pod for StaticMaterialMeshPushConstants {}
+
+impl StaticMaterialMeshPushConstants {
+    #[inline]
+    pub fn new(model: Matrix4, material: &Material) -> StaticMaterialMeshPushConstants {
+        StaticMaterialMeshModel::new(model, material).into()
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+}
+
+impl From<StaticMaterialMeshModel> for StaticMaterialMeshPushConstants {
+    #[inline]
+    fn from(model: StaticMaterialMeshModel) -> StaticMaterialMeshPushConstants {
+        let mut inverse_normal = [[0.0; 4]; 3];
+        for (padded, column) in inverse_normal.iter_mut().zip(&model.inverse_normal.0) {
+            padded[..3].copy_from_slice(&column.0);
+        }
+        StaticMaterialMeshPushConstants {
+            model: model.model,
+            inverse_normal,
+            tex_indices: model.tex_indices,
+            shading: model.shading,
+        }
+    }
+}
+
+// The shaders get these from shader_src/lights.h, which `glsl_header` generates
+pub const MAX_DIRECTIONAL_LIGHTS: usize = 4;
+pub const MAX_POINT_LIGHTS: usize = 64;
+pub const MAX_SPOT_LIGHTS: usize = 32;
+
+// The light structs pack a float after each vec3 where possible, so the layout is the same under
+// std140 and std430.
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct DirectionalLight {
+    direction: Vector3,
+    _pad0: f32,
+    ambient: Vector3,
+    _pad1: f32,
+    diffuse: Vector3,
+    _pad2: f32,
+    specular: Vector3,
+    _pad3: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct PointLight {
+    position: Vector3,
+    constant: f32,
+    ambient: Vector3,
+    linear: f32,
+    diffuse: Vector3,
+    quadratic: f32,
+    specular: Vector3,
+    _pad0: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct SpotLight {
+    position: Vector3,
+    constant: f32,
+    direction: Vector3,
+    linear: f32,
+    ambient: Vector3,
+    quadratic: f32,
+    diffuse: Vector3,
+    /// Cosine of the inner angle.
+    cut_off: f32,
+    specular: Vector3,
+    /// Cosine of the outer angle.
+    outer_cut_off: f32,
+}
+
+/// The `Lights` uniform block.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Lights {
+    /// How many of each light are used: directional, point and spot (then padding).
+    counts: [u32; 4],
+    directional: [DirectionalLight; MAX_DIRECTIONAL_LIGHTS],
+    point: [PointLight; MAX_POINT_LIGHTS],
+    spot: [SpotLight; MAX_SPOT_LIGHTS],
+}
+
+unsafe impl bytemuck::Zeroable for Lights {}
+
+unsafe impl bytemuck::Pod for Lights {}
+
+impl Default for Lights {
+    #[inline]
+    fn default() -> Lights {
+        bytemuck::Zeroable::zeroed()
+    }
+}
+
+impl Lights {
+    /// Pack a light list. Lights past the maximum of their kind are dropped.
+    pub fn new(list: &LightList) -> Lights {
+        let mut lights = Lights::default();
+        let (mut directional, mut point, mut spot) = (0, 0, 0);
+        for placed in list.lights() {
+            let light = &placed.light;
+            match light.kind {
+                LightKind::Directional if directional < MAX_DIRECTIONAL_LIGHTS => {
+                    lights.directional[directional] = DirectionalLight {
+                        direction: placed.direction,
+                        ambient: light.ambient,
+                        diffuse: light.diffuse,
+                        specular: light.specular,
+                        ..DirectionalLight::default()
+                    };
+                    directional += 1;
+                }
+                LightKind::Point(attenuation) if point < MAX_POINT_LIGHTS => {
+                    lights.point[point] = PointLight {
+                        position: placed.position,
+                        constant: attenuation.constant,
+                        ambient: light.ambient,
+                        linear: attenuation.linear,
+                        diffuse: light.diffuse,
+                        quadratic: attenuation.quadratic,
+                        specular: light.specular,
+                        ..PointLight::default()
+                    };
+                    point += 1;
+                }
+                LightKind::Spot {
+                    attenuation,
+                    inner_angle,
+                    outer_angle,
+                } if spot < MAX_SPOT_LIGHTS => {
+                    lights.spot[spot] = SpotLight {
+                        position: placed.position,
+                        constant: attenuation.constant,
+                        direction: placed.direction,
+                        linear: attenuation.linear,
+                        ambient: light.ambient,
+                        quadratic: attenuation.quadratic,
+                        diffuse: light.diffuse,
+                        cut_off: inner_angle.cos(),
+                        specular: light.specular,
+                        outer_cut_off: outer_angle.cos(),
+                    };
+                    spot += 1;
+                }
+                _ => {}
+            }
+        }
+        lights.counts = [directional as u32, point as u32, spot as u32, 0];
+        lights
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+}
+
+/// How the view is cut into clusters, so the shader can find the cluster a fragment is in.
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct ClusterParams {
+    max_slope: Vector2,
+    near: f32,
+    far: f32,
+    dimensions: [u32; 4],
+}
+
+unsafe impl bytemuck::Zeroable for ClusterParams {}
+
+unsafe impl bytemuck::Pod for ClusterParams {}
+
+impl ClusterParams {
+    #[inline]
+    pub fn new(clusters: &LightClusters, projection: &PerspectiveProjection) -> ClusterParams {
+        let [x, y, z] = clusters.dimensions();
+        let tan_y = (projection.fov / 2.0).tan();
+        ClusterParams {
+            max_slope: Vector2::new(tan_y * projection.aspect_ratio, tan_y),
+            near: projection.near,
+            far: projection.far,
+            dimensions: [x as u32, y as u32, z as u32, 0],
+        }
+    }
+
+    /// Puts every fragment in the first cluster. For use with `LightClusters::assign_everywhere`.
+    #[inline]
+    pub fn unclustered() -> ClusterParams {
+        ClusterParams {
+            max_slope: Vector2::new(1.0, 1.0),
+            near: 1.0,
+            far: 2.0,
+            dimensions: [1, 1, 1, 0],
+        }
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+}
+
+/// The bits a packed cluster's point light count takes. The spot light count is above it.
+const CLUSTER_COUNT_BITS: u32 = 16;
+const CLUSTER_COUNT_MASK: u32 = (1 << CLUSTER_COUNT_BITS) - 1;
+
+/// Pack each cluster into a `uvec2` of the offset of its light indices and its point (low
+/// `CLUSTER_COUNT_BITS`) and spot (the bits above) light counts.
+pub fn pack_clusters(clusters: &LightClusters, packed: &mut Vec<[u32; 2]>) {
+    packed.clear();
+    packed.extend(clusters.clusters().iter().map(|cluster| {
+        let counts = cluster.point_lights.min(CLUSTER_COUNT_MASK)
+            | cluster.spot_lights.min(CLUSTER_COUNT_MASK) << CLUSTER_COUNT_BITS;
+        [cluster.offset, counts]
+    }));
+}
+
+pub const SHADOW_CASCADES: usize = 4;
+
+/// The GLSL the static material fragment shader includes as shader_src/lights.h, so it shares
+/// the light array sizes and the cluster packing and indexing above. Only the test that keeps
+/// the file up to date calls it.
+#[cfg(test)]
+fn glsl_header() -> String {
+    format!(
+        "\
+// Generated by uniforms::glsl_header in src/frontend/wgpu/uniforms.rs. Don't edit it, run the
+// uniforms tests with DTH_WRITE_SHADER_HEADERS=1 to update it instead.
+const uint MAX_DIRECTIONAL_LIGHTS = {directional};
+const uint MAX_POINT_LIGHTS = {point};
+const uint MAX_SPOT_LIGHTS = {spot};
+const uint SHADOW_CASCADES = {cascades};
+
+// The point and spot light counts of a cluster packed by pack_clusters
+uvec2 unpack_cluster_counts(uint packed) {{
+    return uvec2(packed & {mask}u, packed >> {bits});
+}}
+
+{cluster_index}",
+        directional = MAX_DIRECTIONAL_LIGHTS,
+        point = MAX_POINT_LIGHTS,
+        spot = MAX_SPOT_LIGHTS,
+        cascades = SHADOW_CASCADES,
+        mask = CLUSTER_COUNT_MASK,
+        bits = CLUSTER_COUNT_BITS,
+        cluster_index = LightClusters::GLSL_CLUSTER_INDEX,
+    )
+}
+
+/// A shadow cascade's view-projection, ready for the GPU. Sent as push constants when drawing
+/// the cascade's shadow map.
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct LightViewProjection(Matrix4);
+
+unsafe impl bytemuck::Zeroable for LightViewProjection {}
+
+unsafe impl bytemuck::Pod for LightViewProjection {}
+
+impl LightViewProjection {
+    #[inline]
+    pub fn new(cascade: &ShadowCascade) -> LightViewProjection {
+        LightViewProjection(
+            &cascade.view_projection_matrix() * &Matrix4::vulkan_projection_correct(),
+        )
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+}
+
+/// The `Shadows` uniform block, for the first directional light.
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Shadows {
+    view_projections: [LightViewProjection; SHADOW_CASCADES],
+    /// How far along the view each cascade reaches.
+    splits: [f32; SHADOW_CASCADES],
+    /// How many cascades are used. Zero turns shadows off.
+    cascades: u32,
+    _pad0: [u32; 3],
+}
+
+unsafe impl bytemuck::Zeroable for Shadows {}
+
+unsafe impl bytemuck::Pod for Shadows {}
+
+impl Shadows {
+    /// Cascades past `SHADOW_CASCADES` are dropped.
+    pub fn new(cascades: &[ShadowCascade]) -> Shadows {
+        let mut shadows = Shadows::default();
+        let cascades = &cascades[..cascades.len().min(SHADOW_CASCADES)];
+        for (i, cascade) in cascades.iter().enumerate() {
+            shadows.view_projections[i] = LightViewProjection::new(cascade);
+            shadows.splits[i] = cascade.split;
+        }
+        shadows.cascades = cascades.len() as u32;
+        shadows
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(self)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct OutputTargetVertex {
+    position: Vector3,
+    tex_coord: Vector2,
+}
+
+unsafe impl bytemuck::Zeroable for OutputTargetVertex {}
+
+unsafe impl bytemuck::Pod for OutputTargetVertex {}
+
+pub const OUTPUT_TARGET_VERTICES: [OutputTargetVertex; 6] = [
+    OutputTargetVertex {
+        position: Vector3::new(-1.0, -1.0, 0.0),
+        tex_coord: Vector2::new(0.0, 1.0),
+    },
+    OutputTargetVertex {
+        position: Vector3::new(1.0, -1.0, 0.0),
+        tex_coord: Vector2::new(1.0, 1.0),
+    },
+    OutputTargetVertex {
+        position: Vector3::new(-1.0, 1.0, 0.0),
+        tex_coord: Vector2::new(0.0, 0.0),
+    },
+    OutputTargetVertex {
+        position: Vector3::new(1.0, -1.0, 0.0),
+        tex_coord: Vector2::new(1.0, 1.0),
+    },
+    OutputTargetVertex {
+        position: Vector3::new(1.0, 1.0, 0.0),
+        tex_coord: Vector2::new(1.0, 0.0),
+    },
+    OutputTargetVertex {
+        position: Vector3::new(-1.0, 1.0, 0.0),
+        tex_coord: Vector2::new(0.0, 0.0),
+    },
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gfx::{Attenuation, CascadeSettings, Light};
+    use std::{f32::consts::FRAC_PI_2, mem};
+
+    fn offset_of<T, F>(base: &T, field: &F) -> usize {
+        field as *const F as usize - base as *const T as usize
+    }
+
+    const PUSH_CONSTANT_ALIGNMENT: usize = wgpu::PUSH_CONSTANT_ALIGNMENT as usize;
+
+    #[test]
+    fn shader_header_is_up_to_date() {
+        let header = glsl_header();
+        if std::env::var_os("DTH_WRITE_SHADER_HEADERS").is_some() {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/shader_src/lights.h");
+            std::fs::write(path, &header).unwrap();
+            return;
+        }
+        assert_eq!(
+            header,
+            include_str!("../../../shader_src/lights.h"),
+            "shader_src/lights.h is out of date, run the uniforms tests with \
+             DTH_WRITE_SHADER_HEADERS=1 to update it"
+        );
+    }
+
+    #[test]
+    fn fits_in_push_constants() {
+        assert!(mem::size_of::<StaticMaterialMeshPushConstants>() <= MAX_PUSH_CONSTANT_SIZE);
+        assert_eq!(PUSH_CONSTANT_ALIGNMENT, mem::align_of::<Projection>());
+        assert_eq!(PUSH_CONSTANT_ALIGNMENT, mem::align_of::<View>());
+        assert_eq!(PUSH_CONSTANT_ALIGNMENT, mem::align_of::<Exposure>());
+        assert_eq!(PUSH_CONSTANT_ALIGNMENT, mem::align_of::<GaussianBlur>());
+        assert!(mem::size_of::<LightViewProjection>() <= MAX_PUSH_CONSTANT_SIZE);
+        assert_eq!(
+            PUSH_CONSTANT_ALIGNMENT,
+            mem::align_of::<LightViewProjection>()
+        );
+        assert_eq!(
+            PUSH_CONSTANT_ALIGNMENT,
+            mem::align_of::<StaticMaterialMeshPushConstants>()
+        );
+    }
+
+    #[test]
+    fn push_constants_match_std430_layout() {
+        let mut model = Matrix4::translate(Vector3::new(1.0, 2.0, 3.0));
+        model.0[0].0[0] = 2.0;
+        let constants = StaticMaterialMeshPushConstants::new(model, &Material::default());
+        // A mat4, then a mat3 with each column padded to a vec4
+        assert_eq!(0, offset_of(&constants, &constants.model));
+        assert_eq!(64, offset_of(&constants, &constants.inverse_normal));
+        assert_eq!(112, offset_of(&constants, &constants.tex_indices));
+        assert_eq!(116, offset_of(&constants, &constants.shading));
+        assert_eq!(120, mem::size_of::<StaticMaterialMeshPushConstants>());
+
+        let packed = StaticMaterialMeshModel::new(model, &Material::default());
+        for (padded, column) in constants
+            .inverse_normal
+            .iter()
+            .zip(&packed.inverse_normal.0)
+        {
+            assert_eq!(&column.0[..], &padded[..3]);
+            assert_eq!(0.0, padded[3]);
+        }
+        assert_eq!(0.5, constants.inverse_normal[0][0]);
+    }
+
+    #[test]
+    fn instance_attributes_are_tightly_packed() {
+        // 4 rows of model, 3 rows of inverse normal, the packed texture indices and shading
+        let size = 4 * mem::size_of::<[f32; 4]>() + 3 * mem::size_of::<[f32; 3]>() + 4 + 4;
+        assert_eq!(size, mem::size_of::<StaticMaterialMeshModel>());
+    }
+
+    #[test]
+    fn light_structs_match_glsl_layout() {
+        let light = DirectionalLight::default();
+        assert_eq!(0, offset_of(&light, &light.direction));
+        assert_eq!(16, offset_of(&light, &light.ambient));
+        assert_eq!(32, offset_of(&light, &light.diffuse));
+        assert_eq!(48, offset_of(&light, &light.specular));
+        assert_eq!(64, mem::size_of::<DirectionalLight>());
+
+        let light = PointLight::default();
+        assert_eq!(0, offset_of(&light, &light.position));
+        assert_eq!(12, offset_of(&light, &light.constant));
+        assert_eq!(16, offset_of(&light, &light.ambient));
+        assert_eq!(28, offset_of(&light, &light.linear));
+        assert_eq!(32, offset_of(&light, &light.diffuse));
+        assert_eq!(44, offset_of(&light, &light.quadratic));
+        assert_eq!(48, offset_of(&light, &light.specular));
+        assert_eq!(64, mem::size_of::<PointLight>());
+
+        let light = SpotLight::default();
+        assert_eq!(0, offset_of(&light, &light.position));
+        assert_eq!(12, offset_of(&light, &light.constant));
+        assert_eq!(16, offset_of(&light, &light.direction));
+        assert_eq!(28, offset_of(&light, &light.linear));
+        assert_eq!(32, offset_of(&light, &light.ambient));
+        assert_eq!(44, offset_of(&light, &light.quadratic));
+        assert_eq!(48, offset_of(&light, &light.diffuse));
+        assert_eq!(60, offset_of(&light, &light.cut_off));
+        assert_eq!(64, offset_of(&light, &light.specular));
+        assert_eq!(76, offset_of(&light, &light.outer_cut_off));
+        assert_eq!(80, mem::size_of::<SpotLight>());
+
+        // Arrays of structs are 16 byte aligned in std140
+        let lights = Lights::default();
+        assert_eq!(0, offset_of(&lights, &lights.counts));
+        assert_eq!(16, offset_of(&lights, &lights.directional));
+        assert_eq!(16 + 64 * 4, offset_of(&lights, &lights.point));
+        assert_eq!(16 + 64 * 4 + 64 * 64, offset_of(&lights, &lights.spot));
+        assert_eq!(16 + 64 * 4 + 64 * 64 + 80 * 32, mem::size_of::<Lights>());
+        // The smallest max uniform buffer size WGPU allows
+        assert!(mem::size_of::<Lights>() <= 16384);
+    }
+
+    #[test]
+    fn packs_lights_by_kind() {
+        let mut list = LightList::default();
+        let spot = LightKind::Spot {
+            attenuation: Attenuation::default(),
+            inner_angle: 0.0,
+            outer_angle: FRAC_PI_2,
+        };
+        list.push(
+            Light::new(spot),
+            &Matrix4::translate(Vector3::new(1.0, 2.0, 3.0)),
+        );
+        for i in 0..MAX_POINT_LIGHTS + 2 {
+            let position = Vector3::new(i as f32, 0.0, 0.0);
+            let light = Light::new(LightKind::Point(Attenuation::default()));
+            list.push(light, &Matrix4::translate(position));
+        }
+
+        let lights = Lights::new(&list);
+        // The extra point lights are dropped
+        assert_eq!([0, MAX_POINT_LIGHTS as u32, 1, 0], lights.counts);
+        assert_eq!(5.0, lights.point[5].position.x());
+        assert_eq!(1.0, lights.point[5].constant);
+        assert_eq!([1.0, 2.0, 3.0], lights.spot[0].position.0);
+        assert_eq!([0.0, 0.0, -1.0], lights.spot[0].direction.0);
+        assert_eq!(1.0, lights.spot[0].cut_off);
+        assert!(lights.spot[0].outer_cut_off.abs() < 1e-6);
+    }
+
+    #[test]
+    fn packs_clusters() {
+        let mut list = LightList::default();
+        for _ in 0..3 {
+            list.push(
+                Light::new(LightKind::Point(Attenuation::default())),
+                &Matrix4::identity(),
+            );
+        }
+        list.push(
+            Light::new(LightKind::Spot {
+                attenuation: Attenuation::default(),
+                inner_angle: 0.0,
+                outer_angle: FRAC_PI_2,
+            }),
+            &Matrix4::identity(),
+        );
+        let mut clusters = LightClusters::new([2, 1, 1], MAX_POINT_LIGHTS, MAX_SPOT_LIGHTS);
+        clusters.assign_everywhere(&list);
+
+        let mut packed = Vec::new();
+        pack_clusters(&clusters, &mut packed);
+        assert_eq!(vec![[0, 3 | 1 << 16], [0, 3 | 1 << 16]], packed);
+
+        let params = ClusterParams::unclustered();
+        assert_eq!(32, params.as_bytes().len());
+        assert_eq!([1, 1, 1, 0], params.dimensions);
+    }
+
+    #[test]
+    fn packs_shadow_cascades() {
+        let projection = PerspectiveProjection {
+            fov: 1.0,
+            aspect_ratio: 1.0,
+            near: 0.1,
+            far: 1000.0,
+        };
+        let settings = CascadeSettings {
+            cascades: SHADOW_CASCADES + 1,
+            ..CascadeSettings::default()
+        };
+        let cascades = settings.fit(
+            &projection,
+            Vector3::splat(0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::up(),
+            Vector3::new(0.0, -1.0, -1.0),
+        );
+
+        let shadows = Shadows::new(&cascades);
+        // The extra cascade is dropped
+        assert_eq!(SHADOW_CASCADES as u32, shadows.cascades);
+        assert_eq!(cascades[2].split, shadows.splits[2]);
+        // The splits are a vec4 rather than a float array, which would be padded out in std140
+        assert_eq!(64 * SHADOW_CASCADES, offset_of(&shadows, &shadows.splits));
+        assert_eq!(
+            64 * SHADOW_CASCADES + 16,
+            offset_of(&shadows, &shadows.cascades)
+        );
+        assert_eq!(64 * SHADOW_CASCADES + 32, mem::size_of::<Shadows>());
+
+        assert_eq!(0, Shadows::new(&[]).cascades);
+    }
+}
@@ -1,11 +1,335 @@
-use crate::gfx::{OrthographicProjection, PerspectiveProjection, Transform};
+use crate::{
+    gfx::{Frustum, OrthographicProjection, PerspectiveProjection, Transform},
+    math::{Matrix4, Quaternion, Ray, Vector2, Vector3, Vector4},
+};
 
+#[derive(Debug, Copy, Clone)]
 pub enum Projection {
     Perspective(PerspectiveProjection),
     Orthographic(OrthographicProjection),
 }
 
+impl From<&Projection> for Matrix4 {
+    #[inline]
+    fn from(projection: &Projection) -> Matrix4 {
+        match projection {
+            Projection::Perspective(p) => p.into(),
+            Projection::Orthographic(p) => p.into(),
+        }
+    }
+}
+
+/// A camera looks down the backward axis of its rotation (i.e. -z when unrotated) with up
+/// being up. Scale is ignored.
+///
+/// All matrices use OpenGL clip space conventions. Screen coordinates are in pixels with
+/// the origin at the top left.
+#[derive(Debug)]
 pub struct Camera {
     transform: Transform,
     projection: Projection,
 }
+
+impl Camera {
+    #[inline]
+    pub fn new(transform: Transform, projection: Projection) -> Camera {
+        Camera {
+            transform,
+            projection,
+        }
+    }
+
+    #[inline]
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    #[inline]
+    pub fn transform_mut(&mut self) -> &mut Transform {
+        &mut self.transform
+    }
+
+    #[inline]
+    pub fn projection(&self) -> &Projection {
+        &self.projection
+    }
+
+    #[inline]
+    pub fn projection_mut(&mut self) -> &mut Projection {
+        &mut self.projection
+    }
+
+    #[inline]
+    pub fn position(&self) -> Vector3 {
+        self.transform.position
+    }
+
+    /// The unit vector the camera is looking along.
+    #[inline]
+    pub fn direction(&self) -> Vector3 {
+        -self.transform.rotation.forward_axis()
+    }
+
+    #[inline]
+    pub fn up(&self) -> Vector3 {
+        self.transform.rotation.up_axis()
+    }
+
+    /// Point the camera with a turn around up (yaw) followed by a tilt around right (pitch).
+    ///
+    /// Both zero looks down -z. Positive pitch looks up.
+    #[inline]
+    pub fn set_yaw_pitch(&mut self, yaw: f32, pitch: f32) {
+        self.transform.rotation =
+            Quaternion::from_angle_up(yaw) * Quaternion::from_angle_right(pitch);
+    }
+
+    /// The yaw and pitch (see `set_yaw_pitch`) that the camera is looking with.
+    #[inline]
+    pub fn yaw_pitch(&self) -> (f32, f32) {
+        yaw_pitch(self.direction())
+    }
+
+    /// Turn the camera towards a point without rolling it.
+    #[inline]
+    pub fn look_at(&mut self, target: Vector3) {
+        let (yaw, pitch) = yaw_pitch((target - self.position()).normalized());
+        self.set_yaw_pitch(yaw, pitch);
+    }
+
+    /// Keep the projection in step with the size of whatever is being rendered to.
+    ///
+    /// Orthographic projections keep their height and center and only change in width.
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
+        match &mut self.projection {
+            Projection::Perspective(p) => p.aspect_ratio = aspect_ratio,
+            Projection::Orthographic(p) => {
+                let center = (p.right + p.left) / 2.0;
+                let half_width = (p.top - p.bottom) * aspect_ratio / 2.0;
+                p.left = center - half_width;
+                p.right = center + half_width;
+            }
+        }
+    }
+
+    #[inline]
+    pub fn view_matrix(&self) -> Matrix4 {
+        let position = self.position();
+        Matrix4::look_at(position, position + self.direction(), self.up())
+    }
+
+    #[inline]
+    pub fn projection_matrix(&self) -> Matrix4 {
+        (&self.projection).into()
+    }
+
+    #[inline]
+    pub fn view_projection_matrix(&self) -> Matrix4 {
+        &self.view_matrix() * &self.projection_matrix()
+    }
+
+    pub fn frustum(&self) -> Frustum {
+        let position = self.position();
+        let at = position + self.direction();
+        match &self.projection {
+            Projection::Perspective(p) => Frustum::new(p, position, at, self.up()),
+            Projection::Orthographic(p) => Frustum::orthographic(p, position, at, self.up()),
+        }
+    }
+
+    /// Where a world space point lands on the screen.
+    ///
+    /// Returns `None` for points behind the camera. Points off screen or past the far plane
+    /// still get (out of range) coordinates.
+    pub fn world_to_screen(&self, point: Vector3, screen_size: Vector2) -> Option<Vector2> {
+        let clip = self.view_projection_matrix().transform(point.widened(1.0));
+        if clip.w() <= 0.0 {
+            return None;
+        }
+        let ndc = clip.narrowed() / clip.w();
+        Some(Vector2::new(
+            (ndc.x() + 1.0) * 0.5 * screen_size.x(),
+            (1.0 - ndc.y()) * 0.5 * screen_size.y(),
+        ))
+    }
+
+    /// The ray from the near plane through a point on the screen (e.g. for mouse picking).
+    pub fn screen_to_ray(&self, point: Vector2, screen_size: Vector2) -> Ray {
+        let x = point.x() / screen_size.x() * 2.0 - 1.0;
+        let y = 1.0 - point.y() / screen_size.y() * 2.0;
+        let inverse = self.view_projection_matrix().inversed();
+        let unproject = |z: f32| {
+            let v = inverse.transform(Vector4::new(x, y, z, 1.0));
+            v.narrowed() / v.w()
+        };
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+        Ray::new(near, (far - near).normalized())
+    }
+}
+
+#[inline]
+fn yaw_pitch(direction: Vector3) -> (f32, f32) {
+    (
+        (-direction.x()).atan2(-direction.z()),
+        direction.y().clamp(-1.0, 1.0).asin(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::math::Quaternion;
+    use std::f32;
+
+    const SCREEN: Vector2 = Vector2([800.0, 600.0]);
+
+    fn assert_close(expected: Vector3, actual: Vector3) {
+        assert!(
+            (expected - actual).length() < 1e-3,
+            "expected {:?} but found {:?}",
+            expected,
+            actual
+        );
+    }
+
+    fn perspective() -> Camera {
+        Camera::new(
+            Transform {
+                position: Vector3::new(1.0, 2.0, 3.0),
+                rotation: Quaternion::from_angle_up(0.4) * Quaternion::from_angle_right(-0.3),
+                ..Transform::default()
+            },
+            Projection::Perspective(PerspectiveProjection {
+                fov: f32::consts::FRAC_PI_2,
+                aspect_ratio: SCREEN.x() / SCREEN.y(),
+                near: 0.1,
+                far: 100.0,
+            }),
+        )
+    }
+
+    fn orthographic() -> Camera {
+        Camera::new(
+            Transform {
+                position: Vector3::new(0.0, 10.0, 0.0),
+                rotation: Quaternion::from_angle_right(-f32::consts::FRAC_PI_2),
+                ..Transform::default()
+            },
+            Projection::Orthographic(OrthographicProjection {
+                left: -4.0,
+                right: 4.0,
+                top: 3.0,
+                bottom: -3.0,
+                near: 0.0,
+                far: 20.0,
+            }),
+        )
+    }
+
+    #[test]
+    fn views_from_the_camera() {
+        let camera = perspective();
+        let view = camera.view_matrix();
+        assert_close(Vector3::splat(0.0), view.transform_point(camera.position()));
+        // Straight ahead is down -z in view space
+        let ahead = camera.position() + camera.direction() * 5.0;
+        assert_close(Vector3::new(0.0, 0.0, -5.0), view.transform_point(ahead));
+        let above = camera.position() + camera.up() * 2.0;
+        assert_close(Vector3::new(0.0, 2.0, 0.0), view.transform_point(above));
+    }
+
+    #[test]
+    fn projects_to_the_screen() {
+        let camera = perspective();
+        let ahead = camera.position() + camera.direction() * 5.0;
+        let center = camera.world_to_screen(ahead, SCREEN).unwrap();
+        let offset = center - SCREEN / 2.0;
+        assert!(offset.x().abs() < 1e-2 && offset.y().abs() < 1e-2);
+
+        // Up in the world is up on the screen (smaller y), right is right
+        let up = camera.world_to_screen(ahead + camera.up(), SCREEN).unwrap();
+        assert!(up.y() < center.y());
+        let right = camera.transform().rotation.right_axis();
+        let right = camera.world_to_screen(ahead + right, SCREEN).unwrap();
+        assert!(right.x() > center.x());
+
+        let behind = camera.position() - camera.direction() * 5.0;
+        assert!(camera.world_to_screen(behind, SCREEN).is_none());
+    }
+
+    #[test]
+    fn unprojects_rays() {
+        for camera in &[perspective(), orthographic()] {
+            let point = camera.position() + camera.direction() * 7.0 + camera.up() * 0.5;
+            let screen = camera.world_to_screen(point, SCREEN).unwrap();
+            let ray = camera.screen_to_ray(screen, SCREEN);
+            // The ray has to pass through the point it was unprojected from
+            let t = (point - ray.origin).dot(ray.direction);
+            assert_close(point, ray.at(t));
+        }
+
+        // Orthographic rays are all parallel
+        let camera = orthographic();
+        let ray = camera.screen_to_ray(Vector2::new(0.0, 0.0), SCREEN);
+        assert_close(camera.direction(), ray.direction);
+        assert_close(Vector3::new(-4.0, 10.0, -3.0), ray.origin);
+    }
+
+    #[test]
+    fn derives_frustums() {
+        let camera = perspective();
+        let frustum = camera.frustum();
+        let ahead = camera.position() + camera.direction() * 10.0;
+        assert!(frustum.point_inside(ahead));
+        assert!(!frustum.point_inside(camera.position() - camera.direction() * 10.0));
+        assert!(!frustum.point_inside(camera.position() + camera.direction() * 200.0));
+        // A 90 degree fov is 10 units tall (each way) 10 units out
+        assert!(frustum.point_inside(ahead + camera.up() * 9.9));
+        assert!(!frustum.point_inside(ahead + camera.up() * 10.1));
+        assert!(frustum.sphere_inside(ahead + camera.up() * 10.5, 1.0));
+
+        let camera = orthographic();
+        let frustum = camera.frustum();
+        // Looking straight down, so the top of the screen is -z
+        assert!(frustum.point_inside(Vector3::new(3.9, 0.0, -2.9)));
+        assert!(!frustum.point_inside(Vector3::new(4.1, 0.0, 0.0)));
+        assert!(!frustum.point_inside(Vector3::new(0.0, 0.0, 3.1)));
+        assert!(!frustum.point_inside(Vector3::new(0.0, -11.0, 0.0)));
+        assert!(frustum.sphere_inside(Vector3::new(4.5, 0.0, 0.0), 1.0));
+    }
+
+    #[test]
+    fn aims() {
+        let mut camera = perspective();
+        let (yaw, pitch) = camera.yaw_pitch();
+        assert!((yaw - 0.4).abs() < 1e-4 && (pitch + 0.3).abs() < 1e-4);
+
+        let target = Vector3::new(-4.0, 0.0, 8.0);
+        camera.look_at(target);
+        let toward = (target - camera.position()).normalized();
+        assert_close(toward, camera.direction());
+        // No roll, so right stays level
+        assert!(camera.transform().rotation.right_axis().y().abs() < 1e-5);
+    }
+
+    #[test]
+    fn resizes() {
+        let mut camera = orthographic();
+        camera.set_aspect_ratio(2.0);
+        match camera.projection() {
+            Projection::Orthographic(p) => {
+                assert_eq!((-6.0, 6.0), (p.left, p.right));
+            }
+            _ => unreachable!(),
+        }
+
+        let mut camera = perspective();
+        camera.set_aspect_ratio(2.0);
+        let frustum = camera.frustum();
+        let ahead = camera.position() + camera.direction() * 10.0;
+        let right = camera.transform().rotation.right_axis();
+        assert!(frustum.point_inside(ahead + right * 19.9));
+        assert!(!frustum.point_inside(ahead + right * 20.1));
+    }
+}
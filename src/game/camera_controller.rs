@@ -0,0 +1,317 @@
+use std::f32;
+
+use crate::{
+    collections::{pool::Handle, Pool},
+    game::{Camera, Entity},
+    math::{self, Vector2, Vector3},
+};
+
+/// Just under straight up/down, so the view never flips over.
+const DEFAULT_PITCH_LIMIT: f32 = f32::consts::FRAC_PI_2 - 0.01;
+
+/// What the player wants the camera to do this update. Filled in from whatever the input
+/// happens to be (keyboard, gamepad, tests...).
+#[derive(Default, Debug, Copy, Clone)]
+pub struct CameraInput {
+    /// Movement relative to the camera: x is right, y is up and z is forward.
+    /// Each axis should be in \[-1, 1\].
+    pub movement: Vector3,
+    /// Change in yaw (x) and pitch (y) in radians.
+    pub look: Vector2,
+    /// Positive zooms in, negative zooms out.
+    pub zoom: f32,
+}
+
+#[derive(Debug)]
+pub enum CameraController {
+    FreeFly(FreeFly),
+    Orbit(Orbit),
+    Follow(Follow),
+}
+
+impl CameraController {
+    /// Move the camera by a single fixed update of `dt` seconds.
+    pub fn update(
+        &mut self,
+        camera: &mut Camera,
+        input: &CameraInput,
+        entities: &Pool<Entity>,
+        dt: f32,
+    ) {
+        match self {
+            CameraController::FreeFly(controller) => controller.update(camera, input, dt),
+            CameraController::Orbit(controller) => controller.update(camera, input),
+            CameraController::Follow(controller) => controller.update(camera, entities, dt),
+        }
+    }
+}
+
+/// Flies around wherever the camera is looking.
+#[derive(Debug, Copy, Clone)]
+pub struct FreeFly {
+    pub yaw: f32,
+    pub pitch: f32,
+    pub pitch_limit: f32,
+    pub velocity: Vector3,
+    /// Units per second squared at full input.
+    pub acceleration: f32,
+    pub max_speed: f32,
+    /// Fraction of velocity lost per second (exponential decay).
+    pub damping: f32,
+}
+
+impl FreeFly {
+    /// Start flying from wherever the camera is pointing.
+    pub fn new(camera: &Camera) -> FreeFly {
+        let (yaw, pitch) = camera.yaw_pitch();
+        FreeFly {
+            yaw,
+            pitch,
+            pitch_limit: DEFAULT_PITCH_LIMIT,
+            velocity: Vector3::default(),
+            acceleration: 240.0,
+            max_speed: 60.0,
+            damping: 8.0,
+        }
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, input: &CameraInput, dt: f32) {
+        self.yaw = math::normalize_angle(self.yaw + input.look.x());
+        self.pitch = math::clamp(
+            self.pitch + input.look.y(),
+            -self.pitch_limit,
+            self.pitch_limit,
+        );
+        camera.set_yaw_pitch(self.yaw, self.pitch);
+
+        let rotation = camera.transform().rotation;
+        let mut wish = rotation.right_axis() * input.movement.x()
+            + Vector3::up() * input.movement.y()
+            + camera.direction() * input.movement.z();
+        // Don't go faster diagonally
+        let wish_length = wish.length();
+        if wish_length > 1.0 {
+            wish = wish / wish_length;
+        }
+
+        self.velocity += wish * (self.acceleration * dt);
+        self.velocity = self.velocity * (-self.damping * dt).exp();
+        let speed = self.velocity.length();
+        if speed > self.max_speed {
+            self.velocity = self.velocity * (self.max_speed / speed);
+        }
+        camera.transform_mut().position += self.velocity * dt;
+    }
+}
+
+/// Circles around a point, always looking at it.
+#[derive(Debug, Copy, Clone)]
+pub struct Orbit {
+    pub target: Vector3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub pitch_limit: f32,
+    pub distance: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// How quickly zooming changes the distance. Zooming is multiplicative so it feels the
+    /// same up close and far away.
+    pub zoom_speed: f32,
+}
+
+impl Orbit {
+    pub fn new(target: Vector3, distance: f32) -> Orbit {
+        Orbit {
+            target,
+            yaw: 0.0,
+            pitch: 0.0,
+            pitch_limit: DEFAULT_PITCH_LIMIT,
+            distance,
+            min_distance: 1.0,
+            max_distance: 1000.0,
+            zoom_speed: 0.1,
+        }
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, input: &CameraInput) {
+        self.yaw = math::normalize_angle(self.yaw + input.look.x());
+        self.pitch = math::clamp(
+            self.pitch + input.look.y(),
+            -self.pitch_limit,
+            self.pitch_limit,
+        );
+        self.distance = math::clamp(
+            self.distance * (-input.zoom * self.zoom_speed).exp(),
+            self.min_distance,
+            self.max_distance,
+        );
+        camera.set_yaw_pitch(self.yaw, self.pitch);
+        let position = self.target - camera.direction() * self.distance;
+        camera.transform_mut().position = position;
+    }
+}
+
+/// Chases an entity on a damped spring, looking at it.
+#[derive(Debug, Copy, Clone)]
+pub struct Follow {
+    pub target: Handle<Entity>,
+    /// Where the camera wants to be, relative to the target (so it stays behind it as it turns).
+    pub offset: Vector3,
+    pub stiffness: f32,
+    pub damping: f32,
+    pub velocity: Vector3,
+}
+
+impl Follow {
+    /// A critically damped follower: it catches up as fast as it can without overshooting.
+    pub fn new(target: Handle<Entity>, offset: Vector3, stiffness: f32) -> Follow {
+        Follow {
+            target,
+            offset,
+            stiffness,
+            damping: 2.0 * stiffness.sqrt(),
+            velocity: Vector3::default(),
+        }
+    }
+
+    /// Uses the target's cached world matrix, so update the scene's world matrices first.
+    /// Does nothing if the target is gone.
+    pub fn update(&mut self, camera: &mut Camera, entities: &Pool<Entity>, dt: f32) {
+        let world = match entities.try_get(self.target) {
+            Some(entity) => entity.world_matrix(),
+            None => return,
+        };
+        let goal = world.transform_point(self.offset);
+        let focus = world.transform_point(Vector3::default());
+
+        // Semi-implicit euler keeps the spring stable at sensible time steps
+        let position = camera.position();
+        let acceleration = (goal - position) * self.stiffness - self.velocity * self.damping;
+        self.velocity += acceleration * dt;
+        camera.transform_mut().position = position + self.velocity * dt;
+        camera.look_at(focus);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        game::{camera::Projection, Scene},
+        gfx::{PerspectiveProjection, Transform},
+    };
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn camera() -> Camera {
+        Camera::new(
+            Transform::default(),
+            Projection::Perspective(PerspectiveProjection::default()),
+        )
+    }
+
+    fn assert_close(expected: Vector3, actual: Vector3) {
+        assert!(
+            (expected - actual).length() < 1e-3,
+            "expected {:?} but found {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn free_fly_accelerates_and_clamps_pitch() {
+        let mut camera = camera();
+        let mut controller = FreeFly::new(&camera);
+        let forward = CameraInput {
+            movement: Vector3::new(0.0, 0.0, 1.0),
+            ..CameraInput::default()
+        };
+
+        controller.update(&mut camera, &forward, DT);
+        let first_step = camera.position().length();
+        controller.update(&mut camera, &forward, DT);
+        let second_step = camera.position().length() - first_step;
+        assert!(second_step > first_step, "should pick up speed");
+        assert!(camera.position().z() < 0.0, "should fly where it looks");
+
+        for _ in 0..600 {
+            controller.update(&mut camera, &forward, DT);
+        }
+        assert!(controller.velocity.length() <= controller.max_speed + 1e-3);
+
+        // Coasts to a stop without input
+        for _ in 0..600 {
+            controller.update(&mut camera, &CameraInput::default(), DT);
+        }
+        assert!(controller.velocity.length() < 1e-3);
+
+        let look_up = CameraInput {
+            look: Vector2::new(0.0, 10.0),
+            ..CameraInput::default()
+        };
+        controller.update(&mut camera, &look_up, DT);
+        assert_eq!(controller.pitch_limit, controller.pitch);
+        assert!(camera.direction().y() < 1.0);
+        assert!(camera.up().y() > 0.0, "shouldn't flip over");
+    }
+
+    #[test]
+    fn orbits_and_zooms() {
+        let mut camera = camera();
+        let target = Vector3::new(1.0, 2.0, 3.0);
+        let mut controller = Orbit::new(target, 10.0);
+        let input = CameraInput {
+            look: Vector2::new(1.0, 0.5),
+            ..CameraInput::default()
+        };
+        controller.update(&mut camera, &input);
+        assert!(((camera.position() - target).length() - 10.0).abs() < 1e-3);
+        assert_close(target, camera.position() + camera.direction() * 10.0);
+
+        let zoom_in = CameraInput {
+            zoom: 1.0,
+            ..CameraInput::default()
+        };
+        controller.update(&mut camera, &zoom_in);
+        assert!(controller.distance < 10.0);
+        for _ in 0..1000 {
+            controller.update(&mut camera, &zoom_in);
+        }
+        assert_eq!(controller.min_distance, controller.distance);
+        assert_close(target, camera.position() + camera.direction());
+    }
+
+    #[test]
+    fn follows_with_a_spring() {
+        let mut scene = Scene::new(camera());
+        let target = scene.spawn(Entity::new(Transform {
+            position: Vector3::new(20.0, 0.0, 0.0),
+            ..Transform::default()
+        }));
+        scene.update_world_matrices();
+
+        let mut camera = camera();
+        let offset = Vector3::new(0.0, 2.0, 5.0);
+        let mut controller = Follow::new(target, offset, 30.0);
+        let mut last_distance = f32::MAX;
+        for _ in 0..120 {
+            controller.update(&mut camera, scene.entities(), DT);
+            let goal = Vector3::new(20.0, 2.0, 5.0);
+            let distance = (goal - camera.position()).length();
+            // Critically damped springs never overshoot
+            assert!(distance <= last_distance + 1e-4);
+            last_distance = distance;
+        }
+        assert!(last_distance < 0.1);
+        let focus = Vector3::new(20.0, 0.0, 0.0);
+        assert_close((focus - camera.position()).normalized(), camera.direction());
+
+        // Losing the target leaves the camera where it is
+        let position = camera.position();
+        scene.despawn(target);
+        let mut controller = CameraController::Follow(controller);
+        controller.update(&mut camera, &CameraInput::default(), scene.entities(), DT);
+        assert_close(position, camera.position());
+    }
+}
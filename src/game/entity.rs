@@ -1,41 +1,150 @@
 use smallvec::SmallVec;
 
 use crate::{
-    collections::pool::Handle,
-    gfx::Transform,
-    math::{Quaternion, Vector3},
+    collections::{pool::Handle, Pool, SparseSet},
+    gfx::{Light, Transform},
+    math::Matrix4,
+};
+
+pub use crate::game::{
+    logic::{Controller, Logic},
+    motion::Motion,
 };
 
 #[derive(Debug)]
 pub enum Renderer {}
 
-// TODO: velocity verlet-integration.
-//  I think it might be interesting to do scale as well for elastic things.
-#[derive(Default, Debug)]
-pub struct Motion {
-    velocity: Vector3,
-    angular_velocity: Quaternion,
-}
-
 // Fat *sparse* entity system. It is pretty ECS-like but every entity has every component.
 // The are controlled by generally 1 controller, but there is no theoretical limit.
 // This idea is based on the entity system in Handmade Hero.
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct Entity {
     handle: Handle<Entity>,
+    // Relative to the parent (or the world for root entities).
     transform: Transform,
+    // The transform before the latest motion step. Used to interpolate between fixed updates.
+    previous_transform: Transform,
     // TODO: should it be option? *probably* since we can branch over a lot of logic.
-    movement: Option<Motion>,
-    renderer: Option<Renderer>,
-    controller: Option<Handle<Controller>>,
+    pub movement: Option<Motion>,
+    pub renderer: Option<Renderer>,
+    pub light: Option<Light>,
+    pub controller: Option<Handle<Controller>>,
+
+    // Scene graph links. These are maintained by `Scene`.
+    pub(crate) parent: Option<Handle<Entity>>,
+    pub(crate) children: SmallVec<[Handle<Entity>; 4]>,
+    // Cached local-to-world matrix. Only up to date while `dirty` is false.
+    pub(crate) world: Matrix4,
+    pub(crate) dirty: bool,
 }
 
-#[derive(Default, Debug)]
-pub struct Controller {
-    handle: Handle<Controller>,
-    children: SmallVec<[Handle<Entity>; 16]>,
-    logic: SmallVec<[Logic; 16]>,
+impl Default for Entity {
+    #[inline]
+    fn default() -> Entity {
+        Entity {
+            handle: Handle::default(),
+            transform: Transform::default(),
+            previous_transform: Transform::default(),
+            movement: None,
+            renderer: None,
+            light: None,
+            controller: None,
+            parent: None,
+            children: SmallVec::new(),
+            world: Matrix4::identity(),
+            dirty: true,
+        }
+    }
 }
 
-#[derive(Debug)]
-pub enum Logic {}
+impl Entity {
+    #[inline]
+    pub fn new(transform: Transform) -> Entity {
+        Entity {
+            transform,
+            previous_transform: transform,
+            ..Entity::default()
+        }
+    }
+
+    #[inline]
+    pub fn handle(&self) -> Handle<Entity> {
+        self.handle
+    }
+
+    /// The transform relative to the parent entity.
+    #[inline]
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    /// Mutable access to the local transform. Marks the cached world matrix as dirty.
+    #[inline]
+    pub fn transform_mut(&mut self) -> &mut Transform {
+        self.dirty = true;
+        &mut self.transform
+    }
+
+    #[inline]
+    pub fn set_transform(&mut self, transform: Transform) {
+        *self.transform_mut() = transform;
+    }
+
+    /// Jump straight to a transform without interpolating from the previous one.
+    #[inline]
+    pub fn teleport(&mut self, transform: Transform) {
+        self.set_transform(transform);
+        self.previous_transform = transform;
+    }
+
+    #[inline]
+    pub fn parent(&self) -> Option<Handle<Entity>> {
+        self.parent
+    }
+
+    #[inline]
+    pub fn children(&self) -> &[Handle<Entity>] {
+        &self.children
+    }
+
+    /// The local-to-world matrix as of the last `Scene::update_world_matrices`.
+    #[inline]
+    pub fn world_matrix(&self) -> &Matrix4 {
+        &self.world
+    }
+
+    /// Whether the local transform changed since the world matrix was last updated.
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Register the entity into a pool, storing its handle back into itself.
+    #[inline]
+    pub fn spawn(self, entities: &mut Pool<Entity>) -> Handle<Entity> {
+        entities.register_with_callback(self, |entity, handle| entity.handle = handle)
+    }
+
+    /// Advance the entity's motion (if any) by `dt` seconds.
+    pub fn integrate(&mut self, dt: f32) {
+        self.previous_transform = self.transform;
+        if let Some(movement) = self.movement.as_mut() {
+            movement.integrate(&mut self.transform, dt);
+            self.dirty = true;
+        }
+    }
+
+    /// Blend between the transform before and after the latest motion step.
+    ///
+    /// `alpha` is usually `FixedTimestep::alpha`.
+    #[inline]
+    pub fn interpolated_transform(&self, alpha: f32) -> Transform {
+        self.previous_transform.interpolated(&self.transform, alpha)
+    }
+}
+
+/// Opt-in packed storage for components keyed by entity.
+///
+/// This lives alongside the fat `Entity` fields. Hot systems that only care about a few
+/// entities can keep their components here and `join` them instead of walking the whole pool.
+pub type Components<T> = SparseSet<Entity, T>;
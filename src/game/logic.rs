@@ -0,0 +1,449 @@
+use smallvec::SmallVec;
+use std::fmt::Debug;
+
+use crate::{
+    collections::{pool::Handle, Pool},
+    game::{Entity, Motion},
+    math::Vector3,
+};
+
+/// Owns a group of entities and the logic that drives them.
+#[derive(Default, Debug)]
+pub struct Controller {
+    handle: Handle<Controller>,
+    children: SmallVec<[Handle<Entity>; 16]>,
+    logic: SmallVec<[Logic; 16]>,
+}
+
+impl Controller {
+    #[inline]
+    pub fn new() -> Controller {
+        Controller::default()
+    }
+
+    #[inline]
+    pub fn with_logic(mut self, logic: Logic) -> Controller {
+        self.logic.push(logic);
+        self
+    }
+
+    #[inline]
+    pub fn handle(&self) -> Handle<Controller> {
+        self.handle
+    }
+
+    #[inline]
+    pub fn children(&self) -> &[Handle<Entity>] {
+        &self.children
+    }
+
+    #[inline]
+    pub fn logic(&self) -> &[Logic] {
+        &self.logic
+    }
+
+    #[inline]
+    pub fn push_logic(&mut self, logic: Logic) {
+        self.logic.push(logic);
+    }
+
+    /// Register the controller into a pool, storing its handle back into itself.
+    #[inline]
+    pub fn spawn(self, controllers: &mut Pool<Controller>) -> Handle<Controller> {
+        controllers.register_with_callback(self, |controller, handle| controller.handle = handle)
+    }
+}
+
+/// Game-specific behaviour that doesn't fit one of the built-in `Logic` variants.
+pub trait Script: Debug {
+    fn update(&mut self, ctx: &mut LogicContext);
+}
+
+#[derive(Debug)]
+pub enum Logic {
+    /// Keep every child spinning. The axis is scaled by the speed in radians per second.
+    Spin {
+        angular_velocity: Vector3,
+    },
+    /// Despawn the controller (and its children) once `remaining` seconds have passed.
+    Lifetime {
+        remaining: f32,
+    },
+    Script(Box<dyn Script>),
+}
+
+impl Logic {
+    pub fn update(&mut self, ctx: &mut LogicContext) {
+        match self {
+            Logic::Spin { angular_velocity } => {
+                for child in ctx.children {
+                    // Earlier logic may have removed it straight from the pool
+                    if let Some(entity) = ctx.entities.try_get_mut(*child) {
+                        entity
+                            .movement
+                            .get_or_insert_with(Motion::default)
+                            .angular_velocity = *angular_velocity;
+                    }
+                }
+            }
+            Logic::Lifetime { remaining } => {
+                *remaining -= ctx.dt;
+                if *remaining <= 0.0 {
+                    ctx.commands.despawn_controller(ctx.controller);
+                }
+            }
+            Logic::Script(script) => script.update(ctx),
+        }
+    }
+}
+
+/// Everything a piece of logic gets to see during a fixed update.
+///
+/// Entities can be freely modified, but spawning and despawning has to go through
+/// `commands` so the children of every controller stay valid until all logic has run.
+pub struct LogicContext<'a> {
+    pub dt: f32,
+    pub controller: Handle<Controller>,
+    pub children: &'a [Handle<Entity>],
+    pub entities: &'a mut Pool<Entity>,
+    pub commands: &'a mut Commands,
+}
+
+#[derive(Debug)]
+enum Command {
+    Spawn {
+        entity: Box<Entity>,
+        controller: Option<Handle<Controller>>,
+    },
+    Despawn(Handle<Entity>),
+    DespawnController(Handle<Controller>),
+}
+
+/// Spawns and despawns queued up to be applied once it is safe to do so.
+#[derive(Default, Debug)]
+pub struct Commands {
+    commands: Vec<Command>,
+}
+
+impl Commands {
+    #[inline]
+    pub fn spawn(&mut self, entity: Entity) {
+        self.commands.push(Command::Spawn {
+            entity: Box::new(entity),
+            controller: None,
+        });
+    }
+
+    /// Spawn an entity as a child of `controller`.
+    ///
+    /// The entity is dropped if the controller is gone by the time the command is applied.
+    #[inline]
+    pub fn spawn_child(&mut self, controller: Handle<Controller>, entity: Entity) {
+        self.commands.push(Command::Spawn {
+            entity: Box::new(entity),
+            controller: Some(controller),
+        });
+    }
+
+    #[inline]
+    pub fn despawn(&mut self, entity: Handle<Entity>) {
+        self.commands.push(Command::Despawn(entity));
+    }
+
+    /// Despawn a controller along with all of its children.
+    #[inline]
+    pub fn despawn_controller(&mut self, controller: Handle<Controller>) {
+        self.commands.push(Command::DespawnController(controller));
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Apply every queued command in the order they were issued.
+    ///
+    /// Despawning something that is already gone does nothing.
+    pub fn apply(&mut self, controllers: &mut Pool<Controller>, entities: &mut Pool<Entity>) {
+        for command in self.commands.drain(..) {
+            match command {
+                Command::Spawn { entity, controller } => match controller {
+                    Some(controller) if controllers.contains(controller) => {
+                        let entity = (*entity).spawn(entities);
+                        attach(controllers, entities, controller, entity);
+                    }
+                    Some(_) => {}
+                    None => {
+                        (*entity).spawn(entities);
+                    }
+                },
+                Command::Despawn(entity) => {
+                    despawn(controllers, entities, entity);
+                }
+                Command::DespawnController(controller) => {
+                    despawn_controller(controllers, entities, controller);
+                }
+            }
+        }
+    }
+}
+
+/// Make `entity` a child of `controller`, taking it away from its previous controller.
+pub fn attach(
+    controllers: &mut Pool<Controller>,
+    entities: &mut Pool<Entity>,
+    controller: Handle<Controller>,
+    entity: Handle<Entity>,
+) {
+    let previous = entities.get_mut(entity).controller.replace(controller);
+    if let Some(previous) = previous.and_then(|previous| controllers.try_get_mut(previous)) {
+        previous.children.retain(|child| *child != entity);
+    }
+    let children = &mut controllers.get_mut(controller).children;
+    if !children.contains(&entity) {
+        children.push(entity);
+    }
+}
+
+/// Remove an entity from the pool and from its controller's children.
+pub fn despawn(
+    controllers: &mut Pool<Controller>,
+    entities: &mut Pool<Entity>,
+    entity: Handle<Entity>,
+) -> Option<Entity> {
+    if !entities.contains(entity) {
+        return None;
+    }
+    let removed = entities.remove(entity);
+    if let Some(controller) = removed
+        .controller
+        .and_then(|controller| controllers.try_get_mut(controller))
+    {
+        controller.children.retain(|child| *child != entity);
+    }
+    Some(removed)
+}
+
+/// Remove a controller and every entity it still owns.
+pub fn despawn_controller(
+    controllers: &mut Pool<Controller>,
+    entities: &mut Pool<Entity>,
+    controller: Handle<Controller>,
+) -> Option<Controller> {
+    if !controllers.contains(controller) {
+        return None;
+    }
+    let removed = controllers.remove(controller);
+    for child in removed.children.iter() {
+        if matches!(entities.try_get(*child), Some(entity) if entity.controller == Some(controller))
+        {
+            entities.remove(*child);
+        }
+    }
+    Some(removed)
+}
+
+/// Run the logic of every controller for a single fixed update of `dt` seconds, then apply
+/// the commands it queued.
+///
+/// Children that were removed from the entity pool behind the controller's back are
+/// forgotten before its logic runs. Logic that removes them itself during the update can
+/// still leave dead children for the logic after it, so those have to be looked up with
+/// `try_get_mut`.
+pub fn update_logic(
+    controllers: &mut Pool<Controller>,
+    entities: &mut Pool<Entity>,
+    commands: &mut Commands,
+    dt: f32,
+) {
+    for controller in controllers.iter_mut() {
+        let Controller {
+            handle,
+            children,
+            logic,
+        } = controller;
+        children.retain(|child| entities.contains(*child));
+        for logic in logic.iter_mut() {
+            logic.update(&mut LogicContext {
+                dt,
+                controller: *handle,
+                children,
+                entities,
+                commands,
+            });
+        }
+    }
+    commands.apply(controllers, entities);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Spawner {
+        seen: Vec<usize>,
+    }
+
+    impl Script for Spawner {
+        fn update(&mut self, ctx: &mut LogicContext) {
+            self.seen.push(ctx.children.len());
+            ctx.commands.spawn_child(ctx.controller, Entity::default());
+            // Nothing shows up until the commands are applied
+            assert_eq!(self.seen.len() - 1, ctx.entities.len());
+        }
+    }
+
+    /// Removes the first child straight from the pool, skipping the commands.
+    #[derive(Debug)]
+    struct Culler;
+
+    impl Script for Culler {
+        fn update(&mut self, ctx: &mut LogicContext) {
+            ctx.entities.remove(ctx.children[0]);
+        }
+    }
+
+    fn spawn_children(
+        controllers: &mut Pool<Controller>,
+        entities: &mut Pool<Entity>,
+        controller: Handle<Controller>,
+        count: usize,
+    ) -> Vec<Handle<Entity>> {
+        (0..count)
+            .map(|_| {
+                let entity = Entity::default().spawn(entities);
+                attach(controllers, entities, controller, entity);
+                entity
+            })
+            .collect()
+    }
+
+    #[test]
+    fn runs_logic_on_children() {
+        let mut controllers = Pool::default();
+        let mut entities = Pool::default();
+        let mut commands = Commands::default();
+        let spinner = Controller::new()
+            .with_logic(Logic::Spin {
+                angular_velocity: Vector3::up(),
+            })
+            .spawn(&mut controllers);
+        let children = spawn_children(&mut controllers, &mut entities, spinner, 3);
+        let loner = Entity::default().spawn(&mut entities);
+
+        update_logic(&mut controllers, &mut entities, &mut commands, 1.0);
+        for child in children {
+            let movement = entities.get(child).movement.unwrap();
+            assert_eq!(Vector3::up(), movement.angular_velocity);
+        }
+        assert!(entities.get(loner).movement.is_none());
+    }
+
+    #[test]
+    fn skips_children_removed_by_earlier_logic() {
+        let mut controllers = Pool::default();
+        let mut entities = Pool::default();
+        let mut commands = Commands::default();
+        let spinner = Controller::new()
+            .with_logic(Logic::Script(Box::new(Culler)))
+            .with_logic(Logic::Spin {
+                angular_velocity: Vector3::up(),
+            })
+            .spawn(&mut controllers);
+        let children = spawn_children(&mut controllers, &mut entities, spinner, 2);
+
+        update_logic(&mut controllers, &mut entities, &mut commands, 1.0);
+        assert!(!entities.contains(children[0]));
+        let movement = entities.get(children[1]).movement.unwrap();
+        assert_eq!(Vector3::up(), movement.angular_velocity);
+    }
+
+    #[test]
+    fn defers_spawns() {
+        let mut controllers = Pool::default();
+        let mut entities = Pool::default();
+        let mut commands = Commands::default();
+        let spawner = Controller::new()
+            .with_logic(Logic::Script(Box::new(Spawner { seen: Vec::new() })))
+            .spawn(&mut controllers);
+
+        for _ in 0..3 {
+            update_logic(&mut controllers, &mut entities, &mut commands, 1.0);
+        }
+        assert!(commands.is_empty());
+        assert_eq!(3, entities.len());
+        let controller = controllers.get(spawner);
+        assert_eq!(3, controller.children().len());
+        for child in controller.children() {
+            assert_eq!(Some(spawner), entities.get(*child).controller);
+        }
+        match &controller.logic()[0] {
+            Logic::Script(script) => {
+                assert_eq!("Spawner { seen: [0, 1, 2] }", format!("{:?}", script))
+            }
+            logic => panic!("unexpected logic {:?}", logic),
+        }
+    }
+
+    #[test]
+    fn despawns_after_lifetime() {
+        let mut controllers = Pool::default();
+        let mut entities = Pool::default();
+        let mut commands = Commands::default();
+        let doomed = Controller::new()
+            .with_logic(Logic::Lifetime { remaining: 1.5 })
+            .spawn(&mut controllers);
+        spawn_children(&mut controllers, &mut entities, doomed, 4);
+        let survivor = Entity::default().spawn(&mut entities);
+
+        update_logic(&mut controllers, &mut entities, &mut commands, 1.0);
+        assert!(controllers.contains(doomed));
+        assert_eq!(5, entities.len());
+
+        update_logic(&mut controllers, &mut entities, &mut commands, 1.0);
+        assert!(!controllers.contains(doomed));
+        assert_eq!(1, entities.len());
+        assert!(entities.contains(survivor));
+    }
+
+    #[test]
+    fn keeps_children_consistent() {
+        let mut controllers = Pool::default();
+        let mut entities = Pool::default();
+        let mut commands = Commands::default();
+        let a = Controller::new().spawn(&mut controllers);
+        let b = Controller::new().spawn(&mut controllers);
+        let children = spawn_children(&mut controllers, &mut entities, a, 4);
+
+        // Moving a child between controllers
+        attach(&mut controllers, &mut entities, b, children[0]);
+        assert_eq!(&children[1..], controllers.get(a).children());
+        assert_eq!(&children[..1], controllers.get(b).children());
+
+        // Despawning through the controller-aware path
+        commands.despawn(children[1]);
+        commands.despawn(children[1]);
+        commands.apply(&mut controllers, &mut entities);
+        assert_eq!(&children[2..], controllers.get(a).children());
+
+        // Removing straight from the pool, with the slot being reused
+        entities.remove(children[2]);
+        let reused = Entity::default().spawn(&mut entities);
+        assert_eq!(children[2].index(), reused.index());
+        update_logic(&mut controllers, &mut entities, &mut commands, 1.0);
+        assert_eq!(&children[3..], controllers.get(a).children());
+
+        // A controller only takes down entities that are still its own
+        attach(&mut controllers, &mut entities, b, children[3]);
+        despawn_controller(&mut controllers, &mut entities, a);
+        assert!(entities.contains(children[3]));
+        assert!(entities.contains(children[0]));
+        assert!(despawn_controller(&mut controllers, &mut entities, a).is_none());
+
+        // Children spawned for a dead controller are dropped
+        commands.spawn_child(a, Entity::default());
+        commands.apply(&mut controllers, &mut entities);
+        assert_eq!(3, entities.len());
+    }
+}
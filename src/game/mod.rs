@@ -1,7 +1,13 @@
 pub mod camera;
+pub mod camera_controller;
 pub mod entity;
+pub mod logic;
+pub mod motion;
 pub mod scene;
 
 pub use camera::Camera;
+pub use camera_controller::{CameraController, CameraInput, Follow, FreeFly, Orbit};
 pub use entity::Entity;
+pub use logic::{Commands, Controller, Logic, LogicContext, Script};
+pub use motion::{FixedTimestep, Motion};
 pub use scene::Scene;
@@ -0,0 +1,246 @@
+use crate::{
+    collections::Pool,
+    game::Entity,
+    gfx::Transform,
+    math::{Quaternion, Vector3},
+};
+
+/// Linear, angular and scale motion integrated with velocity verlet.
+///
+/// Accelerations are treated as constant over a step, so game code should set them
+/// before each fixed update (e.g. from forces or input).
+#[derive(Default, Debug, Copy, Clone)]
+pub struct Motion {
+    pub velocity: Vector3,
+    pub acceleration: Vector3,
+    /// Fraction of velocity lost per second (exponential decay).
+    pub damping: f32,
+
+    /// Rotation axis scaled by the speed in radians per second (in world space).
+    pub angular_velocity: Vector3,
+    pub angular_acceleration: Vector3,
+    /// Fraction of angular velocity lost per second (exponential decay).
+    pub angular_damping: f32,
+
+    /// Change in scale per second. Mostly interesting for elastic things.
+    pub scale_velocity: Vector3,
+    pub scale_acceleration: Vector3,
+}
+
+impl Motion {
+    pub fn integrate(&mut self, transform: &mut Transform, dt: f32) {
+        // x(t + dt) = x(t) + v(t)dt + a(t)dt^2/2
+        // v(t + dt) = v(t) + a(t)dt (since a is constant over the step)
+        transform.position += (self.velocity + self.acceleration * (0.5 * dt)) * dt;
+        self.velocity += self.acceleration * dt;
+        self.velocity = self.velocity * (-self.damping * dt).exp();
+
+        let rotation = (self.angular_velocity + self.angular_acceleration * (0.5 * dt)) * dt;
+        let angle = rotation.length();
+        if angle > f32::EPSILON {
+            transform.rotation = (Quaternion::from_axis_angle(rotation / angle, angle)
+                * transform.rotation)
+                .normalized();
+        }
+        self.angular_velocity += self.angular_acceleration * dt;
+        self.angular_velocity = self.angular_velocity * (-self.angular_damping * dt).exp();
+
+        transform.scale += (self.scale_velocity + self.scale_acceleration * (0.5 * dt)) * dt;
+        self.scale_velocity += self.scale_acceleration * dt;
+    }
+}
+
+/// Integrate every entity in the pool that has motion by a single step of `dt` seconds.
+///
+/// The transforms from before the step are kept for `Entity::interpolated_transform`.
+pub fn integrate_motion(entities: &mut Pool<Entity>, dt: f32) {
+    for entity in entities.iter_mut() {
+        entity.integrate(dt);
+    }
+}
+
+/// Splits variable frame times into fixed-size steps.
+///
+/// ```
+/// use dth::game::FixedTimestep;
+/// let mut timestep = FixedTimestep::new(1.0 / 60.0);
+/// timestep.accumulate(1.0 / 30.0);
+/// let mut steps = 0;
+/// while timestep.tick() {
+///     steps += 1;
+/// }
+/// assert_eq!(2, steps);
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct FixedTimestep {
+    step: f32,
+    accumulator: f32,
+    max_steps: u32,
+}
+
+impl FixedTimestep {
+    #[inline]
+    pub fn new(step: f32) -> FixedTimestep {
+        FixedTimestep {
+            step,
+            accumulator: 0.0,
+            max_steps: 8,
+        }
+    }
+
+    #[inline]
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    #[inline]
+    pub fn set_step(&mut self, step: f32) {
+        self.step = step;
+    }
+
+    /// Limit how many steps can be pending at once. Any frame time past that is dropped,
+    /// so one slow frame can't snowball into ever more updates per frame.
+    #[inline]
+    pub fn set_max_steps(&mut self, max_steps: u32) {
+        self.max_steps = max_steps;
+    }
+
+    #[inline]
+    pub fn accumulate(&mut self, elapsed: f32) {
+        self.accumulator = (self.accumulator + elapsed).min(self.step * self.max_steps as f32);
+    }
+
+    /// Consume a step if enough time has been accumulated.
+    #[inline]
+    pub fn tick(&mut self) -> bool {
+        if self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// How far \[0 - 1\] the leftover time is into the next step.
+    ///
+    /// Use it to blend between the previous and current transforms when rendering.
+    #[inline]
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.step
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::f32;
+
+    fn assert_close(expected: Vector3, actual: Vector3) {
+        assert!(
+            (expected - actual).length() < 1e-3,
+            "expected {:?} but found {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn constant_acceleration_is_exact() {
+        let mut transform = Transform::default();
+        let mut motion = Motion {
+            velocity: Vector3::new(1.0, 0.0, 0.0),
+            acceleration: Vector3::new(0.0, -10.0, 0.0),
+            ..Motion::default()
+        };
+        for _ in 0..60 {
+            motion.integrate(&mut transform, 1.0 / 60.0);
+        }
+        // x = v*t, y = a*t^2/2
+        assert_close(Vector3::new(1.0, -5.0, 0.0), transform.position);
+        assert_close(Vector3::new(1.0, -10.0, 0.0), motion.velocity);
+    }
+
+    #[test]
+    fn damps() {
+        let mut transform = Transform::default();
+        let mut motion = Motion {
+            velocity: Vector3::new(8.0, 0.0, 0.0),
+            damping: f32::consts::LN_2,
+            ..Motion::default()
+        };
+        for _ in 0..60 {
+            motion.integrate(&mut transform, 1.0 / 60.0);
+        }
+        // Half the velocity is lost every second
+        assert_close(Vector3::new(4.0, 0.0, 0.0), motion.velocity);
+    }
+
+    #[test]
+    fn rotates() {
+        let mut transform = Transform::default();
+        let mut motion = Motion {
+            angular_velocity: Vector3::up() * f32::consts::FRAC_PI_2,
+            ..Motion::default()
+        };
+        for _ in 0..60 {
+            motion.integrate(&mut transform, 1.0 / 60.0);
+        }
+        // A quarter turn around up takes forward to right
+        assert_close(Vector3::right(), transform.rotation.forward_axis());
+        assert!((transform.rotation.0.length() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn scales() {
+        let mut transform = Transform::default();
+        let mut motion = Motion {
+            scale_velocity: Vector3::splat(1.0),
+            ..Motion::default()
+        };
+        motion.integrate(&mut transform, 0.5);
+        assert_close(Vector3::splat(1.5), transform.scale);
+    }
+
+    #[test]
+    fn fixed_steps() {
+        let mut timestep = FixedTimestep::new(0.25);
+        timestep.accumulate(0.6);
+        assert!(timestep.tick());
+        assert!(timestep.tick());
+        assert!(!timestep.tick());
+        assert!((timestep.alpha() - 0.4).abs() < 1e-5);
+
+        // Way too much time gets clamped
+        timestep.set_max_steps(4);
+        timestep.accumulate(100.0);
+        let mut steps = 0;
+        while timestep.tick() {
+            steps += 1;
+        }
+        assert_eq!(4, steps);
+    }
+
+    #[test]
+    fn interpolates_entities() {
+        let mut entities = Pool::default();
+        let mut entity = Entity::new(Transform::default());
+        entity.movement = Some(Motion {
+            velocity: Vector3::new(2.0, 0.0, 0.0),
+            ..Motion::default()
+        });
+        let moving = entity.spawn(&mut entities);
+        let still = Entity::new(Transform::default()).spawn(&mut entities);
+
+        integrate_motion(&mut entities, 1.0);
+        let halfway = entities.get(moving).interpolated_transform(0.5);
+        assert_close(Vector3::new(1.0, 0.0, 0.0), halfway.position);
+        assert_close(
+            Vector3::new(2.0, 0.0, 0.0),
+            entities.get(moving).transform().position,
+        );
+        assert_close(
+            Vector3::splat(0.0),
+            entities.get(still).interpolated_transform(0.5).position,
+        );
+    }
+}
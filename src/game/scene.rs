@@ -1,9 +1,376 @@
 use crate::{
-    collections::Pool,
+    collections::{pool::Handle, Pool},
     game::{Camera, Entity},
+    gfx::Transform,
+    math::Matrix4,
 };
 
+// An entity waiting for its world matrix while walking the hierarchy.
+struct Pending {
+    handle: Handle<Entity>,
+    parent: Option<Handle<Entity>>,
+    parent_world: Matrix4,
+    parent_dirty: bool,
+}
+
+impl Pending {
+    #[inline]
+    fn root(handle: Handle<Entity>) -> Pending {
+        Pending {
+            handle,
+            parent: None,
+            parent_world: Matrix4::identity(),
+            parent_dirty: false,
+        }
+    }
+}
+
 pub struct Scene {
     camera: Camera,
     entities: Pool<Entity>,
+    // Scratch space for walking the hierarchy without allocating every update.
+    stack: Vec<Pending>,
+}
+
+impl Scene {
+    #[inline]
+    pub fn new(camera: Camera) -> Scene {
+        Scene {
+            camera,
+            entities: Pool::default(),
+            stack: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    #[inline]
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    #[inline]
+    pub fn entities(&self) -> &Pool<Entity> {
+        &self.entities
+    }
+
+    #[inline]
+    pub fn entities_mut(&mut self) -> &mut Pool<Entity> {
+        &mut self.entities
+    }
+
+    /// Add a root entity to the scene.
+    #[inline]
+    pub fn spawn(&mut self, entity: Entity) -> Handle<Entity> {
+        entity.spawn(&mut self.entities)
+    }
+
+    /// Add an entity whose transform is relative to `parent`.
+    pub fn spawn_child(&mut self, parent: Handle<Entity>, mut entity: Entity) -> Handle<Entity> {
+        entity.parent = Some(parent);
+        let child = entity.spawn(&mut self.entities);
+        self.entities.get_mut(parent).children.push(child);
+        child
+    }
+
+    /// Remove an entity along with everything below it in the hierarchy.
+    ///
+    /// Controllers notice their children are gone the next time their logic runs.
+    pub fn despawn(&mut self, handle: Handle<Entity>) -> Entity {
+        let entity = self.entities.remove(handle);
+        if let Some(parent) = entity
+            .parent
+            .and_then(|parent| self.entities.try_get_mut(parent))
+        {
+            parent.children.retain(|child| *child != handle);
+        }
+        let mut pending = entity.children.to_vec();
+        while let Some(child) = pending.pop() {
+            if self.entities.contains(child) {
+                pending.extend(self.entities.remove(child).children);
+            }
+        }
+        entity
+    }
+
+    /// Compose the transforms from the root down to an entity.
+    ///
+    /// This walks the parents every time. Prefer `Entity::world_matrix` for rendering.
+    pub fn world_transform(&self, handle: Handle<Entity>) -> Transform {
+        let mut entity = self.entities.get(handle);
+        let mut world = *entity.transform();
+        while let Some(parent) = entity
+            .parent
+            .and_then(|parent| self.entities.try_get(parent))
+        {
+            world = parent.transform().concat(&world);
+            entity = parent;
+        }
+        world
+    }
+
+    /// Move an entity under a new parent (or to the root), keeping it where it is in the world.
+    ///
+    /// Panics if `parent` is `child` or one of its descendants.
+    pub fn set_parent(&mut self, child: Handle<Entity>, parent: Option<Handle<Entity>>) {
+        let mut ancestor = parent;
+        while let Some(handle) = ancestor {
+            assert!(
+                handle != child,
+                "Can't parent an entity to itself or one of its descendants"
+            );
+            ancestor = self.entities.get(handle).parent;
+        }
+
+        let world = self.world_transform(child);
+        let local = match parent {
+            Some(parent) => self.world_transform(parent).inversed().concat(&world),
+            None => world,
+        };
+
+        let entity = self.entities.get_mut(child);
+        let previous = entity.parent;
+        entity.parent = parent;
+        entity.teleport(local);
+        if let Some(previous) = previous.and_then(|previous| self.entities.try_get_mut(previous)) {
+            previous.children.retain(|handle| *handle != child);
+        }
+        if let Some(parent) = parent {
+            self.entities.get_mut(parent).children.push(child);
+        }
+    }
+
+    /// Refresh the cached world matrices of every entity whose transform (or the transform of
+    /// one of its ancestors) changed since the last update.
+    ///
+    /// Entities whose parent was removed from the pool directly become roots.
+    pub fn update_world_matrices(&mut self) {
+        let mut orphans = Vec::new();
+        for (handle, entity) in self.entities.iter_with_handles() {
+            match entity.parent {
+                None => self.stack.push(Pending::root(handle)),
+                Some(parent) if !self.entities.contains(parent) => orphans.push(handle),
+                Some(_) => {}
+            }
+        }
+        for handle in orphans {
+            let entity = self.entities.get_mut(handle);
+            entity.parent = None;
+            entity.dirty = true;
+            self.stack.push(Pending::root(handle));
+        }
+
+        let mut dead = Vec::new();
+        while let Some(Pending {
+            handle,
+            parent,
+            parent_world,
+            parent_dirty,
+        }) = self.stack.pop()
+        {
+            let entity = match self.entities.try_get_mut(handle) {
+                Some(entity) => entity,
+                None => {
+                    dead.extend(parent.map(|parent| (parent, handle)));
+                    continue;
+                }
+            };
+            let dirty = entity.dirty || parent_dirty;
+            if dirty {
+                let local: Matrix4 = entity.transform().into();
+                entity.world = &local * &parent_world;
+                entity.dirty = false;
+            }
+            let world = entity.world;
+            self.stack
+                .extend(entity.children.iter().map(|child| Pending {
+                    handle: *child,
+                    parent: Some(handle),
+                    parent_world: world,
+                    parent_dirty: dirty,
+                }));
+        }
+
+        for (parent, child) in dead {
+            if let Some(parent) = self.entities.try_get_mut(parent) {
+                parent.children.retain(|handle| *handle != child);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        game::camera::Projection,
+        gfx::PerspectiveProjection,
+        math::{Quaternion, Vector3},
+    };
+    use std::f32;
+
+    fn scene() -> Scene {
+        Scene::new(Camera::new(
+            Transform::default(),
+            Projection::Perspective(PerspectiveProjection::default()),
+        ))
+    }
+
+    fn assert_close(expected: Vector3, actual: Vector3) {
+        assert!(
+            (expected - actual).length() < 1e-4,
+            "expected {:?} but found {:?}",
+            expected,
+            actual
+        );
+    }
+
+    fn parent_transform() -> Transform {
+        Transform {
+            position: Vector3::new(10.0, 0.0, 0.0),
+            scale: Vector3::splat(2.0),
+            rotation: Quaternion::from_angle_up(f32::consts::FRAC_PI_2),
+        }
+    }
+
+    #[test]
+    fn composes_child_transforms() {
+        let mut scene = scene();
+        let parent = scene.spawn(Entity::new(parent_transform()));
+        let child = scene.spawn_child(
+            parent,
+            Entity::new(Transform {
+                position: Vector3::new(1.0, 0.0, 0.0),
+                ..Transform::default()
+            }),
+        );
+
+        // The offset is doubled and a quarter turn around up takes right to backward
+        let world = scene.world_transform(child);
+        assert_close(Vector3::new(10.0, 0.0, -2.0), world.position);
+        assert_close(Vector3::splat(2.0), world.scale);
+
+        scene.update_world_matrices();
+        let matrix = scene.entities().get(child).world_matrix();
+        assert_close(world.position, matrix.transform_point(Vector3::splat(0.0)));
+        let p = Vector3::new(0.5, -1.0, 3.0);
+        assert_close(world.transform_point(p), matrix.transform_point(p));
+    }
+
+    #[test]
+    fn only_updates_dirty_branches() {
+        let mut scene = scene();
+        let a = scene.spawn(Entity::default());
+        let a_child = scene.spawn_child(a, Entity::default());
+        let b = scene.spawn(Entity::default());
+        let b_child = scene.spawn_child(b, Entity::default());
+        scene.update_world_matrices();
+        for (_, entity) in scene.entities().iter_with_handles() {
+            assert!(!entity.is_dirty());
+        }
+
+        // Poison the cached matrices so recomputed ones stand out
+        let poison = Matrix4::scale(Vector3::splat(-1.0));
+        for handle in [a_child, b_child].iter() {
+            scene.entities_mut().get_mut(*handle).world = poison;
+        }
+        scene.entities_mut().get_mut(a).transform_mut().position = Vector3::new(0.0, 5.0, 0.0);
+        assert!(scene.entities().get(a).is_dirty());
+        scene.update_world_matrices();
+
+        // The child of the moved parent was recomputed, the other branch was left alone
+        let moved = scene.entities().get(a_child).world_matrix();
+        assert_close(
+            Vector3::new(0.0, 5.0, 0.0),
+            moved.transform_point(Vector3::splat(0.0)),
+        );
+        let untouched = scene.entities().get(b_child).world_matrix();
+        assert_close(
+            Vector3::splat(-1.0),
+            untouched.transform_point(Vector3::splat(1.0)),
+        );
+    }
+
+    #[test]
+    fn reparents_in_place() {
+        let mut scene = scene();
+        let a = scene.spawn(Entity::new(parent_transform()));
+        let b = scene.spawn(Entity::new(Transform {
+            position: Vector3::new(-3.0, 4.0, 1.0),
+            scale: Vector3::splat(0.5),
+            rotation: Quaternion::from_angle_right(1.0) * Quaternion::from_angle_forward(0.5),
+        }));
+        let child = scene.spawn_child(
+            a,
+            Entity::new(Transform {
+                position: Vector3::new(1.0, 2.0, 3.0),
+                rotation: Quaternion::from_angle_up(0.3),
+                ..Transform::default()
+            }),
+        );
+        let before = scene.world_transform(child);
+        let probe = Vector3::new(1.0, 1.0, 1.0);
+
+        scene.set_parent(child, Some(b));
+        assert_eq!(Some(b), scene.entities().get(child).parent());
+        assert!(scene.entities().get(a).children().is_empty());
+        assert_eq!(&[child], scene.entities().get(b).children());
+        let after = scene.world_transform(child);
+        assert_close(before.position, after.position);
+        assert_close(before.transform_point(probe), after.transform_point(probe));
+
+        scene.set_parent(child, None);
+        assert!(scene.entities().get(b).children().is_empty());
+        let after = scene.world_transform(child);
+        assert_close(before.transform_point(probe), after.transform_point(probe));
+    }
+
+    #[test]
+    #[should_panic(expected = "descendants")]
+    fn rejects_cycles() {
+        let mut scene = scene();
+        let a = scene.spawn(Entity::default());
+        let b = scene.spawn_child(a, Entity::default());
+        let c = scene.spawn_child(b, Entity::default());
+        scene.set_parent(a, Some(c));
+    }
+
+    #[test]
+    fn despawns_subtrees() {
+        let mut scene = scene();
+        let root = scene.spawn(Entity::default());
+        let a = scene.spawn_child(root, Entity::default());
+        let b = scene.spawn_child(root, Entity::default());
+        scene.spawn_child(a, Entity::default());
+        scene.spawn_child(a, Entity::default());
+
+        scene.despawn(a);
+        assert_eq!(2, scene.entities().len());
+        assert_eq!(&[b], scene.entities().get(root).children());
+    }
+
+    #[test]
+    fn recovers_from_removals_behind_its_back() {
+        let mut scene = scene();
+        let root = scene.spawn(Entity::default());
+        let parent = scene.spawn_child(root, Entity::new(parent_transform()));
+        let child = scene.spawn_child(parent, Entity::default());
+        scene.update_world_matrices();
+
+        scene.entities_mut().remove(parent);
+        scene.update_world_matrices();
+        assert!(scene.entities().get(root).children().is_empty());
+        assert_eq!(None, scene.entities().get(child).parent());
+        assert_close(
+            Vector3::splat(0.0),
+            scene
+                .entities()
+                .get(child)
+                .world_matrix()
+                .transform_point(Vector3::splat(0.0)),
+        );
+    }
 }
@@ -0,0 +1,387 @@
+use std::io::{self, ErrorKind};
+
+use crate::{
+    gfx::{block_count, BitmapFormat, MipLevel},
+    util,
+};
+
+/// A decoded 4x4 block of BGRA pixels, row by row.
+pub type Block = [[u8; 4]; 16];
+
+/// Widen a 5:6:5 color to BGRA, repeating the top bits into the new low ones.
+#[inline]
+fn unpack_565(color: u16) -> [u8; 4] {
+    let r = ((color >> 11) & 0x1F) as u8;
+    let g = ((color >> 5) & 0x3F) as u8;
+    let b = (color & 0x1F) as u8;
+    [
+        (b << 3) | (b >> 2),
+        (g << 2) | (g >> 4),
+        (r << 3) | (r >> 2),
+        255,
+    ]
+}
+
+/// `weight_a` parts of `a` to `weight_b` parts of `b`, rounded.
+#[inline]
+fn blend(a: u8, b: u8, weight_a: u32, weight_b: u32) -> u8 {
+    let total = weight_a + weight_b;
+    ((a as u32 * weight_a + b as u32 * weight_b + total / 2) / total) as u8
+}
+
+/// The colors a block's indices pick from. DXT1 blocks whose first color isn't the bigger one
+/// have three colors plus transparent black, the others always have four colors.
+pub fn color_palette(color0: u16, color1: u16, punch_through: bool) -> [[u8; 4]; 4] {
+    let (c0, c1) = (unpack_565(color0), unpack_565(color1));
+    let three_colors = punch_through && color0 <= color1;
+    let mut palette = [c0, c1, [0, 0, 0, 255], [0, 0, 0, 255]];
+    for channel in 0..3 {
+        if three_colors {
+            palette[2][channel] = blend(c0[channel], c1[channel], 1, 1);
+        } else {
+            palette[2][channel] = blend(c0[channel], c1[channel], 2, 1);
+            palette[3][channel] = blend(c0[channel], c1[channel], 1, 2);
+        }
+    }
+    if three_colors {
+        palette[3][3] = 0;
+    }
+    palette
+}
+
+/// The values a single channel block's indices pick from. Like DXT1, the endpoints' order
+/// picks between eight levels or six plus 0 and 255.
+pub fn channel_palette(a0: u8, a1: u8) -> [u8; 8] {
+    let mut palette = [a0, a1, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for (i, value) in palette.iter_mut().enumerate().skip(2) {
+            *value = blend(a0, a1, 8 - i as u32, i as u32 - 1);
+        }
+    } else {
+        for (i, value) in palette.iter_mut().enumerate().skip(2).take(4) {
+            *value = blend(a0, a1, 6 - i as u32, i as u32 - 1);
+        }
+    }
+    palette
+}
+
+/// Decode the color half of a block.
+pub fn decode_color_block(block: &[u8], punch_through: bool, pixels: &mut Block) {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let palette = color_palette(color0, color1, punch_through);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        *pixel = palette[(indices >> (2 * i) & 0b11) as usize];
+    }
+}
+
+/// Decode the 4 bits of alpha per pixel of a DXT3 block.
+pub fn decode_explicit_alpha_block(block: &[u8], pixels: &mut Block) {
+    let mut alphas = [0; 8];
+    alphas.copy_from_slice(&block[..8]);
+    let alphas = u64::from_le_bytes(alphas);
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        pixel[3] = (alphas >> (4 * i) & 0xF) as u8 * 17;
+    }
+}
+
+/// Decode a single channel block, the alpha of DXT5 and each channel of BC4 and BC5.
+pub fn decode_channel_block(block: &[u8]) -> [u8; 16] {
+    let palette = channel_palette(block[0], block[1]);
+    let mut indices = [0; 8];
+    indices[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(indices);
+    let mut values = [0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[(indices >> (3 * i) & 0b111) as usize];
+    }
+    values
+}
+
+/// Decode a whole block of a compressed format. Single channel blocks come out gray, and two
+/// channel blocks red and green. BC6H and BC7 blocks can't be decoded.
+pub fn decode_block(format: BitmapFormat, block: &[u8], pixels: &mut Block) {
+    match format.linear() {
+        BitmapFormat::Dxt1 => decode_color_block(block, true, pixels),
+        BitmapFormat::Dxt3 => {
+            decode_color_block(&block[8..], false, pixels);
+            decode_explicit_alpha_block(block, pixels);
+        }
+        BitmapFormat::Dxt5 => {
+            decode_color_block(&block[8..], false, pixels);
+            for (pixel, alpha) in pixels.iter_mut().zip(&decode_channel_block(block)) {
+                pixel[3] = *alpha;
+            }
+        }
+        BitmapFormat::Bc4 => {
+            for (pixel, &value) in pixels.iter_mut().zip(&decode_channel_block(block)) {
+                *pixel = [value, value, value, 255];
+            }
+        }
+        BitmapFormat::Bc5 => {
+            let red = decode_channel_block(block);
+            let green = decode_channel_block(&block[8..]);
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                *pixel = [0, green[i], red[i], 255];
+            }
+        }
+        _ => unreachable!("{:?} blocks can't be decoded", format),
+    }
+}
+
+/// The pixels of a mip level in `format` as tightly packed BGRA. Gray levels become opaque
+/// gray, and sRGB levels stay sRGB encoded.
+pub fn decode_to_bgra(format: BitmapFormat, level: &MipLevel) -> io::Result<Vec<u8>> {
+    let (width, height) = (level.size().x() as usize, level.size().y() as usize);
+    let data = level.data();
+    let bytes_per_row = level.bytes_per_row();
+    let expected = format.level_size(width, height);
+    if data.len() < expected || bytes_per_row < format.bytes_per_row(width) {
+        return util::io_err(
+            ErrorKind::InvalidData,
+            format!(
+                "A {}x{} {:?} level needs {} bytes but only has {}",
+                width,
+                height,
+                format,
+                expected,
+                data.len()
+            ),
+        );
+    }
+
+    let mut bgra = vec![0; width * height * 4];
+    match format.linear() {
+        BitmapFormat::BgraU8 => {
+            for (row, out) in data.chunks(bytes_per_row).zip(bgra.chunks_mut(width * 4)) {
+                out.copy_from_slice(&row[..width * 4]);
+            }
+        }
+        BitmapFormat::GrayU8 => {
+            for (row, out) in data.chunks(bytes_per_row).zip(bgra.chunks_mut(width * 4)) {
+                for (&gray, pixel) in row.iter().zip(out.chunks_mut(4)) {
+                    pixel.copy_from_slice(&[gray, gray, gray, 255]);
+                }
+            }
+        }
+        BitmapFormat::Bc6h | BitmapFormat::Bc6hSigned | BitmapFormat::Bc7 => {
+            return util::io_err(
+                ErrorKind::InvalidInput,
+                format!("Decoding {:?} isn't supported", format),
+            );
+        }
+        _ => {
+            let block_bytes = format.block_bytes();
+            let mut pixels = [[0; 4]; 16];
+            for (block_y, row) in data
+                .chunks(bytes_per_row)
+                .take(block_count(height))
+                .enumerate()
+            {
+                for (block_x, block) in row.chunks(block_bytes).take(block_count(width)).enumerate()
+                {
+                    decode_block(format, block, &mut pixels);
+                    // Blocks hang off the edge of levels that aren't a multiple of 4
+                    for (i, pixel) in pixels.iter().enumerate() {
+                        let (x, y) = (block_x * 4 + i % 4, block_y * 4 + i / 4);
+                        if x < width && y < height {
+                            let out = (y * width + x) * 4;
+                            bgra[out..out + 4].copy_from_slice(pixel);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(bgra)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gfx::{Bitmap, BitmapReader};
+
+    const RED: u16 = 0xF800;
+    const BLUE: u16 = 0x001F;
+
+    fn color_block(color0: u16, color1: u16, indices: u32) -> Vec<u8> {
+        let mut block = Vec::new();
+        block.extend(&color0.to_le_bytes());
+        block.extend(&color1.to_le_bytes());
+        block.extend(&indices.to_le_bytes());
+        block
+    }
+
+    fn read(file: &str) -> Bitmap {
+        let mut bitmap = Bitmap::default();
+        BitmapReader::default()
+            .read_into(
+                &mut util::buf_open(format!("res/bitmaps/frigate/{}.dds", file)).unwrap(),
+                &mut bitmap,
+            )
+            .unwrap();
+        bitmap
+    }
+
+    fn pixel(bitmap: &Bitmap, (x, y): (usize, usize)) -> [u8; 4] {
+        let level = bitmap.mip_levels().next().unwrap();
+        let out = (y * level.size().x() as usize + x) * 4;
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&level.data()[out..out + 4]);
+        pixel
+    }
+
+    #[test]
+    fn decodes_four_color_blocks() {
+        let mut pixels = [[0; 4]; 16];
+        // Indices 0, 1, 2, 3 along each row
+        decode_color_block(&color_block(RED, BLUE, 0xE4E4_E4E4), true, &mut pixels);
+        assert_eq!(
+            [
+                [0, 0, 255, 255],
+                [255, 0, 0, 255],
+                [85, 0, 170, 255],
+                [170, 0, 85, 255]
+            ],
+            pixels[..4]
+        );
+        assert_eq!(pixels[..4], pixels[12..]);
+    }
+
+    #[test]
+    fn decodes_punch_through_alpha() {
+        let mut pixels = [[0; 4]; 16];
+        decode_color_block(&color_block(BLUE, RED, 0xE4E4_E4E4), true, &mut pixels);
+        assert_eq!(
+            [
+                [255, 0, 0, 255],
+                [0, 0, 255, 255],
+                [128, 0, 128, 255],
+                [0, 0, 0, 0]
+            ],
+            pixels[..4]
+        );
+        // DXT3 and DXT5 colors always have four
+        decode_color_block(&color_block(BLUE, RED, 0xE4E4_E4E4), false, &mut pixels);
+        assert_eq!([85, 0, 170, 255], pixels[3]);
+    }
+
+    #[test]
+    fn decodes_explicit_alpha() {
+        let mut pixels = [[0; 4]; 16];
+        let alphas = 0xFEDC_BA98_7654_3210u64.to_le_bytes();
+        decode_explicit_alpha_block(&alphas, &mut pixels);
+        for (i, pixel) in pixels.iter().enumerate() {
+            assert_eq!(i as u8 * 17, pixel[3]);
+        }
+    }
+
+    #[test]
+    fn decodes_interpolated_alpha() {
+        // Pixel i uses index i % 8
+        let indices: u64 = (0..16).map(|i| (i % 8) << (3 * i)).sum();
+        let mut block = vec![255, 0];
+        block.extend(&indices.to_le_bytes()[..6]);
+        let alphas = decode_channel_block(&block);
+        assert_eq!([255, 0, 219, 182, 146, 109, 73, 36], alphas[..8]);
+        assert_eq!(alphas[..8], alphas[8..]);
+
+        block[0] = 0;
+        block[1] = 255;
+        let alphas = decode_channel_block(&block);
+        assert_eq!([0, 255, 51, 102, 153, 204, 0, 255], alphas[..8]);
+    }
+
+    #[test]
+    fn decodes_channel_formats() {
+        let mut pixels = [[0; 4]; 16];
+        // Every pixel uses the first endpoint
+        decode_block(
+            BitmapFormat::Bc4,
+            &[200, 100, 0, 0, 0, 0, 0, 0],
+            &mut pixels,
+        );
+        assert!(pixels.iter().all(|&pixel| pixel == [200, 200, 200, 255]));
+        let block = [
+            10, 5, 0, 0, 0, 0, 0, 0, 20, 40, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        decode_block(BitmapFormat::Bc5, &block, &mut pixels);
+        assert!(pixels.iter().all(|&pixel| pixel == [0, 255, 10, 255]));
+    }
+
+    #[test]
+    fn crops_partial_blocks() {
+        let mut bitmap = Bitmap::new(
+            BitmapFormat::Dxt1,
+            2,
+            1,
+            color_block(RED, BLUE, 0xE4E4_E4E4),
+        );
+        let decoded = bitmap.decode_mip_level(0).unwrap();
+        assert_eq!(
+            &[0, 0, 255, 255, 255, 0, 0, 255],
+            decoded.mip_levels().next().unwrap().data()
+        );
+
+        bitmap = Bitmap::new(BitmapFormat::Dxt5, 4, 4, vec![0; 15]);
+        assert!(bitmap.decode_mip_level(0).is_err());
+        assert!(bitmap.decode_mip_level(1).is_err());
+    }
+
+    #[test]
+    fn decodes_frigate_textures() {
+        // Known pixels, as decoded by a separate reference decoder
+        for (file, format, pixels) in &[
+            (
+                "diffuse",
+                BitmapFormat::Dxt5,
+                [
+                    ((0, 0), [0, 0, 0, 255]),
+                    ((517, 300), [74, 73, 74, 255]),
+                    ((200, 611), [99, 101, 99, 255]),
+                ],
+            ),
+            (
+                "normal",
+                BitmapFormat::Dxt1,
+                [
+                    ((0, 0), [255, 127, 126, 255]),
+                    ((517, 300), [255, 128, 129, 255]),
+                    ((200, 611), [242, 130, 154, 255]),
+                ],
+            ),
+            (
+                "specular",
+                BitmapFormat::GrayU8,
+                [
+                    ((0, 0), [0, 0, 0, 255]),
+                    ((517, 300), [27, 27, 27, 255]),
+                    ((200, 611), [15, 15, 15, 255]),
+                ],
+            ),
+        ] {
+            let bitmap = read(file);
+            assert_eq!(*format, bitmap.format());
+            let decoded = bitmap.decode().unwrap();
+            assert_eq!(BitmapFormat::BgraU8, decoded.format());
+            assert_eq!(bitmap.mip_levels().count(), decoded.mip_levels().count());
+            for (original, level) in bitmap.mip_levels().zip(decoded.mip_levels()) {
+                assert_eq!(original.size(), level.size());
+                assert_eq!(
+                    (level.size().x() * level.size().y()) as usize * 4,
+                    level.data().len()
+                );
+            }
+            for (position, expected) in pixels {
+                assert_eq!(
+                    *expected,
+                    pixel(&decoded, *position),
+                    "{} {:?}",
+                    file,
+                    position
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,532 @@
+use crate::gfx::{block_count, channel_palette, color_palette, BitmapFormat, Block};
+
+/// How hard the encoder looks for the best endpoints of each block.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EncodeQuality {
+    /// Endpoints from the corners of each block's bounding box.
+    Fast,
+    /// Endpoints along each block's principal axis, and both single channel modes tried.
+    Normal,
+    /// `Normal` endpoints refined by least squares, and nearby single channel endpoints tried.
+    Best,
+}
+
+impl Default for EncodeQuality {
+    #[inline]
+    fn default() -> EncodeQuality {
+        EncodeQuality::Normal
+    }
+}
+
+/// Least squares refinement stops after this many rounds, or as soon as it stops helping.
+const REFINE_ITERATIONS: usize = 4;
+/// How far from the extremes `Best` looks for better single channel endpoints.
+const CHANNEL_SEARCH_RADIUS: i32 = 3;
+
+type Color = [f32; 3];
+
+/// Pack a BGR color into 5:6:5, rounding each channel.
+#[inline]
+fn pack_565(color: Color) -> u16 {
+    let quantize = |value: f32, max: f32| (value.clamp(0.0, 255.0) * max / 255.0).round() as u16;
+    quantize(color[2], 31.0) << 11 | quantize(color[1], 63.0) << 5 | quantize(color[0], 31.0)
+}
+
+#[inline]
+fn color_distance(a: [u8; 4], b: Color) -> f32 {
+    (0..3).map(|c| (a[c] as f32 - b[c]).powi(2)).sum()
+}
+
+/// Compress the colors of a block. DXT1 blocks with any alpha under 128 are encoded with three
+/// colors and transparent black for those pixels.
+pub fn encode_color_block(pixels: &Block, dxt1: bool, quality: EncodeQuality) -> [u8; 8] {
+    let transparent = dxt1 && pixels.iter().any(|pixel| pixel[3] < 128);
+    let opaque: Vec<Color> = pixels
+        .iter()
+        .filter(|pixel| !transparent || pixel[3] >= 128)
+        .map(|pixel| [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32])
+        .collect();
+    if opaque.is_empty() {
+        // Three color mode with every pixel transparent
+        return [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF];
+    }
+
+    let (start, end) = match quality {
+        EncodeQuality::Fast => bounding_box_endpoints(&opaque),
+        EncodeQuality::Normal | EncodeQuality::Best => principal_axis_endpoints(&opaque),
+    };
+    let mut best = fit_colors(pixels, dxt1, transparent, start, end);
+    if quality == EncodeQuality::Best {
+        for _ in 0..REFINE_ITERATIONS {
+            let (start, end) = match least_squares_endpoints(pixels, &best, transparent) {
+                Some(endpoints) => endpoints,
+                None => break,
+            };
+            let refined = fit_colors(pixels, dxt1, transparent, start, end);
+            if refined.error >= best.error {
+                break;
+            }
+            best = refined;
+        }
+    }
+
+    let mut block = [0; 8];
+    block[..2].copy_from_slice(&best.color0.to_le_bytes());
+    block[2..4].copy_from_slice(&best.color1.to_le_bytes());
+    block[4..].copy_from_slice(&best.indices.to_le_bytes());
+    block
+}
+
+#[derive(Debug)]
+struct ColorFit {
+    color0: u16,
+    color1: u16,
+    indices: u32,
+    error: f32,
+}
+
+/// Quantize the endpoints and pick the closest palette entry for every pixel.
+fn fit_colors(pixels: &Block, dxt1: bool, transparent: bool, start: Color, end: Color) -> ColorFit {
+    let (mut color0, mut color1) = (pack_565(start), pack_565(end));
+    // The order picks the mode: three colors when the first is no bigger
+    if (transparent && color0 > color1) || (!transparent && color0 < color1) {
+        std::mem::swap(&mut color0, &mut color1);
+    }
+    let palette = color_palette(color0, color1, dxt1);
+
+    let mut indices = 0;
+    let mut error = 0.0;
+    for (i, pixel) in pixels.iter().enumerate() {
+        let index = if transparent && pixel[3] < 128 {
+            3
+        } else {
+            let color = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+            let (index, distance) = palette
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry[3] == 255)
+                .map(|(index, entry)| (index, color_distance(*entry, color)))
+                .fold((0, f32::INFINITY), |best, candidate| {
+                    if candidate.1 < best.1 {
+                        candidate
+                    } else {
+                        best
+                    }
+                });
+            error += distance;
+            index
+        };
+        indices |= (index as u32) << (2 * i);
+    }
+    ColorFit {
+        color0,
+        color1,
+        indices,
+        error,
+    }
+}
+
+fn bounding_box_endpoints(colors: &[Color]) -> (Color, Color) {
+    let mut min = [f32::INFINITY; 3];
+    let mut max = [f32::NEG_INFINITY; 3];
+    for color in colors {
+        for c in 0..3 {
+            min[c] = min[c].min(color[c]);
+            max[c] = max[c].max(color[c]);
+        }
+    }
+    // Pull the corners in a little, since the extremes are rarely the best endpoints
+    for c in 0..3 {
+        let inset = (max[c] - min[c]) / 16.0;
+        min[c] += inset;
+        max[c] -= inset;
+    }
+    (max, min)
+}
+
+/// The extremes of the colors along the axis they vary most along.
+fn principal_axis_endpoints(colors: &[Color]) -> (Color, Color) {
+    let n = colors.len() as f32;
+    let mut mean = [0.0; 3];
+    for color in colors {
+        for c in 0..3 {
+            mean[c] += color[c] / n;
+        }
+    }
+    let mut covariance = [[0.0; 3]; 3];
+    for color in colors {
+        for i in 0..3 {
+            for j in 0..3 {
+                covariance[i][j] += (color[i] - mean[i]) * (color[j] - mean[j]);
+            }
+        }
+    }
+
+    // Power iteration, starting from the channel that varies most. The bounding box's diagonal
+    // would miss channels that go opposite ways.
+    let channel = (0..3)
+        .max_by(|&a, &b| covariance[a][a].partial_cmp(&covariance[b][b]).unwrap())
+        .unwrap();
+    let mut axis = covariance[channel];
+    if axis.iter().all(|&v| v == 0.0) {
+        axis = [1.0; 3];
+    }
+    for _ in 0..8 {
+        let mut next = [0.0; 3];
+        for (i, row) in covariance.iter().enumerate() {
+            next[i] = (0..3).map(|j| row[j] * axis[j]).sum();
+        }
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            break;
+        }
+        axis = [next[0] / length, next[1] / length, next[2] / length];
+    }
+
+    let (mut low, mut high) = (f32::INFINITY, f32::NEG_INFINITY);
+    for color in colors {
+        let t: f32 = (0..3).map(|c| (color[c] - mean[c]) * axis[c]).sum();
+        low = low.min(t);
+        high = high.max(t);
+    }
+    let along = |t: f32| {
+        [
+            mean[0] + axis[0] * t,
+            mean[1] + axis[1] * t,
+            mean[2] + axis[2] * t,
+        ]
+    };
+    (along(high), along(low))
+}
+
+/// The endpoints that best reproduce the pixels with the indices they were given.
+fn least_squares_endpoints(
+    pixels: &Block,
+    fit: &ColorFit,
+    transparent: bool,
+) -> Option<(Color, Color)> {
+    // How much of the first endpoint each index is
+    let weights: [f32; 4] = if transparent {
+        [1.0, 0.0, 0.5, 0.0]
+    } else {
+        [1.0, 0.0, 2.0 / 3.0, 1.0 / 3.0]
+    };
+    let (mut aa, mut ab, mut bb) = (0.0, 0.0, 0.0);
+    let (mut ax, mut bx) = ([0.0; 3], [0.0; 3]);
+    for (i, pixel) in pixels.iter().enumerate() {
+        let index = (fit.indices >> (2 * i) & 0b11) as usize;
+        if transparent && index == 3 {
+            continue;
+        }
+        let (a, b) = (weights[index], 1.0 - weights[index]);
+        aa += a * a;
+        ab += a * b;
+        bb += b * b;
+        for c in 0..3 {
+            ax[c] += a * pixel[c] as f32;
+            bx[c] += b * pixel[c] as f32;
+        }
+    }
+    let determinant = aa * bb - ab * ab;
+    if determinant.abs() < 1e-6 {
+        return None;
+    }
+    let mut start = [0.0; 3];
+    let mut end = [0.0; 3];
+    for c in 0..3 {
+        start[c] = (bb * ax[c] - ab * bx[c]) / determinant;
+        end[c] = (aa * bx[c] - ab * ax[c]) / determinant;
+    }
+    Some((start, end))
+}
+
+/// Compress one channel of a block, like DXT5 alpha or BC4.
+pub fn encode_channel_block(values: &[u8; 16], quality: EncodeQuality) -> [u8; 8] {
+    let min = *values.iter().min().unwrap();
+    let max = *values.iter().max().unwrap();
+    // Eight levels between the extremes
+    let mut best = fit_channel(values, max, min);
+
+    if quality != EncodeQuality::Fast {
+        // Six levels between the extremes that aren't 0 or 255, which are free
+        let inner = values.iter().filter(|&&value| value != 0 && value != 255);
+        if let (Some(&low), Some(&high)) = (inner.clone().min(), inner.max()) {
+            let fit = fit_channel(values, low, high);
+            if fit.1 < best.1 {
+                best = fit;
+            }
+        }
+    }
+    if quality == EncodeQuality::Best {
+        for d0 in 0..=CHANNEL_SEARCH_RADIUS {
+            for d1 in 0..=CHANNEL_SEARCH_RADIUS {
+                let a0 = (max as i32 - d0).max(0) as u8;
+                let a1 = (min as i32 + d1).min(255) as u8;
+                if a0 > a1 {
+                    let fit = fit_channel(values, a0, a1);
+                    if fit.1 < best.1 {
+                        best = fit;
+                    }
+                }
+            }
+        }
+    }
+    best.0
+}
+
+/// Pick the closest level for every value, returning the block and its squared error.
+fn fit_channel(values: &[u8; 16], a0: u8, a1: u8) -> ([u8; 8], u32) {
+    let palette = channel_palette(a0, a1);
+    let mut indices = 0u64;
+    let mut error = 0;
+    for (i, &value) in values.iter().enumerate() {
+        let (index, distance) = palette
+            .iter()
+            .enumerate()
+            .map(|(index, &level)| (index, (level as i32 - value as i32).pow(2) as u32))
+            .min_by_key(|&(_, distance)| distance)
+            .unwrap();
+        indices |= (index as u64) << (3 * i);
+        error += distance;
+    }
+    let mut block = [0; 8];
+    block[0] = a0;
+    block[1] = a1;
+    block[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+    (block, error)
+}
+
+/// Compress the 4 bit alpha of a DXT3 block.
+pub fn encode_explicit_alpha_block(pixels: &Block) -> [u8; 8] {
+    let alphas = pixels
+        .iter()
+        .enumerate()
+        .map(|(i, pixel)| ((pixel[3] as u64 + 8) / 17) << (4 * i))
+        .sum::<u64>();
+    alphas.to_le_bytes()
+}
+
+/// Compress a whole block, in the channels each format keeps. BC4 keeps red, and BC5 red and
+/// green. BC6H and BC7 blocks can't be encoded.
+pub fn encode_block(format: BitmapFormat, pixels: &Block, quality: EncodeQuality) -> Vec<u8> {
+    let channel = |c: usize| {
+        let mut values = [0; 16];
+        for (value, pixel) in values.iter_mut().zip(pixels) {
+            *value = pixel[c];
+        }
+        encode_channel_block(&values, quality)
+    };
+    match format.linear() {
+        BitmapFormat::Dxt1 => encode_color_block(pixels, true, quality).to_vec(),
+        BitmapFormat::Dxt3 => [
+            encode_explicit_alpha_block(pixels),
+            encode_color_block(pixels, false, quality),
+        ]
+        .concat(),
+        BitmapFormat::Dxt5 => [channel(3), encode_color_block(pixels, false, quality)].concat(),
+        BitmapFormat::Bc4 => channel(2).to_vec(),
+        BitmapFormat::Bc5 => [channel(2), channel(1)].concat(),
+        _ => unreachable!("{:?} blocks can't be encoded", format),
+    }
+}
+
+/// Compress a `width` x `height` level of tightly packed BGRA pixels. Blocks hanging off the
+/// edge repeat the edge pixels.
+pub fn encode_level(
+    bgra: &[u8],
+    width: usize,
+    height: usize,
+    format: BitmapFormat,
+    quality: EncodeQuality,
+) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(format.level_size(width, height));
+    let mut pixels = [[0; 4]; 16];
+    for block_y in 0..block_count(height) {
+        for block_x in 0..block_count(width) {
+            for (i, pixel) in pixels.iter_mut().enumerate() {
+                let x = (block_x * 4 + i % 4).min(width - 1);
+                let y = (block_y * 4 + i / 4).min(height - 1);
+                let at = (y * width + x) * 4;
+                pixel.copy_from_slice(&bgra[at..at + 4]);
+            }
+            encoded.extend(encode_block(format, &pixels, quality));
+        }
+    }
+    encoded
+}
+
+/// The peak signal to noise ratio, in decibels, between two sets of 8 bit values. Identical
+/// values are infinitely far above the noise.
+pub fn psnr<I: Iterator<Item = (u8, u8)>>(values: I) -> f32 {
+    let (mut squared_error, mut count) = (0.0f64, 0usize);
+    for (a, b) in values {
+        squared_error += (a as f64 - b as f64).powi(2);
+        count += 1;
+    }
+    if squared_error == 0.0 {
+        return f32::INFINITY;
+    }
+    let mean = squared_error / count as f64;
+    (10.0 * (255.0 * 255.0 / mean).log10()) as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        gfx::{decode_block, Bitmap, BitmapReader},
+        util,
+    };
+
+    const QUALITIES: [EncodeQuality; 3] = [
+        EncodeQuality::Fast,
+        EncodeQuality::Normal,
+        EncodeQuality::Best,
+    ];
+
+    fn read(file: &str, mip_level: usize) -> Bitmap {
+        let mut bitmap = Bitmap::default();
+        BitmapReader::default()
+            .read_into(
+                &mut util::buf_open(format!("res/bitmaps/frigate/{}.dds", file)).unwrap(),
+                &mut bitmap,
+            )
+            .unwrap();
+        bitmap.decode_mip_level(mip_level).unwrap()
+    }
+
+    fn round_trip(format: BitmapFormat, pixels: &Block, quality: EncodeQuality) -> Block {
+        let mut decoded = [[0; 4]; 16];
+        decode_block(format, &encode_block(format, pixels, quality), &mut decoded);
+        decoded
+    }
+
+    #[test]
+    fn encodes_exact_colors_exactly() {
+        // Both 5:6:5 colors and their blend are representable
+        let mut pixels = [[0, 0, 255, 255]; 16];
+        for pixel in pixels.iter_mut().skip(8) {
+            *pixel = [255, 0, 0, 255];
+        }
+        // Fast pulls the endpoints in, so only the others hit the colors exactly
+        for &quality in &QUALITIES[1..] {
+            for &format in &[BitmapFormat::Dxt1, BitmapFormat::Dxt3, BitmapFormat::Dxt5] {
+                assert_eq!(
+                    pixels,
+                    round_trip(format, &pixels, quality),
+                    "{:?}",
+                    quality
+                );
+            }
+        }
+        let flat = [[8, 12, 16, 255]; 16];
+        assert_eq!(
+            flat,
+            round_trip(BitmapFormat::Dxt1, &flat, EncodeQuality::Normal)
+        );
+    }
+
+    #[test]
+    fn encodes_punch_through_alpha() {
+        let mut pixels = [[200, 100, 50, 255]; 16];
+        pixels[5][3] = 0;
+        pixels[6][3] = 100;
+        let decoded = round_trip(BitmapFormat::Dxt1, &pixels, EncodeQuality::Normal);
+        for (i, pixel) in decoded.iter().enumerate() {
+            if i == 5 || i == 6 {
+                assert_eq!([0, 0, 0, 0], *pixel);
+            } else {
+                assert_eq!(255, pixel[3]);
+            }
+        }
+        let invisible = [[1, 2, 3, 0]; 16];
+        assert_eq!(
+            [[0; 4]; 16],
+            round_trip(BitmapFormat::Dxt1, &invisible, EncodeQuality::Fast)
+        );
+    }
+
+    #[test]
+    fn encodes_alpha() {
+        let mut pixels = [[0; 4]; 16];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            pixel[3] = i as u8 * 17;
+        }
+        let explicit = round_trip(BitmapFormat::Dxt3, &pixels, EncodeQuality::Fast);
+        assert_eq!(pixels, explicit);
+
+        // A ramp through all 16 values only has 8 levels, so can't be exact, but is close
+        let interpolated = round_trip(BitmapFormat::Dxt5, &pixels, EncodeQuality::Best);
+        for (pixel, decoded) in pixels.iter().zip(&interpolated) {
+            assert!((pixel[3] as i32 - decoded[3] as i32).abs() <= 19);
+        }
+        // 0 and 255 come for free in the six level mode
+        let mut values = [128; 16];
+        values[0] = 0;
+        values[1] = 255;
+        let block = encode_channel_block(&values, EncodeQuality::Normal);
+        assert!(block[0] <= block[1]);
+        assert_eq!(0, fit_channel(&values, block[0], block[1]).1);
+        let fast = encode_channel_block(&values, EncodeQuality::Fast);
+        assert!(fit_channel(&values, fast[0], fast[1]).1 > 0);
+    }
+
+    #[test]
+    fn better_quality_is_no_worse() {
+        // A noisy gradient that doesn't lie on a line
+        let mut pixels = [[0; 4]; 16];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            let t = i as u8 * 15;
+            *pixel = [t, 255 - t, ((i * 97) % 251) as u8, 255];
+        }
+        let error = |quality| {
+            let decoded = round_trip(BitmapFormat::Dxt1, &pixels, quality);
+            psnr(
+                pixels
+                    .iter()
+                    .zip(&decoded)
+                    .flat_map(|(a, b)| (0..3).map(move |c| (a[c], b[c]))),
+            )
+        };
+        let (fast, normal, best) = (
+            error(EncodeQuality::Fast),
+            error(EncodeQuality::Normal),
+            error(EncodeQuality::Best),
+        );
+        assert!(best >= normal, "{} {}", best, normal);
+        assert!(best >= fast, "{} {}", best, fast);
+    }
+
+    #[test]
+    fn measures_psnr() {
+        assert_eq!(f32::INFINITY, psnr([(1, 1), (2, 2)].iter().copied()));
+        // A mean squared error of 1
+        let psnr = psnr([(0, 1), (5, 4)].iter().copied());
+        assert!((psnr - 48.1308).abs() < 1e-3);
+    }
+
+    #[test]
+    fn compresses_frigate_textures() {
+        for &(file, format, quality, min_psnr) in &[
+            // The diffuse map was DXT5 to begin with, so it can come back almost exactly
+            ("diffuse", BitmapFormat::Dxt1, EncodeQuality::Fast, 32.0),
+            ("diffuse", BitmapFormat::Dxt1, EncodeQuality::Best, 50.0),
+            ("diffuse", BitmapFormat::Dxt5, EncodeQuality::Normal, 50.0),
+            ("normal", BitmapFormat::Bc5, EncodeQuality::Normal, 45.0),
+            ("specular", BitmapFormat::Bc4, EncodeQuality::Normal, 38.0),
+        ] {
+            let original = read(file, 3);
+            let encoded = original.encode(format, quality).unwrap();
+            assert_eq!(format, encoded.format());
+            let psnr = original.psnr(&encoded).unwrap();
+            assert!(
+                psnr > min_psnr,
+                "{} {:?} {:?} {}",
+                file,
+                format,
+                quality,
+                psnr
+            );
+        }
+    }
+}
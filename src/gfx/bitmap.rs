@@ -1,21 +1,116 @@
 use std::{
-    io::{self, ErrorKind, Read, Seek, SeekFrom},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
     str, u32,
 };
 
-use crate::{math::Vector2, util};
+use crate::{
+    gfx::{bcn, bcn_encoder, mipmap, ColorSpace, EncodeQuality, MipFilter},
+    math::Vector2,
+    util,
+};
 use std::slice::Iter;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum BitmapFormat {
     BgraU8,
+    BgraU8Srgb,
     GrayU8,
     Dxt1,
+    Dxt1Srgb,
     Dxt3,
+    Dxt3Srgb,
     Dxt5,
+    Dxt5Srgb,
+    /// One channel compressed like DXT5 alpha.
+    Bc4,
+    /// Two channels, red and green, each compressed like BC4.
+    Bc5,
+    /// Unsigned half float RGB.
+    Bc6h,
+    /// Signed half float RGB.
+    Bc6hSigned,
+    /// RGBA with a choice of partitions and precision per block.
+    Bc7,
+    Bc7Srgb,
 }
 
-impl BitmapFormat {}
+impl BitmapFormat {
+    /// The format laid out the same way but with colors stored as they are rather than sRGB
+    /// encoded.
+    #[inline]
+    pub fn linear(self) -> BitmapFormat {
+        match self {
+            BitmapFormat::BgraU8Srgb => BitmapFormat::BgraU8,
+            BitmapFormat::Dxt1Srgb => BitmapFormat::Dxt1,
+            BitmapFormat::Dxt3Srgb => BitmapFormat::Dxt3,
+            BitmapFormat::Dxt5Srgb => BitmapFormat::Dxt5,
+            BitmapFormat::Bc7Srgb => BitmapFormat::Bc7,
+            format => format,
+        }
+    }
+
+    /// Whether colors are sRGB encoded, and should be decoded by the sampler.
+    #[inline]
+    pub fn is_srgb(self) -> bool {
+        self.linear() != self
+    }
+
+    /// Whether pixels are stored in 4x4 blocks.
+    #[inline]
+    pub fn is_compressed(self) -> bool {
+        !matches!(self.linear(), BitmapFormat::BgraU8 | BitmapFormat::GrayU8)
+    }
+
+    /// The bytes in a pixel, or in a 4x4 block of pixels when compressed.
+    #[inline]
+    pub fn block_bytes(self) -> usize {
+        match self.linear() {
+            BitmapFormat::BgraU8 => 4,
+            BitmapFormat::GrayU8 => 1,
+            BitmapFormat::Dxt1 | BitmapFormat::Bc4 => 8,
+            _ => 16,
+        }
+    }
+
+    /// The bytes in a row of pixels (or of blocks when compressed) `width` pixels wide.
+    #[inline]
+    pub fn bytes_per_row(self, width: usize) -> usize {
+        if self.is_compressed() {
+            block_count(width) * self.block_bytes()
+        } else {
+            width * self.block_bytes()
+        }
+    }
+
+    /// `level_size`, or `None` if it doesn't fit in a `usize`.
+    #[inline]
+    fn checked_level_size(self, width: usize, height: usize) -> Option<usize> {
+        let (columns, rows) = if self.is_compressed() {
+            (block_count(width), block_count(height))
+        } else {
+            (width, height)
+        };
+        columns.checked_mul(rows)?.checked_mul(self.block_bytes())
+    }
+
+    /// The bytes in a `width` x `height` mip level.
+    #[inline]
+    pub fn level_size(self, width: usize, height: usize) -> usize {
+        let rows = if self.is_compressed() {
+            block_count(height)
+        } else {
+            height
+        };
+        self.bytes_per_row(width) * rows
+    }
+}
+
+/// How many 4x4 blocks it takes to cover `size` pixels. Levels smaller than a block still
+/// take a whole one.
+#[inline]
+pub fn block_count(size: usize) -> usize {
+    (size / 4 + (size % 4 != 0) as usize).max(1)
+}
 
 impl Default for BitmapFormat {
     #[inline]
@@ -24,7 +119,7 @@ impl Default for BitmapFormat {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, PartialEq)]
 struct RawMipLevel {
     start: usize,
     end: usize,
@@ -56,28 +151,281 @@ impl<'a> MipLevel<'a> {
     }
 }
 
-#[derive(Debug, Default)]
+/// Pixels in some format, as one or more layers each with a chain of mip levels.
+///
+/// Cubemaps store their faces as layers, six per cube in +X, -X, +Y, -Y, +Z, -Z order.
+#[derive(Debug, Default, PartialEq)]
 pub struct Bitmap {
     format: BitmapFormat,
     data: Vec<u8>,
-    mip_levels: Vec<RawMipLevel>,
+    layers: Vec<Vec<RawMipLevel>>,
+    cubemap: bool,
 }
 
 impl Bitmap {
+    /// A bitmap with a single `width` x `height` level of pixels in `format`.
+    #[inline]
+    pub fn new(format: BitmapFormat, width: usize, height: usize, data: Vec<u8>) -> Bitmap {
+        let mut bitmap = Bitmap {
+            format,
+            ..Bitmap::default()
+        };
+        bitmap.push_mip_level(width, height, &data);
+        bitmap
+    }
+
+    /// Add a `width` x `height` level after the existing ones of the last layer. The data is in
+    /// the bitmap's format.
+    pub fn push_mip_level(&mut self, width: usize, height: usize, data: &[u8]) {
+        let start = self.data.len();
+        self.data.extend_from_slice(data);
+        self.push_level_range(start, width, height);
+    }
+
+    /// Add a `width` x `height` level made of the data from `start` to the end.
+    fn push_level_range(&mut self, start: usize, width: usize, height: usize) {
+        if self.layers.is_empty() {
+            self.layers.push(Vec::new());
+        }
+        let bytes_per_row = self.format.bytes_per_row(width);
+        self.layers.last_mut().unwrap().push(RawMipLevel {
+            start,
+            end: self.data.len(),
+            size: (width as f32, height as f32).into(),
+            bytes_per_row,
+        });
+    }
+
+    /// Start another layer, which the levels pushed after it go into.
+    #[inline]
+    pub fn push_layer(&mut self) {
+        self.layers.push(Vec::new());
+    }
+
+    /// Mark the layers as the faces of one or more cubes.
+    #[inline]
+    pub fn set_cubemap(&mut self, cubemap: bool) {
+        self.cubemap = cubemap;
+    }
+
+    /// Replace every mip level after the first with a full chain down to 1x1, each filtered
+    /// from the one above. Odd sizes are halved rounding down.
+    ///
+    /// Only `BgraU8` and `GrayU8` bitmaps, sRGB or not, can be filtered. Every layer gets its
+    /// own chain.
+    pub fn generate_mip_levels(
+        &mut self,
+        filter: MipFilter,
+        color_space: ColorSpace,
+    ) -> io::Result<()> {
+        let channels = match self.format.linear() {
+            BitmapFormat::BgraU8 => 4,
+            BitmapFormat::GrayU8 => 1,
+            format => {
+                return util::io_err(
+                    ErrorKind::InvalidInput,
+                    format!("Can't generate mip levels for {:?} bitmaps", format),
+                )
+            }
+        };
+        if self.layers.is_empty() || self.layers.iter().any(Vec::is_empty) {
+            return util::io_err(
+                ErrorKind::InvalidInput,
+                "The bitmap has no mip level to generate the others from",
+            );
+        }
+
+        let mut generated = Bitmap {
+            format: self.format,
+            cubemap: self.cubemap,
+            ..Bitmap::default()
+        };
+        for index in 0..self.layer_count() {
+            if index > 0 {
+                generated.push_layer();
+            }
+            let level = self.layer(index).next().unwrap();
+            let (mut width, mut height) = (level.size().x() as usize, level.size().y() as usize);
+            let mut pixels = mipmap::decode_pixels(
+                level.data(),
+                width,
+                height,
+                level.bytes_per_row(),
+                channels,
+                color_space,
+            );
+            generated.push_mip_level(width, height, level.data());
+            while width > 1 || height > 1 {
+                let size = ((width / 2).max(1), (height / 2).max(1));
+                // Keep filtering the unrounded values so errors don't build up down the chain
+                pixels = mipmap::downsample(&pixels, (width, height), size, channels, filter);
+                let data = mipmap::encode_pixels(&pixels, channels, color_space);
+                generated.push_mip_level(size.0, size.1, &data);
+                width = size.0;
+                height = size.1;
+            }
+        }
+        *self = generated;
+        Ok(())
+    }
+
+    /// The uncompressed format this bitmap decodes to, `BgraU8` or `BgraU8Srgb`.
+    #[inline]
+    fn decoded_format(&self) -> BitmapFormat {
+        if self.format.is_srgb() {
+            BitmapFormat::BgraU8Srgb
+        } else {
+            BitmapFormat::BgraU8
+        }
+    }
+
+    /// A BGRA copy of one mip level of the first layer, decompressed if need be.
+    pub fn decode_mip_level(&self, index: usize) -> io::Result<Bitmap> {
+        let level = util::io_err_option(
+            self.mip_levels().nth(index),
+            ErrorKind::InvalidInput,
+            || format!("The bitmap has no mip level {}", index),
+        )?;
+        let size = level.size();
+        Ok(Bitmap::new(
+            self.decoded_format(),
+            size.x() as usize,
+            size.y() as usize,
+            bcn::decode_to_bgra(self.format, &level)?,
+        ))
+    }
+
+    /// A BGRA copy of every layer and mip level, decompressed if need be. sRGB bitmaps stay
+    /// sRGB.
+    pub fn decode(&self) -> io::Result<Bitmap> {
+        let mut decoded = Bitmap {
+            format: self.decoded_format(),
+            cubemap: self.cubemap,
+            ..Bitmap::default()
+        };
+        for index in 0..self.layer_count() {
+            decoded.push_layer();
+            for level in self.layer(index) {
+                let size = level.size();
+                decoded.push_mip_level(
+                    size.x() as usize,
+                    size.y() as usize,
+                    &bcn::decode_to_bgra(self.format, &level)?,
+                );
+            }
+        }
+        Ok(decoded)
+    }
+
+    /// Compress every layer and mip level of a `BgraU8` or `BgraU8Srgb` bitmap into a block
+    /// compressed `format`. BC6H and BC7 can't be encoded yet.
+    pub fn encode(&self, format: BitmapFormat, quality: EncodeQuality) -> io::Result<Bitmap> {
+        if self.format.linear() != BitmapFormat::BgraU8 {
+            return util::io_err(
+                ErrorKind::InvalidInput,
+                format!("Only BGRA bitmaps can be encoded, not {:?}", self.format),
+            );
+        }
+        if !format.is_compressed() {
+            return util::io_err(
+                ErrorKind::InvalidInput,
+                format!("{:?} isn't a block compressed format", format),
+            );
+        }
+        if matches!(
+            format.linear(),
+            BitmapFormat::Bc6h | BitmapFormat::Bc6hSigned | BitmapFormat::Bc7
+        ) {
+            return util::io_err(
+                ErrorKind::InvalidInput,
+                format!("Encoding {:?} isn't supported", format),
+            );
+        }
+
+        let mut encoded = Bitmap {
+            format,
+            cubemap: self.cubemap,
+            ..Bitmap::default()
+        };
+        for index in 0..self.layer_count() {
+            encoded.push_layer();
+            for level in self.layer(index) {
+                let (width, height) = (level.size().x() as usize, level.size().y() as usize);
+                let bgra = bcn::decode_to_bgra(self.format, &level)?;
+                encoded.push_mip_level(
+                    width,
+                    height,
+                    &bcn_encoder::encode_level(&bgra, width, height, format, quality),
+                );
+            }
+        }
+        Ok(encoded)
+    }
+
+    /// How close `other` is to `self`, as the peak signal to noise ratio in decibels over
+    /// every layer and mip level. Only the channels `other`'s format keeps are compared: red
+    /// for gray and BC4, red and green for BC5.
+    pub fn psnr(&self, other: &Bitmap) -> io::Result<f32> {
+        let channels: &[usize] = match other.format {
+            BitmapFormat::GrayU8 | BitmapFormat::Bc4 => &[2],
+            BitmapFormat::Bc5 => &[1, 2],
+            _ => &[0, 1, 2, 3],
+        };
+        let (original, compared) = (self.decode()?, other.decode()?);
+        let sizes = |bitmap: &Bitmap| -> Vec<Vec<Vector2>> {
+            bitmap
+                .layers
+                .iter()
+                .map(|layer| layer.iter().map(|level| level.size).collect())
+                .collect()
+        };
+        if sizes(&original) != sizes(&compared) {
+            return util::io_err(
+                ErrorKind::InvalidInput,
+                "Only bitmaps with the same layers and mip level sizes can be compared",
+            );
+        }
+        Ok(bcn_encoder::psnr(
+            original
+                .data
+                .chunks(4)
+                .zip(compared.data.chunks(4))
+                .flat_map(|(a, b)| channels.iter().map(move |&c| (a[c], b[c]))),
+        ))
+    }
+
     pub fn clear(&mut self) {
         self.data.clear();
-        self.mip_levels.clear();
+        self.layers.clear();
         self.format = BitmapFormat::default();
+        self.cubemap = false;
     }
 
+    /// The mip levels of the first layer.
     #[inline]
     pub fn mip_levels(&self) -> MipLevelIterator {
+        self.layer(0)
+    }
+
+    /// The mip levels of one layer, or nothing if there's no such layer.
+    #[inline]
+    pub fn layer(&self, index: usize) -> MipLevelIterator {
         MipLevelIterator {
-            inner: self.mip_levels.iter(),
+            inner: self.layers.get(index).map_or(&[][..], Vec::as_slice).iter(),
             data: &self.data,
         }
     }
 
+    #[inline]
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    #[inline]
+    pub fn is_cubemap(&self) -> bool {
+        self.cubemap
+    }
+
     #[inline]
     pub fn format(&self) -> BitmapFormat {
         self.format
@@ -102,9 +450,23 @@ impl<'a> Iterator for MipLevelIterator<'a> {
     }
 }
 
+bitflags::bitflags! {
+    struct HeaderFlags: u32 {
+        const CAPS = 0x00000001;
+        const HEIGHT = 0x00000002;
+        const WIDTH = 0x00000004;
+        const PITCH = 0x00000008;
+        const PIXEL_FORMAT = 0x00001000;
+        const MIPMAP_COUNT = 0x00020000;
+        const LINEAR_SIZE = 0x00080000;
+    }
+}
+
 bitflags::bitflags! {
     struct PixelFormatFlags: u32 {
         const ALPHA_PIXELS = 0x00000001;
+        /// Only alpha, in the alpha mask.
+        const ALPHA = 0x00000002;
         const FOUR_CHARACTER_CODE = 0x00000004;
         const RGB = 0x00000040;
         const LUMINANCE = 0x00020000;
@@ -119,6 +481,113 @@ bitflags::bitflags! {
     }
 }
 
+bitflags::bitflags! {
+    struct CapabilityFlags2: u32 {
+        const CUBEMAP = 0x00000200;
+        /// Each of the six faces, which are all there in any cubemap we read or write.
+        const ALL_FACES = 0x0000FC00;
+        const VOLUME = 0x00200000;
+    }
+}
+
+const DX10_TEXTURE_1D: u32 = 2;
+const DX10_TEXTURE_2D: u32 = 3;
+const DX10_TEXTURE_CUBE: u32 = 0x4;
+
+/// The DXGI format each bitmap format is stored as in DX10 headers.
+const DXGI_FORMATS: [(BitmapFormat, u32); 15] = [
+    (BitmapFormat::BgraU8, 87),
+    (BitmapFormat::BgraU8Srgb, 91),
+    (BitmapFormat::GrayU8, 61),
+    (BitmapFormat::Dxt1, 71),
+    (BitmapFormat::Dxt1Srgb, 72),
+    (BitmapFormat::Dxt3, 74),
+    (BitmapFormat::Dxt3Srgb, 75),
+    (BitmapFormat::Dxt5, 77),
+    (BitmapFormat::Dxt5Srgb, 78),
+    (BitmapFormat::Bc4, 80),
+    (BitmapFormat::Bc5, 83),
+    (BitmapFormat::Bc6h, 95),
+    (BitmapFormat::Bc6hSigned, 96),
+    (BitmapFormat::Bc7, 98),
+    (BitmapFormat::Bc7Srgb, 99),
+];
+
+/// Where a channel is in an uncompressed DDS pixel, from its bit mask.
+#[derive(Debug, Default, Copy, Clone)]
+struct ChannelMask {
+    mask: u32,
+    shift: u32,
+    max: u32,
+}
+
+impl ChannelMask {
+    /// A channel for `mask`, unless its bits aren't all together.
+    fn new(mask: u32) -> Option<ChannelMask> {
+        let shift = if mask == 0 { 0 } else { mask.trailing_zeros() };
+        let max = mask >> shift;
+        if max & max.wrapping_add(1) != 0 {
+            return None;
+        }
+        Some(ChannelMask { mask, shift, max })
+    }
+
+    /// The channel of `pixel` scaled to a byte, or `missing` when there's no such channel.
+    #[inline]
+    fn get(self, pixel: u32, missing: u8) -> u8 {
+        if self.mask == 0 {
+            missing
+        } else {
+            let value = ((pixel & self.mask) >> self.shift) as u64;
+            ((value * 255 + self.max as u64 / 2) / self.max as u64) as u8
+        }
+    }
+}
+
+/// An uncompressed DDS pixel layout, like R5G6B5 or A8L8, that isn't one of the bitmap
+/// formats.
+#[derive(Debug)]
+struct MaskedLayout {
+    bytes_per_pixel: usize,
+    /// Blue, green, red and alpha. Luminance is in all three colors.
+    channels: [ChannelMask; 4],
+}
+
+impl MaskedLayout {
+    /// Convert tightly packed `pixels` to `format`, which is `BgraU8` or `GrayU8`. Missing
+    /// colors are black and missing alpha opaque.
+    fn convert(&self, pixels: &[u8], format: BitmapFormat, out: &mut Vec<u8>) {
+        for pixel in pixels.chunks(self.bytes_per_pixel) {
+            let mut bytes = [0; 4];
+            bytes[..pixel.len()].copy_from_slice(pixel);
+            let pixel = u32::from_le_bytes(bytes);
+            if format == BitmapFormat::GrayU8 {
+                out.push(self.channels[2].get(pixel, 0));
+            } else {
+                for (i, channel) in self.channels.iter().enumerate() {
+                    out.push(channel.get(pixel, if i == 3 { 255 } else { 0 }));
+                }
+            }
+        }
+    }
+}
+
+/// Append `len` bytes from `reader` to `data`. It only grows as bytes arrive, so a header
+/// that claims more than the file has can't ask for lots of memory.
+fn read_level<R: Read>(reader: &mut R, len: usize, data: &mut Vec<u8>) -> io::Result<()> {
+    let read = reader.by_ref().take(len as u64).read_to_end(data)?;
+    if read != len {
+        return util::io_err(
+            ErrorKind::UnexpectedEof,
+            format!(
+                "Expected {} bytes of pixels but the file ends after {}",
+                len, read
+            ),
+        );
+    }
+    Ok(())
+}
+
 #[derive(Debug, Default)]
 pub struct BitmapReader {}
 
@@ -145,8 +614,8 @@ impl BitmapReader {
         reader.seek(SeekFrom::Start(0x0C))?;
         let height = util::read_u32(reader)?;
         let width = util::read_u32(reader)?;
-        let pitch = util::read_u32(reader)?;
-        reader.seek(SeekFrom::Current(0x04))?;
+        // Skip the pitch, it's implied by the format and width
+        reader.seek(SeekFrom::Current(0x08))?;
         let mip_levels = util::read_u32(reader)?;
 
         reader.seek(SeekFrom::Start(0x50))?;
@@ -166,13 +635,11 @@ impl BitmapReader {
             str::from_utf8(&four_character_code_bytes),
             ErrorKind::InvalidData,
         )?;
-        // TODO: Don't I want to use these bitmasks for something?
-        //   It looks like I could support non 32-bit RGB formats
         let rgb_bit_counts = util::read_u32(reader)?;
-        let _r_bit_mask = util::read_u32(reader)?.to_le_bytes();
-        let _g_bit_mask = util::read_u32(reader)?.to_le_bytes();
-        let _b_bit_mask = util::read_u32(reader)?.to_le_bytes();
-        let _a_bit_mask = util::read_u32(reader)?.to_le_bytes();
+        let r_bit_mask = util::read_u32(reader)?;
+        let g_bit_mask = util::read_u32(reader)?;
+        let b_bit_mask = util::read_u32(reader)?;
+        let a_bit_mask = util::read_u32(reader)?;
         let capabilities_bytes = util::read_u32(reader)?;
         util::io_err_option(
             CapabilityFlags::from_bits(capabilities_bytes),
@@ -184,23 +651,58 @@ impl BitmapReader {
                 )
             },
         )?;
+        let capabilities2 = CapabilityFlags2::from_bits_truncate(util::read_u32(reader)?);
+        if capabilities2.contains(CapabilityFlags2::VOLUME) {
+            return util::io_err(
+                ErrorKind::InvalidData,
+                "Volume DDS textures are not supported",
+            );
+        }
+        let mut cubemap = capabilities2.contains(CapabilityFlags2::CUBEMAP);
+        if cubemap && !capabilities2.contains(CapabilityFlags2::ALL_FACES) {
+            return util::io_err(
+                ErrorKind::InvalidData,
+                "Cubemaps without all six faces are not supported",
+            );
+        }
+        let mut layers = 1;
+        let mut masked = None;
 
-        // Jump to pixel data (it is further down on FourCharacterCode == "DX11" but we dont do it)
-        reader.seek(SeekFrom::Start(0x70))?;
-        if format_flags.contains(PixelFormatFlags::FOUR_CHARACTER_CODE) {
-            let block_size;
+        // Jump to pixel data, past the magic and the 124 byte header
+        reader.seek(SeekFrom::Start(0x80))?;
+        let format = if format_flags.contains(PixelFormatFlags::FOUR_CHARACTER_CODE) {
             match four_character_code {
-                "DXT1" => {
-                    bitmap.format = BitmapFormat::Dxt1;
-                    block_size = 8;
-                }
-                "DXT3" => {
-                    bitmap.format = BitmapFormat::Dxt3;
-                    block_size = 16;
-                }
-                "DXT5" => {
-                    bitmap.format = BitmapFormat::Dxt5;
-                    block_size = 16;
+                "DXT1" => BitmapFormat::Dxt1,
+                "DXT3" => BitmapFormat::Dxt3,
+                "DXT5" => BitmapFormat::Dxt5,
+                "ATI1" | "BC4U" => BitmapFormat::Bc4,
+                "ATI2" | "BC5U" => BitmapFormat::Bc5,
+                // The pixel data is past an extended header that has the real format
+                "DX10" => {
+                    let dxgi_format = util::read_u32(reader)?;
+                    let dimension = util::read_u32(reader)?;
+                    let misc_flags = util::read_u32(reader)?;
+                    layers = util::read_u32(reader)?.max(1);
+                    // The alpha mode, which we don't need
+                    util::read_u32(reader)?;
+                    if dimension != DX10_TEXTURE_1D && dimension != DX10_TEXTURE_2D {
+                        return util::io_err(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "Only 1D and 2D DDS textures are supported, not dimension {}",
+                                dimension
+                            ),
+                        );
+                    }
+                    cubemap = misc_flags & DX10_TEXTURE_CUBE != 0;
+                    util::io_err_option(
+                        DXGI_FORMATS
+                            .iter()
+                            .find(|(_, dxgi)| *dxgi == dxgi_format)
+                            .map(|(format, _)| *format),
+                        ErrorKind::InvalidData,
+                        || format!("Unsupported DXGI format: {}", dxgi_format),
+                    )?
                 }
                 _ => {
                     return util::io_err(
@@ -209,83 +711,780 @@ impl BitmapReader {
                     );
                 }
             }
-            let mut offset = 0;
-            for mip_level in 0..mip_levels {
-                let mip_width = (width >> mip_level) as usize;
-                let mip_height = (height >> mip_level) as usize;
-                let mip_pitch = ((mip_width + 3) / 4).max(1) * block_size;
-                // This *should* also be the same as pitch at mip_level==0
-                let linear_size = mip_pitch * ((mip_height + 3) / 4).max(1);
-                bitmap.data.reserve(linear_size);
-                for _ in 0..linear_size {
-                    bitmap.data.push(util::read_u8(reader)?);
-                }
-                bitmap.mip_levels.push(RawMipLevel {
-                    start: offset,
-                    end: offset + linear_size,
-                    size: (mip_width as f32, mip_height as f32).into(),
-                    bytes_per_row: mip_pitch,
-                });
-                offset += linear_size;
-            }
-        } else if format_flags.contains(PixelFormatFlags::LUMINANCE) {
-            if format_flags.contains(PixelFormatFlags::ALPHA_PIXELS) {
+        } else if format_flags.intersects(
+            PixelFormatFlags::LUMINANCE | PixelFormatFlags::RGB | PixelFormatFlags::ALPHA,
+        ) {
+            if rgb_bit_counts == 0 || rgb_bit_counts > 32 || rgb_bit_counts % 8 != 0 {
                 return util::io_err(
                     ErrorKind::InvalidData,
                     format!(
-                        "Non 8-bit Luminance pixel formats are not supported. Image pixels are {}-bit",
+                        "Only whole byte pixels of up to 32 bits are supported, not {}-bit",
                         rgb_bit_counts
                     ),
                 );
             }
-            bitmap.format = BitmapFormat::GrayU8;
-            let mut offset = 0;
-            for mip_level in 0..mip_levels {
-                let mip_width = (width >> mip_level) as usize;
-                let mip_height = (height >> mip_level) as usize;
-                let mip_pitch = mip_width;
-                let linear_size = mip_pitch * mip_height;
-                bitmap.data.reserve(linear_size);
-                for _ in 0..linear_size {
-                    bitmap.data.push(util::read_u8(reader)?);
+            let luminance = format_flags.contains(PixelFormatFlags::LUMINANCE);
+            let a_bit_mask = if format_flags
+                .intersects(PixelFormatFlags::ALPHA_PIXELS | PixelFormatFlags::ALPHA)
+            {
+                a_bit_mask
+            } else {
+                0
+            };
+            let format = if luminance && a_bit_mask == 0 {
+                BitmapFormat::GrayU8
+            } else {
+                BitmapFormat::BgraU8
+            };
+            // Anything laid out differently to the format gets converted a pixel at a time
+            let native = match format {
+                BitmapFormat::GrayU8 => rgb_bit_counts == 8 && r_bit_mask == 0xff,
+                _ => {
+                    !luminance
+                        && rgb_bit_counts == 32
+                        && [r_bit_mask, g_bit_mask, b_bit_mask, a_bit_mask]
+                            == [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000]
                 }
-                bitmap.mip_levels.push(RawMipLevel {
-                    start: offset,
-                    end: offset + linear_size,
-                    size: (mip_width as f32, mip_height as f32).into(),
-                    bytes_per_row: mip_pitch,
+            };
+            if !native {
+                let (g_bit_mask, b_bit_mask) = if luminance {
+                    (r_bit_mask, r_bit_mask)
+                } else if format_flags.contains(PixelFormatFlags::RGB) {
+                    (g_bit_mask, b_bit_mask)
+                } else {
+                    (0, 0)
+                };
+                let r_bit_mask = if luminance || format_flags.contains(PixelFormatFlags::RGB) {
+                    r_bit_mask
+                } else {
+                    0
+                };
+                let mut channels = [ChannelMask::default(); 4];
+                for (channel, &mask) in channels
+                    .iter_mut()
+                    .zip(&[b_bit_mask, g_bit_mask, r_bit_mask, a_bit_mask])
+                {
+                    *channel = util::io_err_option(
+                        ChannelMask::new(mask),
+                        ErrorKind::InvalidData,
+                        || format!("The bit mask {:08X} isn't contiguous", mask),
+                    )?;
+                }
+                masked = Some(MaskedLayout {
+                    bytes_per_pixel: rgb_bit_counts as usize / 8,
+                    channels,
                 });
-                offset += linear_size;
             }
-        } else if format_flags.contains(PixelFormatFlags::RGB) {
-            if rgb_bit_counts != 32 || !format_flags.contains(PixelFormatFlags::ALPHA_PIXELS) {
+            format
+        } else {
+            return util::io_err(
+                ErrorKind::InvalidData,
+                format!("Unsupported DDS pixel format {:04X}", format_flags_bytes),
+            );
+        };
+
+        // A u32 can only be halved 32 times
+        if mip_levels > 32 {
+            return util::io_err(
+                ErrorKind::InvalidData,
+                format!("A DDS file can't have {} mip levels", mip_levels),
+            );
+        }
+        let layers = util::io_err_option(
+            if cubemap {
+                layers.checked_mul(6)
+            } else {
+                Some(layers)
+            },
+            ErrorKind::InvalidData,
+            || format!("Too many layers: {}", layers),
+        )?;
+
+        bitmap.clear();
+        bitmap.format = format;
+        bitmap.cubemap = cubemap;
+        // Every layer has its whole mip chain before the next starts
+        for _ in 0..layers {
+            bitmap.push_layer();
+            // Files without a mip map count only have the top level
+            for mip_level in 0..mip_levels.max(1) {
+                let mip_width = width.checked_shr(mip_level).unwrap_or(0).max(1) as usize;
+                let mip_height = height.checked_shr(mip_level).unwrap_or(0).max(1) as usize;
+                let level_size = match &masked {
+                    Some(layout) => mip_width
+                        .checked_mul(mip_height)
+                        .and_then(|pixels| pixels.checked_mul(layout.bytes_per_pixel)),
+                    None => format.checked_level_size(mip_width, mip_height),
+                };
+                let level_size = util::io_err_option(level_size, ErrorKind::InvalidData, || {
+                    format!("A {}x{} mip level is too big", mip_width, mip_height)
+                })?;
+                let start = bitmap.data.len();
+                match &masked {
+                    Some(layout) => {
+                        let mut pixels = Vec::new();
+                        read_level(reader, level_size, &mut pixels)?;
+                        layout.convert(&pixels, format, &mut bitmap.data);
+                    }
+                    None => read_level(reader, level_size, &mut bitmap.data)?,
+                }
+                bitmap.push_level_range(start, mip_width, mip_height);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BitmapWriter {}
+
+impl BitmapWriter {
+    /// Write `bitmap` with all its layers and mip levels as a DDS file. Each level has to be
+    /// half the size of the one before, rounding down but no smaller than 1x1, and every layer
+    /// the same, because that's all DDS can describe.
+    ///
+    /// Formats, arrays and cubemaps that older readers understand get the plain header, and
+    /// the rest the DX10 one.
+    pub fn write<W: Write>(&mut self, writer: &mut W, bitmap: &Bitmap) -> io::Result<()> {
+        let format = bitmap.format;
+        let (width, height) = match bitmap.mip_levels().next() {
+            Some(level) => (level.size().x() as usize, level.size().y() as usize),
+            None => return util::io_err(ErrorKind::InvalidInput, "The bitmap has no mip levels"),
+        };
+        let mip_levels = bitmap.mip_levels().count();
+        let layers = bitmap.layer_count();
+        for index in 0..layers {
+            if bitmap.layer(index).count() != mip_levels {
                 return util::io_err(
-                    ErrorKind::InvalidData,
+                    ErrorKind::InvalidInput,
                     format!(
-                        "Non 32-bit RGB pixel formats are not supported. Image pixels are {}-bit",
-                        rgb_bit_counts
+                        "Layer {} has {} mip levels but the first has {}",
+                        index,
+                        bitmap.layer(index).count(),
+                        mip_levels
                     ),
                 );
             }
-            bitmap.format = BitmapFormat::BgraU8;
-            let linear_size = height * pitch;
-            bitmap.data.reserve(linear_size as usize);
-            for _ in 0..(width * height) {
-                bitmap.data.extend(&util::read_u32(reader)?.to_le_bytes());
+            for (i, level) in bitmap.layer(index).enumerate() {
+                let (level_width, level_height) = ((width >> i).max(1), (height >> i).max(1));
+                let size = level.size();
+                if (size.x() as usize, size.y() as usize) != (level_width, level_height) {
+                    return util::io_err(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "Mip level {} of layer {} is {}x{} but should be {}x{}",
+                            i,
+                            index,
+                            size.x(),
+                            size.y(),
+                            level_width,
+                            level_height
+                        ),
+                    );
+                }
+                if level.data().len() != format.level_size(level_width, level_height) {
+                    return util::io_err(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "Mip level {} of layer {} has {} bytes but a {}x{} {:?} level needs {}",
+                            i,
+                            index,
+                            level.data().len(),
+                            level_width,
+                            level_height,
+                            format,
+                            format.level_size(level_width, level_height)
+                        ),
+                    );
+                }
             }
-            bitmap.mip_levels.push(RawMipLevel {
-                start: 0,
-                end: linear_size as usize,
-                size: (width as f32, height as f32).into(),
-                bytes_per_row: pitch as usize,
-            });
-        } else {
+        }
+        if bitmap.cubemap && (layers % 6 != 0 || width != height) {
             return util::io_err(
-                ErrorKind::InvalidData,
-                format!("Unsupported DDS pixel format {:04X}", format_flags_bytes),
+                ErrorKind::InvalidInput,
+                format!(
+                    "Cubemaps need square faces, six per cube, not {} {}x{} layers",
+                    layers, width, height
+                ),
             );
         }
 
+        let mut flags = HeaderFlags::CAPS
+            | HeaderFlags::HEIGHT
+            | HeaderFlags::WIDTH
+            | HeaderFlags::PIXEL_FORMAT
+            | HeaderFlags::MIPMAP_COUNT;
+        let pitch_or_linear_size = if format.is_compressed() {
+            flags |= HeaderFlags::LINEAR_SIZE;
+            format.level_size(width, height)
+        } else {
+            flags |= HeaderFlags::PITCH;
+            format.bytes_per_row(width)
+        };
+        let mut capabilities = CapabilityFlags::TEXTURE;
+        if mip_levels > 1 || layers > 1 {
+            capabilities |= CapabilityFlags::COMPLEX;
+        }
+        if mip_levels > 1 {
+            capabilities |= CapabilityFlags::MIPMAP;
+        }
+        let capabilities2 = if bitmap.cubemap {
+            CapabilityFlags2::CUBEMAP | CapabilityFlags2::ALL_FACES
+        } else {
+            CapabilityFlags2::empty()
+        };
+
+        // Flags, four character code, bit count and red, green, blue and alpha masks
+        let fourcc = |code: &[u8; 4]| (PixelFormatFlags::FOUR_CHARACTER_CODE, *code, 0, [0; 4]);
+        let plain_format = match format {
+            BitmapFormat::BgraU8 => Some((
+                PixelFormatFlags::RGB | PixelFormatFlags::ALPHA_PIXELS,
+                [0; 4],
+                32,
+                [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000],
+            )),
+            BitmapFormat::GrayU8 => Some((PixelFormatFlags::LUMINANCE, [0; 4], 8, [0xff, 0, 0, 0])),
+            BitmapFormat::Dxt1 => Some(fourcc(b"DXT1")),
+            BitmapFormat::Dxt3 => Some(fourcc(b"DXT3")),
+            BitmapFormat::Dxt5 => Some(fourcc(b"DXT5")),
+            BitmapFormat::Bc4 => Some(fourcc(b"ATI1")),
+            BitmapFormat::Bc5 => Some(fourcc(b"ATI2")),
+            _ => None,
+        };
+        // Only single textures and single cubemaps can do without the DX10 header
+        let plain_format = plain_format.filter(|_| layers == 1 || (bitmap.cubemap && layers == 6));
+        let (format_flags, four_character_code, bit_count, masks) =
+            plain_format.unwrap_or_else(|| fourcc(b"DX10"));
+
+        writer.write_all(b"DDS ")?;
+        util::write_u32(writer, 124)?;
+        util::write_u32(writer, flags.bits())?;
+        util::write_u32(writer, height as u32)?;
+        util::write_u32(writer, width as u32)?;
+        util::write_u32(writer, pitch_or_linear_size as u32)?;
+        // Depth
+        util::write_u32(writer, 0)?;
+        util::write_u32(writer, mip_levels as u32)?;
+        writer.write_all(&[0; 11 * 4])?;
+
+        util::write_u32(writer, 32)?;
+        util::write_u32(writer, format_flags.bits())?;
+        writer.write_all(&four_character_code)?;
+        util::write_u32(writer, bit_count)?;
+        for &mask in &masks {
+            util::write_u32(writer, mask)?;
+        }
+
+        util::write_u32(writer, capabilities.bits())?;
+        util::write_u32(writer, capabilities2.bits())?;
+        // Two unused capabilities and a reserved field
+        writer.write_all(&[0; 3 * 4])?;
+
+        if plain_format.is_none() {
+            let dxgi_format = DXGI_FORMATS.iter().find(|(f, _)| *f == format).unwrap().1;
+            util::write_u32(writer, dxgi_format)?;
+            util::write_u32(writer, DX10_TEXTURE_2D)?;
+            if bitmap.cubemap {
+                util::write_u32(writer, DX10_TEXTURE_CUBE)?;
+                util::write_u32(writer, (layers / 6) as u32)?;
+            } else {
+                util::write_u32(writer, 0)?;
+                util::write_u32(writer, layers as u32)?;
+            }
+            // The alpha mode, left unknown
+            util::write_u32(writer, 0)?;
+        }
+
+        for index in 0..layers {
+            for level in bitmap.layer(index) {
+                writer.write_all(level.data())?;
+            }
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Add a full mip chain of made up data, different for each `seed`, to the last layer.
+    fn push_chain(bitmap: &mut Bitmap, width: usize, height: usize, seed: usize) {
+        let mut level = 0;
+        loop {
+            let (w, h) = ((width >> level).max(1), (height >> level).max(1));
+            let data: Vec<u8> = (0..bitmap.format.level_size(w, h))
+                .map(|i| ((i * 97 + level * 13 + seed * 31) % 251) as u8)
+                .collect();
+            bitmap.push_mip_level(w, h, &data);
+            if w == 1 && h == 1 {
+                return;
+            }
+            level += 1;
+        }
+    }
+
+    fn chain(format: BitmapFormat, width: usize, height: usize) -> Bitmap {
+        let mut bitmap = Bitmap {
+            format,
+            ..Bitmap::default()
+        };
+        push_chain(&mut bitmap, width, height, 0);
+        bitmap
+    }
+
+    fn layers(format: BitmapFormat, size: usize, count: usize, cubemap: bool) -> Bitmap {
+        let mut bitmap = Bitmap {
+            format,
+            cubemap,
+            ..Bitmap::default()
+        };
+        for seed in 0..count {
+            bitmap.push_layer();
+            push_chain(&mut bitmap, size, size, seed);
+        }
+        bitmap
+    }
+
+    fn round_trip(bitmap: &Bitmap) -> (Vec<u8>, Bitmap) {
+        let mut file = Vec::new();
+        BitmapWriter::default().write(&mut file, bitmap).unwrap();
+        // Reading into a used bitmap replaces what was there
+        let mut read = chain(BitmapFormat::Dxt1, 4, 4);
+        BitmapReader::default()
+            .read_into(&mut Cursor::new(&file), &mut read)
+            .unwrap();
+        (file, read)
+    }
+
+    /// A DDS file of `width` x `height` pixels and its mip levels in an uncompressed layout.
+    fn masked_file(
+        (width, height, mip_levels): (usize, usize, usize),
+        flags: PixelFormatFlags,
+        bit_count: u32,
+        masks: [u32; 4],
+        pixels: &[u8],
+    ) -> Vec<u8> {
+        let mut bitmap = chain(BitmapFormat::GrayU8, width, height);
+        bitmap.layers[0].truncate(mip_levels);
+        let mut file = Vec::new();
+        BitmapWriter::default().write(&mut file, &bitmap).unwrap();
+        file.truncate(0x80);
+        file[0x50..0x54].copy_from_slice(&flags.bits().to_le_bytes());
+        file[0x58..0x5C].copy_from_slice(&bit_count.to_le_bytes());
+        for (i, mask) in masks.iter().enumerate() {
+            file[0x5C + i * 4..0x60 + i * 4].copy_from_slice(&mask.to_le_bytes());
+        }
+        file.extend_from_slice(pixels);
+        file
+    }
+
+    fn read(file: &[u8]) -> Bitmap {
+        let mut bitmap = Bitmap::default();
+        BitmapReader::default()
+            .read_into(&mut Cursor::new(file), &mut bitmap)
+            .unwrap();
+        bitmap
+    }
+
+    fn header_u32(file: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([
+            file[offset],
+            file[offset + 1],
+            file[offset + 2],
+            file[offset + 3],
+        ])
+    }
+
+    fn sizes(bitmap: &Bitmap) -> Vec<(f32, f32)> {
+        bitmap
+            .mip_levels()
+            .map(|level| (level.size().x(), level.size().y()))
+            .collect()
+    }
+
+    #[test]
+    fn generates_mip_chains() {
+        let mut bitmap = Bitmap::new(BitmapFormat::GrayU8, 5, 3, (0..15).collect());
+        bitmap
+            .generate_mip_levels(MipFilter::Box, ColorSpace::Linear)
+            .unwrap();
+        assert_eq!(vec![(5.0, 3.0), (2.0, 1.0), (1.0, 1.0)], sizes(&bitmap));
+        for level in bitmap.mip_levels() {
+            let size = level.size();
+            assert_eq!(size.x() as usize, level.bytes_per_row());
+            assert_eq!((size.x() * size.y()) as usize, level.data().len());
+        }
+        // Everything gets averaged into the last level
+        assert_eq!(&[7], bitmap.mip_levels().last().unwrap().data());
+
+        // Generating again replaces the chain
+        bitmap
+            .generate_mip_levels(MipFilter::Kaiser, ColorSpace::Linear)
+            .unwrap();
+        assert_eq!(3, bitmap.mip_levels().count());
+    }
+
+    #[test]
+    fn averages_srgb_in_linear_light() {
+        let black_and_white = vec![0, 0, 0, 255, 255, 255, 255, 255];
+        let mut srgb = Bitmap::new(BitmapFormat::BgraU8, 2, 1, black_and_white.clone());
+        srgb.generate_mip_levels(MipFilter::Box, ColorSpace::Srgb)
+            .unwrap();
+        assert_eq!(
+            &[188, 188, 188, 255],
+            srgb.mip_levels().nth(1).unwrap().data()
+        );
+
+        let mut linear = Bitmap::new(BitmapFormat::BgraU8, 2, 1, black_and_white);
+        linear
+            .generate_mip_levels(MipFilter::Box, ColorSpace::Linear)
+            .unwrap();
+        assert_eq!(
+            &[128, 128, 128, 255],
+            linear.mip_levels().nth(1).unwrap().data()
+        );
+    }
+
+    #[test]
+    fn only_generates_uncompressed_mip_levels() {
+        let mut bitmap = Bitmap::new(BitmapFormat::Dxt1, 4, 4, vec![0; 8]);
+        assert!(bitmap
+            .generate_mip_levels(MipFilter::Box, ColorSpace::Srgb)
+            .is_err());
+        assert!(Bitmap::default()
+            .generate_mip_levels(MipFilter::Box, ColorSpace::Srgb)
+            .is_err());
+    }
+
+    #[test]
+    fn writes_and_reads_back_every_format() {
+        for &(format, _) in &DXGI_FORMATS {
+            // Formats older readers don't know need the extended header
+            let dx10 = format.is_srgb()
+                || matches!(
+                    format,
+                    BitmapFormat::Bc6h | BitmapFormat::Bc6hSigned | BitmapFormat::Bc7
+                );
+            let header = if dx10 { 0x94 } else { 0x80 };
+            for &(width, height) in &[(16, 8), (5, 3), (1, 1)] {
+                let bitmap = chain(format, width, height);
+                let (file, read) = round_trip(&bitmap);
+                assert_eq!(bitmap, read, "{:?} {}x{}", format, width, height);
+                assert_eq!(header + bitmap.data.len(), file.len());
+            }
+            // Only the top level
+            let top = chain(format, 8, 8);
+            let bitmap = Bitmap::new(format, 8, 8, top.mip_levels().next().unwrap().data().into());
+            assert_eq!(bitmap, round_trip(&bitmap).1);
+        }
+    }
+
+    #[test]
+    fn writes_dx10_headers() {
+        let (file, read) = round_trip(&chain(BitmapFormat::Bc7Srgb, 8, 8));
+        assert_eq!(BitmapFormat::Bc7Srgb, read.format());
+        assert_eq!(b"DX10", &file[0x54..0x58]);
+        assert_eq!(99, header_u32(&file, 0x80));
+        assert_eq!(DX10_TEXTURE_2D, header_u32(&file, 0x84));
+        assert_eq!(0, header_u32(&file, 0x88));
+        assert_eq!(1, header_u32(&file, 0x8C));
+
+        // Unknown formats and volumes are rejected
+        let mut unknown = file.clone();
+        unknown[0x80] = 2;
+        assert!(BitmapReader::default()
+            .read_into(&mut Cursor::new(&unknown), &mut Bitmap::default())
+            .is_err());
+        let mut volume = file;
+        volume[0x84] = 4;
+        assert!(BitmapReader::default()
+            .read_into(&mut Cursor::new(&volume), &mut Bitmap::default())
+            .is_err());
+    }
+
+    #[test]
+    fn writes_arrays_and_cubemaps() {
+        // A single cubemap fits the plain header
+        let cubemap = layers(BitmapFormat::Dxt1, 8, 6, true);
+        let (file, read) = round_trip(&cubemap);
+        assert_eq!(cubemap, read);
+        assert!(read.is_cubemap());
+        assert_eq!(6, read.layer_count());
+        assert_ne!(
+            read.layer(0).next().unwrap().data(),
+            read.layer(5).next().unwrap().data()
+        );
+        assert_eq!(b"DXT1", &file[0x54..0x58]);
+        assert_eq!(0x0040_1008, header_u32(&file, 0x6C));
+        assert_eq!(0xFE00, header_u32(&file, 0x70));
+
+        // More than one needs the DX10 one
+        let cubemaps = layers(BitmapFormat::Dxt1, 8, 12, true);
+        let (file, read) = round_trip(&cubemaps);
+        assert_eq!(cubemaps, read);
+        assert_eq!(b"DX10", &file[0x54..0x58]);
+        assert_eq!(71, header_u32(&file, 0x80));
+        assert_eq!(DX10_TEXTURE_CUBE, header_u32(&file, 0x88));
+        assert_eq!(2, header_u32(&file, 0x8C));
+
+        let array = layers(BitmapFormat::BgraU8, 4, 3, false);
+        let (file, read) = round_trip(&array);
+        assert_eq!(array, read);
+        assert!(!read.is_cubemap());
+        assert_eq!(87, header_u32(&file, 0x80));
+        assert_eq!(0, header_u32(&file, 0x88));
+        assert_eq!(3, header_u32(&file, 0x8C));
+        assert_eq!(0, header_u32(&file, 0x70));
+
+        // Decoding and mip generation keep every layer
+        let decoded = cubemaps.decode().unwrap();
+        assert!(decoded.is_cubemap());
+        assert_eq!(12, decoded.layer_count());
+        let mut array = layers(BitmapFormat::GrayU8, 4, 2, false);
+        array
+            .generate_mip_levels(MipFilter::Box, ColorSpace::Linear)
+            .unwrap();
+        assert_eq!(2, array.layer_count());
+        assert_eq!(3, array.layer(1).count());
+        assert_eq!(array, round_trip(&array).1);
+    }
+
+    #[test]
+    fn writes_dds_headers() {
+        let (file, _) = round_trip(&chain(BitmapFormat::Dxt5, 16, 8));
+        assert_eq!(b"DDS ", &file[..4]);
+        assert_eq!(124, header_u32(&file, 0x04));
+        assert_eq!(0x000a_1007, header_u32(&file, 0x08));
+        assert_eq!((8, 16), (header_u32(&file, 0x0C), header_u32(&file, 0x10)));
+        // The linear size of the top level
+        assert_eq!(4 * 2 * 16, header_u32(&file, 0x14));
+        assert_eq!(5, header_u32(&file, 0x1C));
+        assert_eq!(32, header_u32(&file, 0x4C));
+        assert_eq!(0x04, header_u32(&file, 0x50));
+        assert_eq!(b"DXT5", &file[0x54..0x58]);
+        assert_eq!(0x0040_1008, header_u32(&file, 0x6C));
+
+        let (file, _) = round_trip(&Bitmap::new(BitmapFormat::BgraU8, 3, 1, vec![0; 12]));
+        assert_eq!(0x0002_100F, header_u32(&file, 0x08));
+        // The pitch of the top level
+        assert_eq!(12, header_u32(&file, 0x14));
+        assert_eq!(0x41, header_u32(&file, 0x50));
+        assert_eq!(32, header_u32(&file, 0x58));
+        assert_eq!(0xff00_0000, header_u32(&file, 0x68));
+        assert_eq!(0x1000, header_u32(&file, 0x6C));
+    }
+
+    #[test]
+    fn rewrites_frigate_textures() {
+        for file in &["diffuse", "normal", "specular", "emissive"] {
+            let mut bitmap = Bitmap::default();
+            BitmapReader::default()
+                .read_into(
+                    &mut util::buf_open(format!("res/bitmaps/frigate/{}.dds", file)).unwrap(),
+                    &mut bitmap,
+                )
+                .unwrap();
+            assert_eq!(bitmap, round_trip(&bitmap).1);
+        }
+    }
+
+    #[test]
+    fn only_writes_what_dds_can_describe() {
+        let mut writer = BitmapWriter::default();
+        assert!(writer.write(&mut Vec::new(), &Bitmap::default()).is_err());
+
+        let mut skipped_level = Bitmap::new(BitmapFormat::GrayU8, 4, 4, vec![0; 16]);
+        skipped_level.push_mip_level(1, 1, &[0]);
+        assert!(writer.write(&mut Vec::new(), &skipped_level).is_err());
+
+        let short = Bitmap::new(BitmapFormat::Dxt1, 8, 8, vec![0; 8]);
+        assert!(writer.write(&mut Vec::new(), &short).is_err());
+
+        let mut uneven = chain(BitmapFormat::GrayU8, 4, 4);
+        uneven.push_layer();
+        uneven.push_mip_level(4, 4, &[0; 16]);
+        assert!(writer.write(&mut Vec::new(), &uneven).is_err());
+
+        let five_faces = layers(BitmapFormat::GrayU8, 4, 5, true);
+        assert!(writer.write(&mut Vec::new(), &five_faces).is_err());
+    }
+
+    #[test]
+    fn converts_masked_rgb_layouts() {
+        let rgb = PixelFormatFlags::RGB;
+        let rgba = PixelFormatFlags::RGB | PixelFormatFlags::ALPHA_PIXELS;
+        for (flags, bit_count, masks, pixels, bgra) in vec![
+            // R5G6B5 red, green and blue, then gray
+            (
+                rgb,
+                16,
+                [0xF800, 0x07E0, 0x001F, 0],
+                vec![0x00, 0xF8, 0xE0, 0x07, 0x1F, 0x00, 0x10, 0x84],
+                vec![
+                    0, 0, 255, 255, 0, 255, 0, 255, 255, 0, 0, 255, 132, 130, 132, 255,
+                ],
+            ),
+            // A1R5G5B5 opaque red and transparent blue
+            (
+                rgba,
+                16,
+                [0x7C00, 0x03E0, 0x001F, 0x8000],
+                vec![0x00, 0xFC, 0x1F, 0x00],
+                vec![0, 0, 255, 255, 255, 0, 0, 0],
+            ),
+            // A4R4G4B4
+            (
+                rgba,
+                16,
+                [0x0F00, 0x00F0, 0x000F, 0xF000],
+                vec![0x2F, 0xF1],
+                vec![255, 34, 17, 255],
+            ),
+            // RGB24, stored blue first
+            (
+                rgb,
+                24,
+                [0xFF0000, 0x00FF00, 0x0000FF, 0],
+                vec![1, 2, 3],
+                vec![1, 2, 3, 255],
+            ),
+            // X8B8G8R8, red first and no alpha
+            (
+                rgb,
+                32,
+                [0x0000FF, 0x00FF00, 0xFF0000, 0xFF000000],
+                vec![1, 2, 3, 4],
+                vec![3, 2, 1, 255],
+            ),
+            // Alpha only
+            (
+                PixelFormatFlags::ALPHA,
+                8,
+                [0, 0, 0, 0xFF],
+                vec![0x7F],
+                vec![0, 0, 0, 0x7F],
+            ),
+            // A8L8
+            (
+                PixelFormatFlags::LUMINANCE | PixelFormatFlags::ALPHA_PIXELS,
+                16,
+                [0x00FF, 0, 0, 0xFF00],
+                vec![0x40, 0x80],
+                vec![0x40, 0x40, 0x40, 0x80],
+            ),
+        ] {
+            let width = bgra.len() / 4;
+            let bitmap = read(&masked_file(
+                (width, 1, 1),
+                flags,
+                bit_count,
+                masks,
+                &pixels,
+            ));
+            assert_eq!(BitmapFormat::BgraU8, bitmap.format());
+            assert_eq!(&bgra[..], bitmap.mip_levels().next().unwrap().data());
+        }
+    }
+
+    #[test]
+    fn converts_masked_luminance_and_mip_chains() {
+        // L16 keeps the top byte, rounded
+        let l16 = masked_file(
+            (2, 1, 1),
+            PixelFormatFlags::LUMINANCE,
+            16,
+            [0xFFFF, 0, 0, 0],
+            &[0xFF, 0xFF, 0x80, 0x80],
+        );
+        let bitmap = read(&l16);
+        assert_eq!(BitmapFormat::GrayU8, bitmap.format());
+        assert_eq!(&[255, 128], bitmap.mip_levels().next().unwrap().data());
+
+        // R5G6B5 4x2, 2x1 and 1x1 levels of white, black and red
+        let mut pixels = vec![0xFF; 4 * 2 * 2];
+        pixels.extend(&[0; 2 * 2]);
+        pixels.extend(&[0x00, 0xF8]);
+        let bitmap = read(&masked_file(
+            (4, 2, 3),
+            PixelFormatFlags::RGB,
+            16,
+            [0xF800, 0x07E0, 0x001F, 0],
+            &pixels,
+        ));
+        assert_eq!(vec![(4.0, 2.0), (2.0, 1.0), (1.0, 1.0)], sizes(&bitmap));
+        let levels: Vec<_> = bitmap
+            .mip_levels()
+            .map(|level| level.data().to_vec())
+            .collect();
+        assert_eq!(vec![255; 4 * 2 * 4], levels[0]);
+        assert_eq!(vec![0, 0, 0, 255, 0, 0, 0, 255], levels[1]);
+        assert_eq!(vec![0, 0, 255, 255], levels[2]);
+
+        // Missing pixels and scattered masks are errors
+        let mut reader = BitmapReader::default();
+        let short = &pixels[..pixels.len() - 1];
+        for file in &[
+            masked_file(
+                (4, 2, 3),
+                PixelFormatFlags::RGB,
+                16,
+                [0xF800, 0x07E0, 0x001F, 0],
+                short,
+            ),
+            masked_file(
+                (1, 1, 1),
+                PixelFormatFlags::RGB,
+                16,
+                [0xF00F, 0, 0, 0],
+                &[0, 0],
+            ),
+            masked_file(
+                (1, 1, 1),
+                PixelFormatFlags::RGB,
+                12,
+                [0xF00, 0, 0, 0],
+                &[0, 0],
+            ),
+        ] {
+            assert!(reader
+                .read_into(&mut Cursor::new(file), &mut Bitmap::default())
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let error = |file: &[u8]| {
+            BitmapReader::default()
+                .read_into(&mut Cursor::new(file), &mut Bitmap::default())
+                .unwrap_err()
+                .kind()
+        };
+        let (file, _) = round_trip(&chain(BitmapFormat::BgraU8, 4, 4));
+        let set = |offset: usize, value: u32| {
+            let mut file = file.clone();
+            file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            file
+        };
+
+        assert_eq!(ErrorKind::InvalidData, error(&set(0x1C, 33)));
+        // Huge sizes with hardly any data behind them
+        assert_eq!(ErrorKind::UnexpectedEof, error(&set(0x10, 0x7FFF_FFFF)));
+        // ... and sizes whose byte count doesn't even fit
+        let mut huge = set(0x0C, u32::MAX);
+        huge[0x10..0x14].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(ErrorKind::InvalidData, error(&huge));
+        assert_eq!(ErrorKind::UnexpectedEof, error(&file[..file.len() - 1]));
+
+        // Too many cubemaps to count the faces of
+        let (mut cubemaps, _) = round_trip(&layers(BitmapFormat::Dxt1Srgb, 4, 12, true));
+        cubemaps[0x8C..0x90].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(ErrorKind::InvalidData, error(&cubemaps));
+        // Lots of layers but the data for two cubes
+        cubemaps[0x8C..0x90].copy_from_slice(&0x1000_0000u32.to_le_bytes());
+        assert_eq!(ErrorKind::UnexpectedEof, error(&cubemaps));
+    }
+}
@@ -0,0 +1,504 @@
+use crate::{
+    gfx::{Frustum, LightKind, LightList, PerspectiveProjection},
+    math::{Vector2, Vector3},
+};
+
+/// The lights touching one cluster.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Cluster {
+    /// Where the cluster's point light indices start in `LightClusters::indices`. The spot
+    /// light indices follow them.
+    pub offset: u32,
+    pub point_lights: u32,
+    pub spot_lights: u32,
+}
+
+// A light boiled down to what's needed to find the clusters it touches.
+#[derive(Debug, Copy, Clone)]
+struct LightBounds {
+    index: u32,
+    center: Vector3,
+    radius: f32,
+    // Only for spot lights: apex, direction, range and the cosine and sine of the outer angle
+    cone: Option<(Vector3, Vector3, f32, f32, f32)>,
+    slices: (usize, usize),
+}
+
+/// Which point and spot lights touch each cluster (or "froxel") of a perspective view.
+///
+/// The view is cut into a grid of tiles across and exponentially spaced slices along it, so
+/// slices near the camera are thin. Light indices count point and spot lights separately in
+/// `LightList` order, i.e. 0 is the first point (or spot) light. Directional lights touch
+/// everything so they aren't clustered.
+#[derive(Debug)]
+pub struct LightClusters {
+    dimensions: [usize; 3],
+    max_point_lights: usize,
+    max_spot_lights: usize,
+    clusters: Vec<Cluster>,
+    indices: Vec<u32>,
+    point_bounds: Vec<LightBounds>,
+    spot_bounds: Vec<LightBounds>,
+}
+
+impl LightClusters {
+    /// `dimensions` are the number of tiles across, up and slices along the view, each at
+    /// least 1. Lights past the maximums (e.g. what a renderer has room for) are left out.
+    pub fn new(
+        dimensions: [usize; 3],
+        max_point_lights: usize,
+        max_spot_lights: usize,
+    ) -> LightClusters {
+        let dimensions = [
+            dimensions[0].max(1),
+            dimensions[1].max(1),
+            dimensions[2].max(1),
+        ];
+        LightClusters {
+            dimensions,
+            max_point_lights,
+            max_spot_lights,
+            clusters: vec![Cluster::default(); dimensions.iter().product()],
+            indices: Vec::new(),
+            point_bounds: Vec::new(),
+            spot_bounds: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn dimensions(&self) -> [usize; 3] {
+        self.dimensions
+    }
+
+    /// `slice` and `index` for the shaders, finding the cluster of a view space position from
+    /// the `ClusterParams` uniforms.
+    pub const GLSL_CLUSTER_INDEX: &'static str = "\
+// The cluster a view space position is in, like LightClusters::slice and LightClusters::index
+uint cluster_index(vec3 view_space, vec2 max_slope, float near, float far, uvec3 dimensions) {
+    float depth = max(-view_space.z, near);
+    vec2 tile = (view_space.xy / depth + max_slope) / (2.0 * max_slope);
+    // Slices are spaced exponentially
+    float slice = log(depth / near) / log(far / near);
+    uvec3 cluster = uvec3(clamp(vec3(tile, slice) * vec3(dimensions), vec3(0.0), vec3(dimensions - 1u)));
+    return cluster.x + dimensions.x * (cluster.y + dimensions.y * cluster.z);
+}
+";
+
+    /// Clusters are laid out left to right, bottom to top, then near to far.
+    #[inline]
+    pub fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + self.dimensions[0] * (y + self.dimensions[1] * z)
+    }
+
+    #[inline]
+    pub fn clusters(&self) -> &[Cluster] {
+        &self.clusters
+    }
+
+    /// Every cluster's light indices, one after another.
+    #[inline]
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    #[inline]
+    pub fn point_lights(&self, cluster: usize) -> &[u32] {
+        let cluster = &self.clusters[cluster];
+        let start = cluster.offset as usize;
+        &self.indices[start..start + cluster.point_lights as usize]
+    }
+
+    #[inline]
+    pub fn spot_lights(&self, cluster: usize) -> &[u32] {
+        let cluster = &self.clusters[cluster];
+        let start = (cluster.offset + cluster.point_lights) as usize;
+        &self.indices[start..start + cluster.spot_lights as usize]
+    }
+
+    /// The view distance slice `z` starts at. Slice `dimensions[2]` starts at the far plane.
+    #[inline]
+    pub fn slice_depth(&self, projection: &PerspectiveProjection, z: usize) -> f32 {
+        let t = z as f32 / self.dimensions[2] as f32;
+        projection.near * (projection.far / projection.near).powf(t)
+    }
+
+    /// The slice a view distance falls in, clamped to the slices that exist.
+    #[inline]
+    pub fn slice(&self, projection: &PerspectiveProjection, depth: f32) -> usize {
+        let t = (depth / projection.near).ln() / (projection.far / projection.near).ln();
+        let slice = (t * self.dimensions[2] as f32).floor().max(0.0) as usize;
+        slice.min(self.dimensions[2] - 1)
+    }
+
+    /// Find the lights touching each cluster of the view from `position` looking at `at`.
+    pub fn assign(
+        &mut self,
+        projection: &PerspectiveProjection,
+        position: Vector3,
+        at: Vector3,
+        up: Vector3,
+        lights: &LightList,
+    ) {
+        let direction = (at - position).normalized();
+        self.gather_bounds(lights, |clusters, center, radius| {
+            let depth = (center - position).dot(direction);
+            if depth + radius < projection.near || depth - radius > projection.far {
+                return None;
+            }
+            Some((
+                clusters.slice(projection, depth - radius),
+                clusters.slice(projection, depth + radius),
+            ))
+        });
+
+        let [width, height, depth] = self.dimensions;
+        let tan_y = (projection.fov / 2.0).tan();
+        let max_slope = Vector2::new(tan_y * projection.aspect_ratio, tan_y);
+        let tile_slope = Vector2::new(
+            2.0 * max_slope.x() / width as f32,
+            2.0 * max_slope.y() / height as f32,
+        );
+
+        self.indices.clear();
+        let mut frustum = Frustum::default();
+        frustum.update_look_at(position, at, up);
+        for z in 0..depth {
+            let near = self.slice_depth(projection, z);
+            let far = self.slice_depth(projection, z + 1);
+            for y in 0..height {
+                for x in 0..width {
+                    let min = Vector2::new(
+                        x as f32 * tile_slope.x() - max_slope.x(),
+                        y as f32 * tile_slope.y() - max_slope.y(),
+                    );
+                    frustum.update_off_center_projection(min, min + tile_slope, near, far);
+
+                    let offset = self.indices.len() as u32;
+                    let point_lights =
+                        assign_cluster(&frustum, z, &self.point_bounds, &mut self.indices);
+                    let spot_lights =
+                        assign_cluster(&frustum, z, &self.spot_bounds, &mut self.indices);
+                    let index = self.index(x, y, z);
+                    self.clusters[index] = Cluster {
+                        offset,
+                        point_lights,
+                        spot_lights,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Put every light in every cluster. For views that can't be clustered, like orthographic
+    /// ones.
+    pub fn assign_everywhere(&mut self, lights: &LightList) {
+        let slices = self.dimensions[2] - 1;
+        self.gather_bounds(lights, |_, _, _| Some((0, slices)));
+        self.indices.clear();
+        self.indices
+            .extend(self.point_bounds.iter().map(|bounds| bounds.index));
+        self.indices
+            .extend(self.spot_bounds.iter().map(|bounds| bounds.index));
+        let everything = Cluster {
+            offset: 0,
+            point_lights: self.point_bounds.len() as u32,
+            spot_lights: self.spot_bounds.len() as u32,
+        };
+        for cluster in &mut self.clusters {
+            *cluster = everything;
+        }
+    }
+
+    // Find the bounds of every point and spot light. `slices` gives the range of slices a
+    // bounding sphere could touch, or `None` to skip the light.
+    fn gather_bounds<F>(&mut self, lights: &LightList, slices: F)
+    where
+        F: Fn(&LightClusters, Vector3, f32) -> Option<(usize, usize)>,
+    {
+        self.point_bounds.clear();
+        self.spot_bounds.clear();
+        let (mut point, mut spot) = (0, 0);
+        for placed in lights.lights() {
+            let (index, center, radius, cone) = match placed.light.kind {
+                LightKind::Directional => continue,
+                LightKind::Point(attenuation) => {
+                    point += 1;
+                    if point > self.max_point_lights {
+                        continue;
+                    }
+                    let index = point - 1;
+                    let range = attenuation.range();
+                    (index, placed.position, range, None)
+                }
+                LightKind::Spot {
+                    attenuation,
+                    outer_angle,
+                    ..
+                } => {
+                    spot += 1;
+                    if spot > self.max_spot_lights {
+                        continue;
+                    }
+                    let index = spot - 1;
+                    let range = attenuation.range();
+                    let (sin, cos) = outer_angle.sin_cos();
+                    // The smallest sphere around the cone
+                    let (distance, radius) = if outer_angle > std::f32::consts::FRAC_PI_4 {
+                        (range * cos, range * sin)
+                    } else {
+                        let radius = range / (2.0 * cos);
+                        (radius, radius)
+                    };
+                    let center = placed.position + placed.direction * distance;
+                    let cone = (placed.position, placed.direction, range, cos, sin);
+                    (index, center, radius, Some(cone))
+                }
+            };
+            let slices = match slices(self, center, radius) {
+                Some(slices) => slices,
+                None => continue,
+            };
+            let light = LightBounds {
+                index: index as u32,
+                center,
+                radius,
+                cone,
+                slices,
+            };
+            match cone {
+                None => self.point_bounds.push(light),
+                Some(_) => self.spot_bounds.push(light),
+            }
+        }
+    }
+}
+
+// Add the lights touching a cluster to `indices`, returning how many there were.
+fn assign_cluster(
+    frustum: &Frustum,
+    slice: usize,
+    lights: &[LightBounds],
+    indices: &mut Vec<u32>,
+) -> u32 {
+    let mut count = 0;
+    let mut bounding_sphere = None;
+    for light in lights {
+        if slice < light.slices.0 || slice > light.slices.1 {
+            continue;
+        }
+        if !frustum.sphere_inside(light.center, light.radius) {
+            continue;
+        }
+        if let Some((apex, direction, range, cos, sin)) = light.cone {
+            let (center, radius) = *bounding_sphere.get_or_insert_with(|| sphere_around(frustum));
+            if !cone_touches_sphere(apex, direction, range, cos, sin, center, radius) {
+                continue;
+            }
+        }
+        indices.push(light.index);
+        count += 1;
+    }
+    count
+}
+
+#[inline]
+fn sphere_around(frustum: &Frustum) -> (Vector3, f32) {
+    let corners = frustum.corners();
+    let center = corners
+        .iter()
+        .fold(Vector3::default(), |sum, corner| sum + *corner)
+        / corners.len() as f32;
+    let radius = corners
+        .iter()
+        .map(|corner| (*corner - center).length())
+        .fold(0.0, f32::max);
+    (center, radius)
+}
+
+// Conservative cone-sphere test, treating the cone as ending in a flat cap at `range`.
+#[inline]
+fn cone_touches_sphere(
+    apex: Vector3,
+    direction: Vector3,
+    range: f32,
+    cos: f32,
+    sin: f32,
+    center: Vector3,
+    radius: f32,
+) -> bool {
+    let to_center = center - apex;
+    let along = to_center.dot(direction);
+    let across = (to_center.dot(to_center) - along * along).max(0.0).sqrt();
+    // How far the center is from the side of the cone
+    let distance = cos * across - sin * along;
+    distance <= radius && along <= range + radius && along >= -radius
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        gfx::{Attenuation, Light},
+        math::Matrix4,
+    };
+
+    const PROJECTION: PerspectiveProjection = PerspectiveProjection {
+        fov: std::f32::consts::FRAC_PI_2,
+        aspect_ratio: 1.0,
+        near: 0.1,
+        far: 100.0,
+    };
+
+    // Looking down -z from the origin
+    fn assign(lights: &LightList) -> LightClusters {
+        let mut clusters = LightClusters::new([8, 8, 16], 8, 8);
+        clusters.assign(
+            &PROJECTION,
+            Vector3::splat(0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::up(),
+            lights,
+        );
+        clusters
+    }
+
+    // A point light reaching about `range` units
+    fn point_light(range: f32) -> Light {
+        Light::new(LightKind::Point(Attenuation {
+            constant: 1.0,
+            linear: 255.0 / range,
+            quadratic: 0.0,
+        }))
+    }
+
+    fn spot_light(range: f32, angle: f32) -> Light {
+        Light::new(LightKind::Spot {
+            attenuation: Attenuation {
+                constant: 1.0,
+                linear: 255.0 / range,
+                quadratic: 0.0,
+            },
+            inner_angle: angle,
+            outer_angle: angle,
+        })
+    }
+
+    fn clusters_with_point_light(clusters: &LightClusters, light: u32) -> Vec<usize> {
+        (0..clusters.clusters().len())
+            .filter(|&i| clusters.point_lights(i).contains(&light))
+            .collect()
+    }
+
+    #[test]
+    fn slices_exponentially() {
+        let clusters = LightClusters::new([1, 1, 16], 0, 0);
+        assert!((clusters.slice_depth(&PROJECTION, 0) - PROJECTION.near).abs() < 1e-6);
+        assert!((clusters.slice_depth(&PROJECTION, 16) - PROJECTION.far).abs() < 1e-3);
+        for z in 0..16 {
+            let near = clusters.slice_depth(&PROJECTION, z);
+            let far = clusters.slice_depth(&PROJECTION, z + 1);
+            assert_eq!(z, clusters.slice(&PROJECTION, (near + far) / 2.0));
+            // Each slice is the same factor deeper than the one before
+            let ratio = (PROJECTION.far / PROJECTION.near).powf(1.0 / 16.0);
+            assert!((far / near - ratio).abs() < 1e-3);
+        }
+        assert_eq!(0, clusters.slice(&PROJECTION, 0.0));
+        assert_eq!(15, clusters.slice(&PROJECTION, 1000.0));
+    }
+
+    #[test]
+    fn has_at_least_one_cluster_each_way() {
+        let mut lights = LightList::default();
+        lights.push(point_light(1000.0), &Matrix4::identity());
+        let mut clusters = LightClusters::new([0, 4, 0], 8, 8);
+        assert_eq!([1, 4, 1], clusters.dimensions());
+        assert_eq!(0, clusters.slice(&PROJECTION, 50.0));
+        clusters.assign(
+            &PROJECTION,
+            Vector3::splat(0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::up(),
+            &lights,
+        );
+        assert_eq!(4, clusters_with_point_light(&clusters, 0).len());
+        clusters.assign_everywhere(&lights);
+        assert_eq!(4, clusters_with_point_light(&clusters, 0).len());
+    }
+
+    #[test]
+    fn assigns_point_lights_to_the_clusters_they_touch() {
+        let mut lights = LightList::default();
+        let position = Vector3::new(3.0, -2.0, -20.0);
+        lights.push(point_light(0.5), &Matrix4::translate(position));
+        // Behind the camera
+        lights.push(
+            point_light(0.5),
+            &Matrix4::translate(Vector3::new(0.0, 0.0, 5.0)),
+        );
+        // Lights the whole view
+        lights.push(point_light(1000.0), &Matrix4::identity());
+        let clusters = assign(&lights);
+
+        // x / -z = 0.15 and y / -z = -0.1 land in tiles 4 and 3 of 8 across [-1, 1]
+        let slice = clusters.slice(&PROJECTION, 20.0);
+        let home = clusters.index(4, 3, slice);
+        let touched = clusters_with_point_light(&clusters, 0);
+        assert!(touched.contains(&home));
+        // A half unit sphere 20 units away only spans a couple of clusters each way
+        assert!(touched.len() <= 8, "{:?}", touched);
+        for &i in &touched {
+            let z = i / 64;
+            let (x, y) = (i % 8, i / 8 % 8);
+            assert!((x as isize - 4).abs() <= 1 && (y as isize - 3).abs() <= 1);
+            assert!((z as isize - slice as isize).abs() <= 1);
+        }
+
+        assert!(clusters_with_point_light(&clusters, 1).is_empty());
+        assert_eq!(
+            clusters.clusters().len(),
+            clusters_with_point_light(&clusters, 2).len()
+        );
+    }
+
+    #[test]
+    fn assigns_spot_lights_by_cone() {
+        let mut lights = LightList::default();
+        // At the camera, shining straight ahead in a narrow cone
+        lights.push(spot_light(50.0, 0.05), &Matrix4::identity());
+        // Shining away from the view
+        let backwards = Matrix4::rotate_up(std::f32::consts::PI);
+        lights.push(spot_light(50.0, 0.05), &backwards);
+        let clusters = assign(&lights);
+
+        let slice = clusters.slice(&PROJECTION, 10.0);
+        let spot_lights = |x, y, z| clusters.spot_lights(clusters.index(x, y, z)).to_vec();
+        // The four tiles meeting at the center of the view
+        assert_eq!(vec![0], spot_lights(3, 3, slice));
+        assert_eq!(vec![0], spot_lights(4, 4, slice));
+        // Inside the cone's bounding sphere, but outside the cone
+        assert!(spot_lights(0, 0, slice).is_empty());
+        assert!(spot_lights(7, 4, slice).is_empty());
+        // Past its range
+        assert!(spot_lights(4, 4, 15).is_empty());
+        for i in 0..clusters.clusters().len() {
+            assert!(!clusters.spot_lights(i).contains(&1));
+        }
+    }
+
+    #[test]
+    fn respects_maximum_lights() {
+        let mut lights = LightList::default();
+        for _ in 0..3 {
+            lights.push(point_light(1000.0), &Matrix4::identity());
+            lights.push(spot_light(1000.0, 1.0), &Matrix4::identity());
+            lights.push(Light::new(LightKind::Directional), &Matrix4::identity());
+        }
+        let mut clusters = LightClusters::new([2, 2, 2], 2, 1);
+        clusters.assign_everywhere(&lights);
+        for i in 0..8 {
+            assert_eq!(&[0, 1], clusters.point_lights(i));
+            assert_eq!(&[0], clusters.spot_lights(i));
+        }
+        assert_eq!(&[0, 1, 0], clusters.indices());
+    }
+}
@@ -42,6 +42,8 @@ enum TriangleInputKind {
     Normal,
     TexCoord,
     Color,
+    Tangent,
+    Bitangent,
 }
 
 #[derive(Debug)]
@@ -89,6 +91,10 @@ pub struct ColladaReader {
 
     // Buffer to store colors
     colors: Vec<Vector4>,
+
+    // Buffers to store tangents and bitangents, if the file has them
+    tangents: Vec<Vector3>,
+    bitangents: Vec<Vector3>,
 }
 
 impl ColladaReader {
@@ -109,6 +115,8 @@ impl ColladaReader {
         self.normals.clear();
         self.tex_coords.clear();
         self.colors.clear();
+        self.tangents.clear();
+        self.bitangents.clear();
         self.push(State::Init);
 
         let mut xml_reader = EventReader::new_with_config(
@@ -339,6 +347,14 @@ impl ColladaReader {
                                         offset,
                                         kind: TriangleInputKind::Color,
                                     },
+                                    "TEXTANGENT" | "TANGENT" => TriangleInput {
+                                        offset,
+                                        kind: TriangleInputKind::Tangent,
+                                    },
+                                    "TEXBINORMAL" | "BINORMAL" => TriangleInput {
+                                        offset,
+                                        kind: TriangleInputKind::Bitangent,
+                                    },
                                     i => unimplemented!("{:?}", i),
                                 },
                             );
@@ -432,6 +448,34 @@ impl ColladaReader {
                                         }
                                         k => unimplemented!("{:?}", k),
                                     },
+                                    TriangleInputKind::Tangent => match &source.kind {
+                                        Some(SourceKind::FloatArray(tangents)) => {
+                                            let offset = index * 3;
+                                            self.tangents.push(
+                                                (
+                                                    tangents[offset],
+                                                    tangents[offset + 1],
+                                                    tangents[offset + 2],
+                                                )
+                                                    .into(),
+                                            );
+                                        }
+                                        k => unimplemented!("{:?}", k),
+                                    },
+                                    TriangleInputKind::Bitangent => match &source.kind {
+                                        Some(SourceKind::FloatArray(bitangents)) => {
+                                            let offset = index * 3;
+                                            self.bitangents.push(
+                                                (
+                                                    bitangents[offset],
+                                                    bitangents[offset + 1],
+                                                    bitangents[offset + 2],
+                                                )
+                                                    .into(),
+                                            );
+                                        }
+                                        k => unimplemented!("{:?}", k),
+                                    },
                                 }
                             }
                         }
@@ -461,11 +505,21 @@ impl ColladaReader {
             .zip(colors)
             .enumerate()
         {
-            mesh.add_vertex(StaticMaterialVertex::new(
-                position, normal, tex_coord, color,
-            ));
+            let mut vertex = StaticMaterialVertex::new(position, normal, tex_coord, color);
+            if let Some(&tangent) = self.tangents.get(i) {
+                // The handedness is whichever way the bitangent points
+                let handedness = match self.bitangents.get(i) {
+                    Some(&bitangent) if normal.cross(tangent).dot(bitangent) < 0.0 => -1.0,
+                    _ => 1.0,
+                };
+                vertex = vertex.with_tangent(tangent.normalized().widened(handedness));
+            }
+            mesh.add_vertex(vertex);
             mesh.add_index(i as u32);
         }
+        if self.tangents.len() < self.positions.len() {
+            mesh.generate_tangents();
+        }
         Ok(())
     }
 
@@ -602,4 +656,54 @@ mod test {
             .read_into(&mut cursor, &mut mesh)
             .expect("It should not fail to parse that!");
     }
+
+    #[test]
+    fn reads_tangents() {
+        let test = r##"
+<?xml version="1.0" encoding="utf-8"?>
+<COLLADA xmlns="http://www.collada.org/2005/11/COLLADASchema" version="1.4.1">
+  <library_geometries>
+    <geometry id="Plane-mesh" name="Plane">
+      <mesh>
+        <source id="Plane-mesh-positions">
+          <float_array id="Plane-mesh-positions-array" count="9">0 0 0 1 0 0 0 1 0</float_array>
+        </source>
+        <source id="Plane-mesh-normals">
+          <float_array id="Plane-mesh-normals-array" count="3">0 0 1</float_array>
+        </source>
+        <source id="Plane-mesh-map-0">
+          <float_array id="Plane-mesh-map-0-array" count="6">0 0 1 0 0 1</float_array>
+        </source>
+        <source id="Plane-mesh-map-0-tangents">
+          <float_array id="Plane-mesh-map-0-tangents-array" count="3">2 0 0</float_array>
+        </source>
+        <source id="Plane-mesh-map-0-binormals">
+          <float_array id="Plane-mesh-map-0-binormals-array" count="3">0 -1 0</float_array>
+        </source>
+        <vertices id="Plane-mesh-vertices">
+          <input semantic="POSITION" source="#Plane-mesh-positions"/>
+        </vertices>
+        <triangles count="1">
+          <input semantic="VERTEX" source="#Plane-mesh-vertices" offset="0"/>
+          <input semantic="NORMAL" source="#Plane-mesh-normals" offset="1"/>
+          <input semantic="TEXCOORD" source="#Plane-mesh-map-0" offset="2" set="0"/>
+          <input semantic="TEXTANGENT" source="#Plane-mesh-map-0-tangents" offset="3" set="0"/>
+          <input semantic="TEXBINORMAL" source="#Plane-mesh-map-0-binormals" offset="4" set="0"/>
+          <p>0 0 0 0 0 1 0 1 0 0 2 0 2 0 0</p>
+        </triangles>
+      </mesh>
+    </geometry>
+  </library_geometries>
+</COLLADA>
+        "##;
+
+        let mut mesh = StaticMaterialMesh::default();
+        ColladaReader::default()
+            .read_into(&mut Cursor::new(test), &mut mesh)
+            .unwrap();
+        // Taken from the file rather than generated, which would give a right handed tangent
+        for vertex in mesh.vertices() {
+            assert_eq!([1.0, 0.0, 0.0, -1.0], vertex.tangent().0);
+        }
+    }
 }
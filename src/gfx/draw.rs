@@ -0,0 +1,399 @@
+use crate::math::{Matrix4, Vector3};
+
+/// Identifies a mesh that was uploaded to whatever is doing the rendering.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct MeshId(pub u32);
+
+/// Passes are drawn in order.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Pass {
+    /// Drawn front to back so the depth test throws away as much as possible.
+    Opaque = 0,
+    /// Drawn back to front after everything opaque so blending comes out right.
+    Transparent = 1,
+}
+
+impl Default for Pass {
+    #[inline]
+    fn default() -> Pass {
+        Pass::Opaque
+    }
+}
+
+/// How a mesh gets shaded.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Pipeline {
+    StaticMaterial = 0,
+}
+
+impl Default for Pipeline {
+    #[inline]
+    fn default() -> Pipeline {
+        Pipeline::StaticMaterial
+    }
+}
+
+#[derive(Default, Debug, Copy, Clone)]
+pub struct DrawCommand {
+    pub mesh: MeshId,
+    /// The material, as added to whatever is doing the rendering.
+    pub material: u32,
+    pub model: Matrix4,
+    pub pass: Pass,
+    pub pipeline: Pipeline,
+    /// Filled in by `DrawList::sort`.
+    pub sort_key: u64,
+}
+
+impl DrawCommand {
+    /// An opaque static material draw.
+    #[inline]
+    pub fn new(mesh: MeshId, material: u32, model: Matrix4) -> DrawCommand {
+        DrawCommand {
+            mesh,
+            material,
+            model,
+            ..DrawCommand::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_pass(mut self, pass: Pass) -> DrawCommand {
+        self.pass = pass;
+        self
+    }
+
+    /// Where the model's origin ends up in the world.
+    #[inline]
+    pub fn position(&self) -> Vector3 {
+        self.model[3].narrowed()
+    }
+
+    /// Build the key commands are sorted by.
+    ///
+    /// From the most significant bits down, opaque draws are grouped by pipeline, mesh and
+    /// material and then sorted front to back. Mesh comes before material so every draw of a
+    /// mesh can be one instanced batch, since materials are per instance.
+    /// Transparent draws are sorted back to front first, since they have to be for blending
+    /// to work. Pass always comes first. Only the low bits of materials and meshes are used,
+    /// which at worst costs a few extra state changes.
+    pub fn compute_sort_key(&self, depth: f32) -> u64 {
+        let pass = self.pass as u64 & 0b11;
+        let pipeline = self.pipeline as u64 & 0x3F;
+        let material = self.material as u64 & 0xFFFF;
+        let mesh = self.mesh.0 as u64 & 0xFFFF;
+        // The bits of a positive float sort the same as the float itself
+        let depth = (depth.max(0.0).to_bits() >> 8) as u64;
+        match self.pass {
+            Pass::Opaque => pass << 62 | pipeline << 56 | mesh << 40 | material << 24 | depth,
+            Pass::Transparent => {
+                let depth = !depth & 0xFF_FFFF;
+                pass << 62 | depth << 38 | pipeline << 32 | material << 16 | mesh
+            }
+        }
+    }
+}
+
+/// Everything to draw in a frame, in the order it should be drawn.
+#[derive(Debug, Default)]
+pub struct DrawList {
+    commands: Vec<DrawCommand>,
+}
+
+impl DrawList {
+    #[inline]
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    #[inline]
+    pub fn push(&mut self, command: DrawCommand) {
+        self.commands.push(command);
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    #[inline]
+    pub fn commands(&self) -> &[DrawCommand] {
+        &self.commands
+    }
+
+    /// Fill in the sort keys for a viewer at `eye` looking along `direction` and sort.
+    ///
+    /// The sort is stable so commands with equal keys keep the order they were pushed in.
+    pub fn sort(&mut self, eye: Vector3, direction: Vector3) {
+        for command in &mut self.commands {
+            let depth = (command.position() - eye).dot(direction);
+            command.sort_key = command.compute_sort_key(depth);
+        }
+        self.commands.sort_by_key(|command| command.sort_key);
+    }
+
+    /// The commands broken into the state changes and draws needed to render them.
+    ///
+    /// State is only set when it differs from the command before.
+    #[inline]
+    pub fn steps(&self) -> DrawSteps {
+        DrawSteps {
+            commands: &self.commands,
+            index: 0,
+            pass: None,
+            pipeline: None,
+            material: None,
+            mesh: None,
+        }
+    }
+
+    /// Runs of commands that can be drawn with a single instanced draw.
+    ///
+    /// Commands are batched when they are next to each other and share a pass, pipeline and
+    /// mesh. Materials can differ within a batch. Sort first to get the most out of this.
+    #[inline]
+    pub fn batches(&self) -> DrawBatches {
+        DrawBatches {
+            commands: &self.commands,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum DrawStep<'a> {
+    Pass(Pass),
+    Pipeline(Pipeline),
+    Material(u32),
+    Mesh(MeshId),
+    Draw(&'a DrawCommand),
+}
+
+pub struct DrawSteps<'a> {
+    commands: &'a [DrawCommand],
+    index: usize,
+    pass: Option<Pass>,
+    pipeline: Option<Pipeline>,
+    material: Option<u32>,
+    mesh: Option<MeshId>,
+}
+
+impl<'a> Iterator for DrawSteps<'a> {
+    type Item = DrawStep<'a>;
+
+    fn next(&mut self) -> Option<DrawStep<'a>> {
+        let command = self.commands.get(self.index)?;
+        if self.pass != Some(command.pass) {
+            self.pass = Some(command.pass);
+            // Backends may reset state between passes, so start afresh
+            self.pipeline = None;
+            self.material = None;
+            self.mesh = None;
+            return Some(DrawStep::Pass(command.pass));
+        }
+        if self.pipeline != Some(command.pipeline) {
+            self.pipeline = Some(command.pipeline);
+            return Some(DrawStep::Pipeline(command.pipeline));
+        }
+        if self.material != Some(command.material) {
+            self.material = Some(command.material);
+            return Some(DrawStep::Material(command.material));
+        }
+        if self.mesh != Some(command.mesh) {
+            self.mesh = Some(command.mesh);
+            return Some(DrawStep::Mesh(command.mesh));
+        }
+        self.index += 1;
+        Some(DrawStep::Draw(command))
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DrawBatch<'a> {
+    pub pass: Pass,
+    pub pipeline: Pipeline,
+    pub mesh: MeshId,
+    pub commands: &'a [DrawCommand],
+}
+
+pub struct DrawBatches<'a> {
+    commands: &'a [DrawCommand],
+}
+
+impl<'a> Iterator for DrawBatches<'a> {
+    type Item = DrawBatch<'a>;
+
+    fn next(&mut self) -> Option<DrawBatch<'a>> {
+        let first = self.commands.first()?;
+        let len = self
+            .commands
+            .iter()
+            .take_while(|command| {
+                command.pass == first.pass
+                    && command.pipeline == first.pipeline
+                    && command.mesh == first.mesh
+            })
+            .count();
+        let (commands, rest) = self.commands.split_at(len);
+        self.commands = rest;
+        Some(DrawBatch {
+            pass: first.pass,
+            pipeline: first.pipeline,
+            mesh: first.mesh,
+            commands,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(z: f32) -> Matrix4 {
+        Matrix4::translate(Vector3::new(0.0, 0.0, z))
+    }
+
+    fn sorted(commands: &[DrawCommand]) -> Vec<(u32, u32, f32)> {
+        let mut list = DrawList::default();
+        for command in commands {
+            list.push(*command);
+        }
+        // Looking down -z from the origin, so depth is -z
+        list.sort(Vector3::splat(0.0), Vector3::new(0.0, 0.0, -1.0));
+        list.commands()
+            .iter()
+            .map(|c| (c.material, c.mesh.0, -c.position().z()))
+            .collect()
+    }
+
+    #[test]
+    fn groups_opaque_draws_then_sorts_front_to_back() {
+        let commands = [
+            DrawCommand::new(MeshId(1), 2, at(-5.0)),
+            DrawCommand::new(MeshId(0), 1, at(-9.0)),
+            DrawCommand::new(MeshId(1), 2, at(-1.0)),
+            DrawCommand::new(MeshId(0), 1, at(-3.0)),
+            DrawCommand::new(MeshId(2), 1, at(-2.0)),
+        ];
+        assert_eq!(
+            vec![
+                (1, 0, 3.0),
+                (1, 0, 9.0),
+                (2, 1, 1.0),
+                (2, 1, 5.0),
+                (1, 2, 2.0)
+            ],
+            sorted(&commands)
+        );
+    }
+
+    #[test]
+    fn draws_transparent_last_back_to_front() {
+        let commands = [
+            DrawCommand::new(MeshId(0), 0, at(-4.0)).with_pass(Pass::Transparent),
+            DrawCommand::new(MeshId(1), 1, at(-100.0)),
+            DrawCommand::new(MeshId(1), 3, at(-8.0)).with_pass(Pass::Transparent),
+            DrawCommand::new(MeshId(0), 0, at(-6.0)).with_pass(Pass::Transparent),
+        ];
+        assert_eq!(
+            vec![(1, 1, 100.0), (3, 1, 8.0), (0, 0, 6.0), (0, 0, 4.0)],
+            sorted(&commands)
+        );
+    }
+
+    #[test]
+    fn sorts_stably() {
+        let mut commands = Vec::new();
+        for i in 0..8 {
+            let mut model = at(-2.0);
+            // Tag each command so its original position can be recovered
+            model[0][1] = i as f32;
+            commands.push(DrawCommand::new(MeshId(0), 0, model));
+        }
+        let mut list = DrawList::default();
+        for command in &commands {
+            list.push(*command);
+        }
+        list.sort(Vector3::splat(0.0), Vector3::new(0.0, 0.0, -1.0));
+        for (i, command) in list.commands().iter().enumerate() {
+            assert_eq!(i as f32, command.model[0][1]);
+        }
+    }
+
+    #[test]
+    fn elides_state_changes() {
+        let mut list = DrawList::default();
+        list.push(DrawCommand::new(MeshId(0), 0, at(-1.0)));
+        list.push(DrawCommand::new(MeshId(0), 0, at(-2.0)));
+        list.push(DrawCommand::new(MeshId(1), 0, at(-3.0)));
+        list.push(DrawCommand::new(MeshId(1), 1, at(-4.0)));
+        list.push(DrawCommand::new(MeshId(1), 1, at(-5.0)).with_pass(Pass::Transparent));
+        list.sort(Vector3::splat(0.0), Vector3::new(0.0, 0.0, -1.0));
+
+        let steps: Vec<_> = list
+            .steps()
+            .map(|step| match step {
+                DrawStep::Draw(command) => format!("draw {}", -command.position().z()),
+                step => format!("{:?}", step),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                "Pass(Opaque)",
+                "Pipeline(StaticMaterial)",
+                "Material(0)",
+                "Mesh(MeshId(0))",
+                "draw 1",
+                "draw 2",
+                "Mesh(MeshId(1))",
+                "draw 3",
+                "Material(1)",
+                "draw 4",
+                "Pass(Transparent)",
+                "Pipeline(StaticMaterial)",
+                "Material(1)",
+                "Mesh(MeshId(1))",
+                "draw 5",
+            ],
+            steps
+        );
+        assert_eq!(0, DrawList::default().steps().count());
+    }
+
+    #[test]
+    fn batches_commands_sharing_a_mesh() {
+        let mut list = DrawList::default();
+        for i in 0..4 {
+            list.push(DrawCommand::new(MeshId(0), i % 2, at(-(i as f32) - 1.0)));
+            list.push(DrawCommand::new(MeshId(1), 0, at(-(i as f32) - 1.0)));
+        }
+        list.push(DrawCommand::new(MeshId(0), 0, at(-2.0)).with_pass(Pass::Transparent));
+        list.push(DrawCommand::new(MeshId(1), 0, at(-3.0)).with_pass(Pass::Transparent));
+        list.push(DrawCommand::new(MeshId(0), 0, at(-4.0)).with_pass(Pass::Transparent));
+        list.sort(Vector3::splat(0.0), Vector3::new(0.0, 0.0, -1.0));
+
+        let batches: Vec<_> = list
+            .batches()
+            .map(|batch| (batch.pass, batch.mesh.0, batch.commands.len()))
+            .collect();
+        assert_eq!(
+            vec![
+                // Different materials don't split a batch
+                (Pass::Opaque, 0, 4),
+                (Pass::Opaque, 1, 4),
+                // Transparent draws can't be pulled out of order to batch them
+                (Pass::Transparent, 0, 1),
+                (Pass::Transparent, 1, 1),
+                (Pass::Transparent, 0, 1),
+            ],
+            batches
+        );
+        let total: usize = list.batches().map(|batch| batch.commands.len()).sum();
+        assert_eq!(list.len(), total);
+        assert_eq!(0, DrawList::default().batches().count());
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    gfx::PerspectiveProjection,
+    gfx::{OrthographicProjection, PerspectiveProjection},
     math::{Vector2, Vector3},
 };
 
@@ -20,8 +20,15 @@ pub struct Frustum {
     // It is constant since the slope is constant.
     sphere_factor: Vector2,
 
-    tan_fov: f32,
-    aspect_ratio: f32,
+    // The half width/height of the frustum at a distance z along the view direction is
+    // `half_extent + slope * z` (measured from `center + center_slope * z`).
+    // Perspective frustums only have a slope, orthographic ones only have an extent.
+    // Only off-center perspective frustums have a center slope.
+    slope: Vector2,
+    half_extent: Vector2,
+    center: Vector2,
+    center_slope: Vector2,
+
     near: f32,
     far: f32,
 }
@@ -40,21 +47,107 @@ impl Frustum {
         frustum
     }
 
+    #[inline]
+    pub fn orthographic(
+        projection: &OrthographicProjection,
+        position: Vector3,
+        at: Vector3,
+        up: Vector3,
+    ) -> Frustum {
+        let mut frustum = Frustum::default();
+        frustum.update_orthographic_projection(projection);
+        frustum.update_look_at(position, at, up);
+        frustum
+    }
+
+    /// A perspective frustum whose sides aren't necessarily symmetric, e.g. one cluster of a
+    /// bigger frustum.
+    ///
+    /// The sides are given as slopes (x / z and y / z, like the tangent of the angle from the
+    /// view direction) of the left and bottom (`min_slope`) and right and top (`max_slope`)
+    /// planes.
+    #[inline]
+    pub fn off_center(
+        min_slope: Vector2,
+        max_slope: Vector2,
+        near: f32,
+        far: f32,
+        position: Vector3,
+        at: Vector3,
+        up: Vector3,
+    ) -> Frustum {
+        let mut frustum = Frustum::default();
+        frustum.update_off_center_projection(min_slope, max_slope, near, far);
+        frustum.update_look_at(position, at, up);
+        frustum
+    }
+
     pub fn update_projection(&mut self, projection: &PerspectiveProjection) {
-        self.aspect_ratio = projection.aspect_ratio;
         self.near = projection.near;
         self.far = projection.far;
-        self.tan_fov = projection.fov.tan();
 
-        let fov_x = (self.tan_fov * projection.aspect_ratio).atan();
-        self.sphere_factor = (1.0 / fov_x.cos(), 1.0 / projection.fov.cos()).into();
+        // The projection's fov is the full vertical angle, but the planes sit at half of it
+        let half_fov_y = projection.fov / 2.0;
+        let tan_fov = half_fov_y.tan();
+        self.slope = (tan_fov * projection.aspect_ratio, tan_fov).into();
+        self.half_extent = Vector2::default();
+        self.center = Vector2::default();
+        self.center_slope = Vector2::default();
+
+        let half_fov_x = (tan_fov * projection.aspect_ratio).atan();
+        self.sphere_factor = (1.0 / half_fov_x.cos(), 1.0 / half_fov_y.cos()).into();
+    }
+
+    pub fn update_off_center_projection(
+        &mut self,
+        min_slope: Vector2,
+        max_slope: Vector2,
+        near: f32,
+        far: f32,
+    ) {
+        self.near = near;
+        self.far = far;
+
+        self.slope = (max_slope - min_slope) / 2.0;
+        self.half_extent = Vector2::default();
+        self.center = Vector2::default();
+        self.center_slope = (max_slope + min_slope) / 2.0;
+
+        // The steeper of each pair of planes needs the most fudging, so use it for both
+        let steepest = |min: f32, max: f32| (1.0 + min.abs().max(max.abs()).powi(2)).sqrt();
+        self.sphere_factor = (
+            steepest(min_slope.x(), max_slope.x()),
+            steepest(min_slope.y(), max_slope.y()),
+        )
+            .into();
+    }
+
+    pub fn update_orthographic_projection(&mut self, projection: &OrthographicProjection) {
+        self.near = projection.near;
+        self.far = projection.far;
+
+        self.slope = Vector2::default();
+        self.half_extent = (
+            (projection.right - projection.left) / 2.0,
+            (projection.top - projection.bottom) / 2.0,
+        )
+            .into();
+        self.center = (
+            (projection.right + projection.left) / 2.0,
+            (projection.top + projection.bottom) / 2.0,
+        )
+            .into();
+        self.center_slope = Vector2::default();
+
+        // The side planes are parallel to the view direction so no fudging is needed
+        self.sphere_factor = (1.0, 1.0).into();
     }
 
     pub fn update_look_at(&mut self, position: Vector3, at: Vector3, up: Vector3) {
         self.position = position;
         self.z = (position - at).normalized();
-        self.x = (up * self.z).normalized();
-        self.y = self.z * self.x;
+        self.x = up.cross(self.z).normalized();
+        self.y = self.z.cross(self.x);
     }
 
     pub fn point_inside(&self, position: Vector3) -> bool {
@@ -67,16 +160,16 @@ impl Frustum {
             return false;
         }
 
-        // Find the width/2 (via height*aspect ratio) of the frustum at z and check if we're inside
-        let x = to_position.dot(self.x);
-        let half_width_at_z = z * self.tan_fov * self.aspect_ratio;
+        // Find the width/2 of the frustum at z and check if we're inside
+        let x = to_position.dot(self.x) - self.center.x() - z * self.center_slope.x();
+        let half_width_at_z = self.half_extent.x() + z * self.slope.x();
         if x > half_width_at_z || x < -half_width_at_z {
             return false;
         }
 
         // Find the height/2 of the frustum at z and check if we're inside
-        let y = to_position.dot(self.y);
-        let half_height_at_z = z * self.tan_fov;
+        let y = to_position.dot(self.y) - self.center.y() - z * self.center_slope.y();
+        let half_height_at_z = self.half_extent.y() + z * self.slope.y();
         if y > half_height_at_z || y < -half_height_at_z {
             return false;
         }
@@ -97,8 +190,8 @@ impl Frustum {
         let test_distance = self.sphere_factor * radius;
 
         // Then y (using the sphere-factor)
-        let y = to_position.dot(self.y);
-        let half_height_at_z = z * self.tan_fov;
+        let y = to_position.dot(self.y) - self.center.y() - z * self.center_slope.y();
+        let half_height_at_z = self.half_extent.y() + z * self.slope.y();
         if y > half_height_at_z + test_distance.y() || y < -half_height_at_z - test_distance.y() {
             return false;
         }
@@ -119,19 +212,83 @@ impl Frustum {
         let test_distance = self.sphere_factor * radius;
 
         // Now for x (using the sphere-factor)
-        let x = to_position.dot(self.x);
-        let half_width_at_z = z * self.tan_fov * self.aspect_ratio;
+        let x = to_position.dot(self.x) - self.center.x() - z * self.center_slope.x();
+        let half_width_at_z = self.half_extent.x() + z * self.slope.x();
         if x > half_width_at_z + test_distance.x() || x < -half_width_at_z - test_distance.x() {
             return false;
         }
 
         // Then y (using the sphere-factor)
-        let y = to_position.dot(self.y);
-        let half_height_at_z = z * self.tan_fov;
+        let y = to_position.dot(self.y) - self.center.y() - z * self.center_slope.y();
+        let half_height_at_z = self.half_extent.y() + z * self.slope.y();
         if y > half_height_at_z + test_distance.y() || y < -half_height_at_z - test_distance.y() {
             return false;
         }
 
         true
     }
+
+    /// The corners of the near plane then the far plane, each going bottom left, bottom right,
+    /// top left and top right.
+    pub fn corners(&self) -> [Vector3; 8] {
+        let mut corners = [Vector3::default(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let z = if i < 4 { self.near } else { self.far };
+            let sx = if i % 2 == 0 { -1.0 } else { 1.0 };
+            let sy = if i % 4 < 2 { -1.0 } else { 1.0 };
+            let x = self.center.x()
+                + z * self.center_slope.x()
+                + sx * (self.half_extent.x() + z * self.slope.x());
+            let y = self.center.y()
+                + z * self.center_slope.y()
+                + sy * (self.half_extent.y() + z * self.slope.y());
+            *corner = self.position + self.x * x + self.y * y - self.z * z;
+        }
+        corners
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn off_center_frustums() {
+        let (position, at, up) = (
+            Vector3::splat(0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::up(),
+        );
+        // The right half of a 90 degree frustum
+        let frustum = Frustum::off_center(
+            Vector2::new(0.0, -1.0),
+            Vector2::new(1.0, 1.0),
+            1.0,
+            10.0,
+            position,
+            at,
+            up,
+        );
+        assert!(frustum.point_inside(Vector3::new(0.5, 0.0, -2.0)));
+        assert!(!frustum.point_inside(Vector3::new(-0.5, 0.0, -2.0)));
+        assert!(!frustum.point_inside(Vector3::new(2.5, 0.0, -2.0)));
+        assert!(frustum.sphere_inside(Vector3::new(-0.5, 0.0, -2.0), 1.0));
+        assert!(!frustum.sphere_inside(Vector3::new(-1.5, 0.0, -2.0), 1.0));
+
+        let corners = frustum.corners();
+        let expected = [
+            (0.0, -1.0, -1.0),
+            (1.0, -1.0, -1.0),
+            (0.0, 1.0, -1.0),
+            (1.0, 1.0, -1.0),
+            (0.0, -10.0, -10.0),
+            (10.0, -10.0, -10.0),
+            (0.0, 10.0, -10.0),
+            (10.0, 10.0, -10.0),
+        ];
+        for (corner, expected) in corners.iter().zip(expected.iter()) {
+            let expected: Vector3 = (*expected).into();
+            assert!((*corner - expected).length() < 1e-5);
+        }
+    }
 }
@@ -0,0 +1,171 @@
+use crate::math::{Matrix4, Vector3};
+
+/// How quickly a light fades with distance: `1 / (constant + linear * d + quadratic * d^2)`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Default for Attenuation {
+    /// Fades out at roughly 90 units.
+    #[inline]
+    fn default() -> Attenuation {
+        Attenuation {
+            constant: 1.0,
+            linear: 0.09,
+            quadratic: 0.032,
+        }
+    }
+}
+
+/// How much of a light is left at its range. Past this it stops being noticeable.
+pub const LIGHT_CUTOFF: f32 = 1.0 / 256.0;
+
+impl Attenuation {
+    /// How far the light reaches before it fades below `LIGHT_CUTOFF`.
+    pub fn range(&self) -> f32 {
+        // Solve constant + linear * d + quadratic * d^2 = 1 / LIGHT_CUTOFF for d
+        let c = self.constant - 1.0 / LIGHT_CUTOFF;
+        if self.quadratic > 0.0 {
+            let discriminant = self.linear * self.linear - 4.0 * self.quadratic * c;
+            (-self.linear + discriminant.sqrt()) / (2.0 * self.quadratic)
+        } else if self.linear > 0.0 {
+            -c / self.linear
+        } else {
+            f32::INFINITY
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightKind {
+    /// Infinitely far away, so only the direction matters (e.g. the sun).
+    Directional,
+    /// Shines in every direction from a point.
+    Point(Attenuation),
+    /// Shines in a cone from a point. The angles (in radians) are measured from the direction
+    /// to the edge of the cone, and the light fades out between them.
+    Spot {
+        attenuation: Attenuation,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+/// A light, shining down its local -z axis like the camera looks.
+#[derive(Debug, Copy, Clone)]
+pub struct Light {
+    pub kind: LightKind,
+    pub ambient: Vector3,
+    pub diffuse: Vector3,
+    pub specular: Vector3,
+}
+
+impl Light {
+    /// A white light with a little ambient.
+    #[inline]
+    pub fn new(kind: LightKind) -> Light {
+        Light {
+            kind,
+            ambient: Vector3::splat(0.2),
+            diffuse: Vector3::splat(1.0),
+            specular: Vector3::splat(1.0),
+        }
+    }
+}
+
+/// A light placed in the world.
+#[derive(Debug, Copy, Clone)]
+pub struct PlacedLight {
+    pub light: Light,
+    pub position: Vector3,
+    /// The unit vector the light shines along.
+    pub direction: Vector3,
+}
+
+/// Every light affecting a frame.
+#[derive(Debug, Default)]
+pub struct LightList {
+    lights: Vec<PlacedLight>,
+}
+
+impl LightList {
+    #[inline]
+    pub fn clear(&mut self) {
+        self.lights.clear();
+    }
+
+    /// Add a light placed by a (local-to-world) matrix.
+    #[inline]
+    pub fn push(&mut self, light: Light, world: &Matrix4) {
+        self.lights.push(PlacedLight {
+            light,
+            position: world[3].narrowed(),
+            direction: -world[2].narrowed().normalized(),
+        });
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    #[inline]
+    pub fn lights(&self) -> &[PlacedLight] {
+        &self.lights
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{gfx::Transform, math::Quaternion};
+    use std::f32::consts::FRAC_PI_2;
+
+    #[test]
+    fn places_lights_from_world_matrix() {
+        let transform = Transform {
+            position: Vector3::new(1.0, 2.0, 3.0),
+            scale: Vector3::splat(4.0),
+            rotation: Quaternion::from_angle_right(-FRAC_PI_2),
+        };
+        let mut lights = LightList::default();
+        lights.push(Light::new(LightKind::Directional), &(&transform).into());
+
+        let placed = lights.lights()[0];
+        assert!((placed.position - transform.position).length() < 1e-5);
+        // Tipped forward to shine straight down, regardless of the scale
+        assert!((placed.direction - Vector3::new(0.0, -1.0, 0.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn attenuation_range() {
+        let attenuation = Attenuation::default();
+        let range = attenuation.range();
+        let at_range = 1.0
+            / (attenuation.constant
+                + attenuation.linear * range
+                + attenuation.quadratic * range * range);
+        assert!((at_range - LIGHT_CUTOFF).abs() < 1e-6);
+
+        let linear = Attenuation {
+            constant: 1.0,
+            linear: 1.0,
+            quadratic: 0.0,
+        };
+        assert_eq!(255.0, linear.range());
+        let constant = Attenuation {
+            constant: 1.0,
+            linear: 0.0,
+            quadratic: 0.0,
+        };
+        assert_eq!(f32::INFINITY, constant.range());
+    }
+}
@@ -0,0 +1,343 @@
+use std::f32::consts::PI;
+
+use crate::math::Vector3;
+
+// The shaders get these from shader_src/material.h, which `glsl_header` generates
+/// How much light a surface reflects head on when it isn't metal.
+pub const DIELECTRIC_F0: f32 = 0.04;
+/// Perfectly smooth surfaces have infinitely small highlights, so roughness is kept above this.
+pub const MIN_ROUGHNESS: f32 = 0.04;
+/// The Blinn-Phong specular exponent.
+pub const BLINN_PHONG_SHININESS: f32 = 16.0;
+
+/// How a material reacts to light.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ShadingModel {
+    /// Blinn-Phong highlights scaled by the specular map.
+    BlinnPhong = 0,
+    /// A Cook-Torrance BRDF driven by the metallic/roughness map.
+    MetallicRoughness = 1,
+}
+
+impl Default for ShadingModel {
+    #[inline]
+    fn default() -> ShadingModel {
+        ShadingModel::BlinnPhong
+    }
+}
+
+/// The bits each texture index is packed into.
+pub const TEXTURE_INDEX_BITS: u32 = 8;
+/// How many textures a texture index can pick from.
+pub const MAX_TEXTURE_LAYERS: usize = 1 << TEXTURE_INDEX_BITS;
+const TEXTURE_INDEX_MASK: u32 = MAX_TEXTURE_LAYERS as u32 - 1;
+/// The bits the shading model is packed into, below the metallic/roughness index.
+const SHADING_MODEL_BITS: u32 = 8;
+
+/// What a draw looks like: the texture index of each of its maps and how they're lit.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Material {
+    /// The albedo for metallic/roughness materials.
+    pub diffuse: u32,
+    /// Only used by Blinn-Phong materials.
+    pub specular: u32,
+    pub emissive: u32,
+    pub normal: u32,
+    /// Roughness in green and metalness in blue, like glTF. Only used by metallic/roughness
+    /// materials.
+    pub metallic_roughness: u32,
+    pub shading: ShadingModel,
+}
+
+impl Material {
+    /// A Blinn-Phong material with every map at the same texture index.
+    #[inline]
+    pub fn blinn_phong(layer: u32) -> Material {
+        Material {
+            diffuse: layer,
+            specular: layer,
+            emissive: layer,
+            normal: layer,
+            metallic_roughness: layer,
+            shading: ShadingModel::BlinnPhong,
+        }
+    }
+
+    /// A metallic/roughness material with every map at the same texture index.
+    #[inline]
+    pub fn metallic_roughness(layer: u32) -> Material {
+        Material {
+            shading: ShadingModel::MetallicRoughness,
+            ..Material::blinn_phong(layer)
+        }
+    }
+
+    /// The diffuse, specular, emissive and normal layers.
+    #[inline]
+    pub fn layers(&self) -> [u32; 4] {
+        [self.diffuse, self.specular, self.emissive, self.normal]
+    }
+
+    /// The layers packed the way the static material shaders unpack them. Each layer has to
+    /// be under `MAX_TEXTURE_LAYERS`.
+    #[inline]
+    pub fn pack_texture_indices(&self) -> u32 {
+        pack_texture_indices(self.layers())
+    }
+
+    /// The shading model with the metallic/roughness layer above it, the way the static
+    /// material shaders unpack them. The layer has to be under `MAX_TEXTURE_LAYERS`.
+    #[inline]
+    pub fn pack_shading(&self) -> u32 {
+        debug_assert!((self.metallic_roughness as usize) < MAX_TEXTURE_LAYERS);
+        self.shading as u32 | (self.metallic_roughness & TEXTURE_INDEX_MASK) << SHADING_MODEL_BITS
+    }
+}
+
+/// Pack the diffuse, specular, emissive and normal layers into `TEXTURE_INDEX_BITS` each, from
+/// the lowest bits up.
+#[inline]
+pub fn pack_texture_indices(layers: [u32; 4]) -> u32 {
+    debug_assert!(layers
+        .iter()
+        .all(|&layer| (layer as usize) < MAX_TEXTURE_LAYERS));
+    layers.iter().enumerate().fold(0, |packed, (i, &layer)| {
+        packed | (layer & TEXTURE_INDEX_MASK) << (i as u32 * TEXTURE_INDEX_BITS)
+    })
+}
+
+/// The reverse of `pack_texture_indices`.
+#[inline]
+pub fn unpack_texture_indices(packed: u32) -> [u32; 4] {
+    let mut layers = [0; 4];
+    for (i, layer) in layers.iter_mut().enumerate() {
+        *layer = (packed >> (i as u32 * TEXTURE_INDEX_BITS)) & TEXTURE_INDEX_MASK;
+    }
+    layers
+}
+
+/// The GLSL the static material shaders include as shader_src/material.h, so they share the
+/// constants and the texture index and shading packing above.
+pub fn glsl_header() -> String {
+    let shifts: Vec<String> = (0..4)
+        .map(|i| (i * TEXTURE_INDEX_BITS).to_string())
+        .collect();
+    format!(
+        "\
+// Generated by material::glsl_header in src/gfx/material.rs. Don't edit it, run the material
+// tests with DTH_WRITE_SHADER_HEADERS=1 to update it instead.
+const uint SHADING_BLINN_PHONG = {blinn_phong};
+const uint SHADING_METALLIC_ROUGHNESS = {metallic_roughness};
+const float DIELECTRIC_F0 = {dielectric_f0:?};
+const float MIN_ROUGHNESS = {min_roughness:?};
+const float BLINN_PHONG_SHININESS = {shininess:?};
+const uint MAX_TEXTURE_LAYERS = {max_layers};
+
+// The diffuse, specular, emissive and normal texture indices of a material
+uvec4 unpack_texture_indices(uint packed) {{
+    return (uvec4(packed) >> uvec4({shifts})) & {mask}u;
+}}
+
+// The shading model and metallic/roughness texture index of a material
+uvec2 unpack_shading(uint packed) {{
+    return uvec2(packed & {shading_mask}u, (packed >> {shading_bits}) & {mask}u);
+}}
+",
+        blinn_phong = ShadingModel::BlinnPhong as u32,
+        metallic_roughness = ShadingModel::MetallicRoughness as u32,
+        dielectric_f0 = DIELECTRIC_F0,
+        min_roughness = MIN_ROUGHNESS,
+        shininess = BLINN_PHONG_SHININESS,
+        max_layers = MAX_TEXTURE_LAYERS,
+        shifts = shifts.join(", "),
+        mask = TEXTURE_INDEX_MASK,
+        shading_mask = (1 << SHADING_MODEL_BITS) - 1,
+        shading_bits = SHADING_MODEL_BITS,
+    )
+}
+
+/// The GGX (Trowbridge-Reitz) normal distribution: how many microfacets face along the
+/// halfway vector.
+#[inline]
+pub fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+/// How many microfacets are visible from one direction (Schlick-GGX, remapped for direct
+/// lighting).
+#[inline]
+pub fn geometry_schlick_ggx(n_dot_v: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    n_dot_v / (n_dot_v * (1.0 - k) + k)
+}
+
+/// How many microfacets are visible from both the view and the light.
+#[inline]
+pub fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness)
+}
+
+/// How much light is reflected rather than refracted at `cos_theta` from head on.
+#[inline]
+pub fn fresnel_schlick(cos_theta: f32, f0: Vector3) -> Vector3 {
+    let t = (1.0 - cos_theta).clamp(0.0, 1.0).powi(5);
+    f0 + (Vector3::splat(1.0) - f0) * t
+}
+
+/// The light reflected towards `view` by a light of `radiance` shining from `light`, following
+/// the metallic/roughness model in static_material.frag.glsl. All directions point away from
+/// the surface and are unit length.
+pub fn cook_torrance(
+    normal: Vector3,
+    view: Vector3,
+    light: Vector3,
+    radiance: Vector3,
+    albedo: Vector3,
+    metallic: f32,
+    roughness: f32,
+) -> Vector3 {
+    let roughness = roughness.max(MIN_ROUGHNESS);
+    let halfway = (view + light).normalized();
+    let n_dot_v = normal.dot(view).max(0.0);
+    let n_dot_l = normal.dot(light).max(0.0);
+    let n_dot_h = normal.dot(halfway).max(0.0);
+
+    // Metals tint their reflections and don't have a diffuse part
+    let f0 = Vector3::splat(DIELECTRIC_F0) * (1.0 - metallic) + albedo * metallic;
+    let fresnel = fresnel_schlick(halfway.dot(view).max(0.0), f0);
+    let specular = fresnel
+        * (distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness)
+            / (4.0 * n_dot_v * n_dot_l).max(1e-4));
+    let diffuse = (Vector3::splat(1.0) - fresnel) * (1.0 - metallic) * albedo / PI;
+
+    (diffuse + specular) * radiance * n_dot_l
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn approx(a: f32, b: f32, epsilon: f32) -> bool {
+        (a - b).abs() <= epsilon
+    }
+
+    #[test]
+    fn shader_header_is_up_to_date() {
+        let header = glsl_header();
+        if std::env::var_os("DTH_WRITE_SHADER_HEADERS").is_some() {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/shader_src/material.h");
+            std::fs::write(path, &header).unwrap();
+            return;
+        }
+        assert_eq!(
+            header,
+            include_str!("../../shader_src/material.h"),
+            "shader_src/material.h is out of date, run the material tests with \
+             DTH_WRITE_SHADER_HEADERS=1 to update it"
+        );
+    }
+
+    #[test]
+    fn packs_texture_indices() {
+        let material = Material {
+            diffuse: 1,
+            specular: 2,
+            emissive: 3,
+            normal: 255,
+            metallic_roughness: 4,
+            shading: ShadingModel::BlinnPhong,
+        };
+        let packed = material.pack_texture_indices();
+        assert_eq!(0xFF03_0201, packed);
+        assert_eq!(material.layers(), unpack_texture_indices(packed));
+        // Same as the bytes of the old four u8 layout
+        assert_eq!([1, 2, 3, 255], packed.to_le_bytes());
+
+        assert_eq!(
+            [7; 4],
+            unpack_texture_indices(Material::metallic_roughness(7).pack_texture_indices())
+        );
+    }
+
+    #[test]
+    fn packs_shading() {
+        assert_eq!(0x0000, Material::blinn_phong(0).pack_shading());
+        let material = Material {
+            metallic_roughness: 255,
+            ..Material::metallic_roughness(3)
+        };
+        assert_eq!(0xFF01, material.pack_shading());
+        // The metallic/roughness map can be in a layer of its own
+        assert_eq!(
+            [3; 4],
+            unpack_texture_indices(material.pack_texture_indices())
+        );
+    }
+
+    #[test]
+    fn ggx_is_normalized() {
+        // The projected microfacet area has to add up to the macro surface's
+        for &roughness in &[0.2, 0.5, 1.0] {
+            let steps = 20000;
+            let step = (PI / 2.0) / steps as f32;
+            let integral: f32 = (0..steps)
+                .map(|i| {
+                    let theta = (i as f32 + 0.5) * step;
+                    let cos = theta.cos();
+                    distribution_ggx(cos, roughness) * cos * theta.sin() * step * 2.0 * PI
+                })
+                .sum();
+            assert!(approx(1.0, integral, 1e-2), "{} {}", roughness, integral);
+        }
+    }
+
+    #[test]
+    fn fresnel_goes_from_f0_to_white() {
+        let f0 = Vector3::new(0.04, 0.5, 1.0);
+        assert_eq!(f0, fresnel_schlick(1.0, f0));
+        let grazing = fresnel_schlick(0.0, f0);
+        assert!((grazing - Vector3::splat(1.0)).length() < 1e-6);
+    }
+
+    #[test]
+    fn cook_torrance_reference() {
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let radiance = Vector3::splat(1.0);
+        let albedo = Vector3::new(1.0, 0.5, 0.25);
+
+        // Rough dielectric lit and seen head on: mostly Lambertian
+        let rough = cook_torrance(normal, normal, normal, radiance, albedo, 0.0, 1.0);
+        let diffuse = 0.96 / PI;
+        let specular = 0.04 * distribution_ggx(1.0, 1.0) * geometry_smith(1.0, 1.0, 1.0) / 4.0;
+        assert!(approx(diffuse + specular, rough.x(), 1e-6));
+        assert!(approx(diffuse * 0.5 + specular, rough.y(), 1e-6));
+        assert!(approx(1.0 / PI, distribution_ggx(1.0, 1.0), 1e-6));
+
+        // Metals have no diffuse, so black metal reflects nothing
+        let black_metal = cook_torrance(
+            normal,
+            normal,
+            normal,
+            radiance,
+            Vector3::splat(0.0),
+            1.0,
+            0.5,
+        );
+        assert_eq!(Vector3::splat(0.0), black_metal);
+
+        // Nothing from behind the surface
+        let behind = cook_torrance(normal, normal, -normal, radiance, albedo, 0.0, 0.5);
+        assert_eq!(Vector3::splat(0.0), behind);
+
+        // A smooth surface's highlight is much brighter than a rough one's
+        let light = Vector3::new(0.6, 0.0, 0.8);
+        let view = Vector3::new(-0.6, 0.0, 0.8);
+        let smooth = cook_torrance(normal, view, light, radiance, albedo, 1.0, 0.1);
+        let rough = cook_torrance(normal, view, light, radiance, albedo, 1.0, 0.9);
+        assert!(smooth.x() > 10.0 * rough.x());
+    }
+}
@@ -1,4 +1,7 @@
-use crate::math::{Vector2, Vector3, Vector4};
+use crate::{
+    collections::XorHashMap,
+    math::{Vector2, Vector3, Vector4},
+};
 
 // TODO: Animated mesh?
 // #[derive(Debug, Default)]
@@ -21,6 +24,9 @@ pub struct StaticMaterialVertex {
     normal: Vector3,
     tex_coord: Vector2,
     color: Vector4,
+    /// Points along the tex coord's u axis. `w` is the handedness: the bitangent (along v) is
+    /// `w * normal.cross(tangent)`.
+    tangent: Vector4,
 }
 
 impl StaticMaterialVertex {
@@ -36,8 +42,35 @@ impl StaticMaterialVertex {
             normal,
             tex_coord,
             color,
+            tangent: Vector4::default(),
         }
     }
+
+    #[inline]
+    pub fn with_tangent(mut self, tangent: Vector4) -> StaticMaterialVertex {
+        self.tangent = tangent;
+        self
+    }
+
+    #[inline]
+    pub fn position(&self) -> Vector3 {
+        self.position
+    }
+
+    #[inline]
+    pub fn normal(&self) -> Vector3 {
+        self.normal
+    }
+
+    #[inline]
+    pub fn tex_coord(&self) -> Vector2 {
+        self.tex_coord
+    }
+
+    #[inline]
+    pub fn tangent(&self) -> Vector4 {
+        self.tangent
+    }
 }
 
 unsafe impl bytemuck::Zeroable for StaticMaterialVertex {}
@@ -70,4 +103,181 @@ impl StaticMaterialMesh {
     pub fn add_index(&mut self, index: u32) {
         self.indices.push(index);
     }
+
+    /// The radius of the smallest sphere around the origin that holds every vertex.
+    #[inline]
+    pub fn bounding_radius(&self) -> f32 {
+        self.vertices
+            .iter()
+            .map(|vertex| vertex.position.length())
+            .fold(0.0, f32::max)
+    }
+
+    /// Work out every vertex's tangent from its triangles' positions and tex coords.
+    ///
+    /// This follows MikkTSpace: each triangle's tangent is projected onto the plane of the
+    /// vertex normal and weighted by the angle of the triangle's corner, and vertices with the
+    /// same position, normal and tex coord share a tangent even when they aren't shared by index.
+    pub fn generate_tangents(&mut self) {
+        let mut groups = XorHashMap::default();
+        let vertex_groups: Vec<usize> = self
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let mut key = [0u32; 8];
+                let attributes = vertex
+                    .position
+                    .0
+                    .iter()
+                    .chain(&vertex.normal.0)
+                    .chain(&vertex.tex_coord.0);
+                for (key, attribute) in key.iter_mut().zip(attributes) {
+                    // Treat -0.0 as 0.0
+                    *key = (attribute + 0.0).to_bits();
+                }
+                let next = groups.len();
+                *groups.entry(key).or_insert(next)
+            })
+            .collect();
+
+        let mut tangents = vec![Vector3::default(); groups.len()];
+        let mut bitangents = vec![Vector3::default(); groups.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let corners = [
+                triangle[0] as usize,
+                triangle[1] as usize,
+                triangle[2] as usize,
+            ];
+            let [v0, v1, v2] = [
+                &self.vertices[corners[0]],
+                &self.vertices[corners[1]],
+                &self.vertices[corners[2]],
+            ];
+            let (edge1, edge2) = (v1.position - v0.position, v2.position - v0.position);
+            let (delta1, delta2) = (v1.tex_coord - v0.tex_coord, v2.tex_coord - v0.tex_coord);
+            let determinant = delta1.x() * delta2.y() - delta2.x() * delta1.y();
+            if determinant.abs() < f32::EPSILON {
+                // The tex coords don't span the triangle so it says nothing about the tangent
+                continue;
+            }
+            let tangent = (edge1 * delta2.y() - edge2 * delta1.y()) / determinant;
+            let bitangent = (edge2 * delta1.x() - edge1 * delta2.x()) / determinant;
+
+            for i in 0..3 {
+                let vertex = &self.vertices[corners[i]];
+                let to_next = self.vertices[corners[(i + 1) % 3]].position - vertex.position;
+                let to_previous = self.vertices[corners[(i + 2) % 3]].position - vertex.position;
+                if to_next.length() == 0.0 || to_previous.length() == 0.0 {
+                    continue;
+                }
+                let cos = to_next.normalized().dot(to_previous.normalized());
+                let angle = cos.clamp(-1.0, 1.0).acos();
+
+                let group = vertex_groups[corners[i]];
+                let normal = vertex.normal;
+                tangents[group] += project_normalized(tangent, normal) * angle;
+                bitangents[group] += project_normalized(bitangent, normal) * angle;
+            }
+        }
+
+        for (vertex, &group) in self.vertices.iter_mut().zip(&vertex_groups) {
+            let normal = vertex.normal;
+            let mut tangent = project_normalized(tangents[group], normal);
+            if tangent.length() == 0.0 {
+                // Nothing to go on, so any tangent will do
+                tangent = project_normalized(Vector3::right(), normal);
+                if tangent.length() == 0.0 {
+                    tangent = project_normalized(Vector3::forward(), normal);
+                }
+            }
+            let handedness = if normal.cross(tangent).dot(bitangents[group]) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            vertex.tangent = tangent.widened(handedness);
+        }
+    }
+}
+
+/// Remove the part of `v` along `normal` and normalize what's left, or zero if nothing is left.
+#[inline]
+fn project_normalized(v: Vector3, normal: Vector3) -> Vector3 {
+    let projected = v - normal * normal.dot(v);
+    let length = projected.length();
+    if length > f32::EPSILON {
+        projected / length
+    } else {
+        Vector3::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{gfx::ColladaReader, util};
+
+    /// Check the tangents against every triangle's tex coords, which should change along the
+    /// tangent and bitangent at `scale` per unit.
+    fn assert_tangents_follow_tex_coords(mesh: &StaticMaterialMesh, scale: f32) {
+        for vertex in mesh.vertices() {
+            let tangent = vertex.tangent().narrowed();
+            assert!((tangent.length() - 1.0).abs() < 1e-5);
+            assert!(tangent.dot(vertex.normal()).abs() < 1e-5);
+            assert_eq!(1.0, vertex.tangent().w().abs());
+        }
+        for triangle in mesh.indices().chunks_exact(3) {
+            let v0 = &mesh.vertices()[triangle[0] as usize];
+            for &index in &triangle[1..] {
+                let v = &mesh.vertices()[index as usize];
+                for corner in &[v0, v] {
+                    let tangent = corner.tangent().narrowed();
+                    let bitangent = corner.normal().cross(tangent) * corner.tangent().w();
+                    let edge = v.position() - v0.position();
+                    let delta = v.tex_coord() - v0.tex_coord();
+                    assert!((edge.dot(tangent) * scale - delta.x()).abs() < 1e-5);
+                    assert!((edge.dot(bitangent) * scale - delta.y()).abs() < 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn generates_cube_tangents() {
+        let mut mesh = StaticMaterialMesh::default();
+        ColladaReader::default()
+            .read_into(
+                &mut util::buf_open("res/models/cube.dae").unwrap(),
+                &mut mesh,
+            )
+            .unwrap();
+        assert_eq!(36, mesh.vertices().len());
+        // Each 2x2 face covers a quarter of the texture's width
+        assert_tangents_follow_tex_coords(&mesh, 0.125);
+    }
+
+    #[test]
+    fn generates_mirrored_tangents() {
+        let mut mesh = StaticMaterialMesh::default();
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let white = Vector4::splat(1.0);
+        // A quad with the texture flipped left to right
+        for &(x, y) in &[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)] {
+            mesh.add_vertex(StaticMaterialVertex::new(
+                Vector3::new(x, y, 0.0),
+                normal,
+                Vector2::new(1.0 - x, y),
+                white,
+            ));
+        }
+        for &index in &[0, 1, 2, 2, 1, 3] {
+            mesh.add_index(index);
+        }
+        mesh.generate_tangents();
+
+        for vertex in mesh.vertices() {
+            assert_eq!([-1.0, 0.0, 0.0, -1.0], vertex.tangent().0);
+        }
+        assert_tangents_follow_tex_coords(&mesh, 1.0);
+    }
 }
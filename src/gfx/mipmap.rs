@@ -0,0 +1,326 @@
+use std::f32::consts::PI;
+
+/// How each mip level is filtered down from the one above it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MipFilter {
+    /// Average the pixels each pixel covers. Cheap, but a little blurry.
+    Box,
+    /// A Kaiser windowed sinc. Sharper, and aliases less than a box.
+    Kaiser,
+}
+
+impl Default for MipFilter {
+    #[inline]
+    fn default() -> MipFilter {
+        MipFilter::Box
+    }
+}
+
+/// How the values of a bitmap's color channels relate to light. Alpha is always linear.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ColorSpace {
+    /// Stored as is, e.g. normal, specular and roughness maps.
+    Linear,
+    /// Gamma encoded colors, e.g. diffuse maps. They are averaged in linear light so mip levels
+    /// don't get darker.
+    Srgb,
+}
+
+impl Default for ColorSpace {
+    #[inline]
+    fn default() -> ColorSpace {
+        ColorSpace::Srgb
+    }
+}
+
+/// How many destination pixels either side of its center the Kaiser filter reaches.
+const KAISER_RADIUS: f32 = 3.0;
+/// How quickly the Kaiser window falls off. Higher is smoother but blurrier.
+const KAISER_ALPHA: f32 = 4.0;
+
+#[inline]
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[inline]
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Unpack the rows of `channels` byte pixels into values between 0.0 and 1.0, in linear light.
+/// The last of 4 channels is alpha.
+pub fn decode_pixels(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    bytes_per_row: usize,
+    channels: usize,
+    color_space: ColorSpace,
+) -> Vec<f32> {
+    let mut srgb = [0.0; 256];
+    for (i, value) in srgb.iter_mut().enumerate() {
+        *value = srgb_to_linear(i as f32 / 255.0);
+    }
+    let mut pixels = Vec::with_capacity(width * height * channels);
+    for row in data.chunks(bytes_per_row).take(height) {
+        for (i, &byte) in row[..width * channels].iter().enumerate() {
+            pixels.push(if is_color(i % channels, channels, color_space) {
+                srgb[byte as usize]
+            } else {
+                byte as f32 / 255.0
+            });
+        }
+    }
+    pixels
+}
+
+/// The reverse of `decode_pixels`, packing tightly.
+pub fn encode_pixels(pixels: &[f32], channels: usize, color_space: ColorSpace) -> Vec<u8> {
+    pixels
+        .iter()
+        .enumerate()
+        .map(|(i, &value)| {
+            let value = value.clamp(0.0, 1.0);
+            let value = if is_color(i % channels, channels, color_space) {
+                linear_to_srgb(value)
+            } else {
+                value
+            };
+            (value * 255.0).round() as u8
+        })
+        .collect()
+}
+
+#[inline]
+fn is_color(channel: usize, channels: usize, color_space: ColorSpace) -> bool {
+    color_space == ColorSpace::Srgb && (channels != 4 || channel != 3)
+}
+
+/// Resize `width` x `height` pixels of `channels` values each to `new_width` x `new_height`.
+///
+/// Each axis is filtered separately. Sizes don't have to divide evenly, so odd sized levels
+/// blend three pixels into each rather than dropping a row or column.
+pub fn downsample(
+    pixels: &[f32],
+    (width, height): (usize, usize),
+    (new_width, new_height): (usize, usize),
+    channels: usize,
+    filter: MipFilter,
+) -> Vec<f32> {
+    let columns = axis_weights(width, new_width, filter);
+    let rows = axis_weights(height, new_height, filter);
+
+    let mut horizontal = vec![0.0; new_width * height * channels];
+    for y in 0..height {
+        for (x, weights) in columns.iter().enumerate() {
+            let out = (y * new_width + x) * channels;
+            for &(source, weight) in weights {
+                let source = (y * width + source) * channels;
+                for c in 0..channels {
+                    horizontal[out + c] += pixels[source + c] * weight;
+                }
+            }
+        }
+    }
+
+    let mut resized = vec![0.0; new_width * new_height * channels];
+    for (y, weights) in rows.iter().enumerate() {
+        for x in 0..new_width {
+            let out = (y * new_width + x) * channels;
+            for &(source, weight) in weights {
+                let source = (source * new_width + x) * channels;
+                for c in 0..channels {
+                    resized[out + c] += horizontal[source + c] * weight;
+                }
+            }
+        }
+    }
+    resized
+}
+
+/// The source pixels, and how much of each, that make up every destination pixel along one
+/// axis. The weights of each destination pixel add up to 1.0.
+fn axis_weights(size: usize, new_size: usize, filter: MipFilter) -> Vec<Vec<(usize, f32)>> {
+    let scale = size as f32 / new_size as f32;
+    (0..new_size)
+        .map(|i| {
+            let mut weights = Vec::new();
+            match filter {
+                MipFilter::Box => {
+                    let (start, end) = (i as f32 * scale, (i + 1) as f32 * scale);
+                    for source in start.floor() as usize..(end.ceil() as usize).min(size) {
+                        let overlap = end.min(source as f32 + 1.0) - start.max(source as f32);
+                        if overlap > 0.0 {
+                            weights.push((source, overlap));
+                        }
+                    }
+                }
+                MipFilter::Kaiser => {
+                    let center = (i as f32 + 0.5) * scale;
+                    let reach = KAISER_RADIUS * scale;
+                    let first = (center - reach).floor() as isize;
+                    let last = (center + reach).ceil() as isize;
+                    for source in first..last {
+                        let t = (source as f32 + 0.5 - center) / scale;
+                        let weight = sinc(t) * kaiser(t / KAISER_RADIUS);
+                        if weight != 0.0 {
+                            // Clamp to the edge like the sampler does
+                            let source = source.clamp(0, size as isize - 1) as usize;
+                            match weights.iter_mut().find(|(s, _)| *s == source) {
+                                Some((_, total)) => *total += weight,
+                                None => weights.push((source, weight)),
+                            }
+                        }
+                    }
+                }
+            }
+            let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+            for (_, weight) in &mut weights {
+                *weight /= total;
+            }
+            weights
+        })
+        .collect()
+}
+
+#[inline]
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The Kaiser window, from 1.0 at 0.0 down to nothing past 1.0.
+#[inline]
+fn kaiser(x: f32) -> f32 {
+    if x.abs() >= 1.0 {
+        0.0
+    } else {
+        bessel_i0(KAISER_ALPHA * (1.0 - x * x).sqrt()) / bessel_i0(KAISER_ALPHA)
+    }
+}
+
+/// The zeroth order modified Bessel function of the first kind, by its power series.
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-8 {
+        term *= (x / (2.0 * k)).powi(2);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn approx(a: f32, b: f32, epsilon: f32) -> bool {
+        (a - b).abs() <= epsilon
+    }
+
+    #[test]
+    fn converts_srgb() {
+        assert_eq!(0.0, srgb_to_linear(0.0));
+        assert!(approx(1.0, srgb_to_linear(1.0), 1e-6));
+        assert!(approx(0.2140, srgb_to_linear(0.5), 1e-4));
+        for i in 0..=255 {
+            let value = i as f32 / 255.0;
+            assert!(approx(value, linear_to_srgb(srgb_to_linear(value)), 1e-5));
+        }
+        let bytes: Vec<u8> = (0..=255).collect();
+        let decoded = decode_pixels(&bytes, 256, 1, 256, 1, ColorSpace::Srgb);
+        assert_eq!(bytes, encode_pixels(&decoded, 1, ColorSpace::Srgb));
+    }
+
+    #[test]
+    fn leaves_alpha_linear() {
+        let decoded = decode_pixels(&[128; 4], 1, 1, 4, 4, ColorSpace::Srgb);
+        assert!(approx(srgb_to_linear(128.0 / 255.0), decoded[0], 1e-6));
+        assert!(approx(128.0 / 255.0, decoded[3], 1e-6));
+    }
+
+    #[test]
+    fn skips_row_padding() {
+        let data = [1, 2, 0, 0, 3, 4, 0, 0];
+        let decoded = decode_pixels(&data, 2, 2, 4, 1, ColorSpace::Linear);
+        assert_eq!(
+            vec![1, 2, 3, 4],
+            encode_pixels(&decoded, 1, ColorSpace::Linear)
+        );
+    }
+
+    #[test]
+    fn box_weights_cover_odd_sizes() {
+        assert_eq!(
+            vec![vec![(0, 0.5), (1, 0.5)], vec![(2, 0.5), (3, 0.5)]],
+            axis_weights(4, 2, MipFilter::Box)
+        );
+        // The middle pixel is shared between both
+        let odd = axis_weights(3, 1, MipFilter::Box);
+        assert_eq!(3, odd[0].len());
+        let odd = axis_weights(5, 2, MipFilter::Box);
+        assert_eq!(
+            vec![0, 1, 2],
+            odd[0].iter().map(|w| w.0).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![2, 3, 4],
+            odd[1].iter().map(|w| w.0).collect::<Vec<_>>()
+        );
+        assert!(approx(0.2, odd[0][2].1, 1e-6));
+        // Nothing to filter along an axis that stays the same size
+        assert_eq!(vec![vec![(0, 1.0)]], axis_weights(1, 1, MipFilter::Box));
+    }
+
+    #[test]
+    fn kaiser_weights_are_normalized() {
+        for &(size, new_size) in &[(8, 4), (7, 3), (2, 1), (1, 1)] {
+            for weights in axis_weights(size, new_size, MipFilter::Kaiser) {
+                let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+                assert!(approx(1.0, total, 1e-5));
+                assert!(weights.iter().all(|&(source, _)| source < size));
+            }
+        }
+        assert!(approx(1.0, kaiser(0.0), 1e-6));
+        assert_eq!(0.0, kaiser(1.0));
+        assert!(approx(11.3019, bessel_i0(4.0), 1e-3));
+    }
+
+    #[test]
+    fn downsamples_evenly() {
+        let pixels = [0.0, 1.0, 1.0, 0.0];
+        assert_eq!(
+            vec![0.5],
+            downsample(&pixels, (2, 2), (1, 1), 1, MipFilter::Box)
+        );
+
+        // A flat image stays flat whatever the filter
+        let flat = vec![0.25; 7 * 5 * 4];
+        for &filter in &[MipFilter::Box, MipFilter::Kaiser] {
+            for value in downsample(&flat, (7, 5), (3, 2), 4, filter) {
+                assert!(approx(0.25, value, 1e-5));
+            }
+        }
+
+        // A symmetric step stays symmetric
+        let step = [0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0];
+        let sharp = downsample(&step, (8, 1), (4, 1), 1, MipFilter::Kaiser);
+        let blurry = downsample(&step, (8, 1), (4, 1), 1, MipFilter::Box);
+        assert!(approx(1.0, sharp[1] + sharp[2], 1e-5));
+        assert_eq!(vec![0.0, 0.0, 1.0, 1.0], blurry);
+    }
+}
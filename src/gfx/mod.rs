@@ -1,15 +1,31 @@
+mod bcn;
+mod bcn_encoder;
 mod bitmap;
+mod cluster;
 mod collada;
+mod draw;
 mod frustum;
+mod light;
+mod material;
 mod mesh;
+mod mipmap;
+mod shadow;
 
 use crate::math::{Matrix4, Quaternion, Vector3, Vector4};
+pub use bcn::*;
+pub use bcn_encoder::*;
 pub use bitmap::*;
+pub use cluster::*;
 pub use collada::*;
+pub use draw::*;
 pub use frustum::*;
+pub use light::*;
+pub use material::*;
 pub use mesh::*;
+pub use mipmap::*;
+pub use shadow::*;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Copy, Clone)]
 pub struct PerspectiveProjection {
     pub fov: f32,
     pub aspect_ratio: f32,
@@ -31,7 +47,7 @@ impl From<&PerspectiveProjection> for Matrix4 {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Copy, Clone)]
 pub struct OrthographicProjection {
     pub left: f32,
     pub right: f32,
@@ -44,15 +60,7 @@ pub struct OrthographicProjection {
 impl From<&OrthographicProjection> for Matrix4 {
     #[inline]
     fn from(p: &OrthographicProjection) -> Matrix4 {
-        let height = p.top - p.bottom;
-        let width = p.right - p.left;
-        let depth = p.far - p.near;
-        Matrix4([
-            Vector4([2.0 / width, 0.0, 0.0, -((p.right + p.left) / width)]),
-            Vector4([0.0, 2.0 / height, 0.0, -((p.top + p.bottom) / height)]),
-            Vector4([0.0, 0.0, -2.0 / depth, -((p.far + p.near) / depth)]),
-            Vector4([0.0, 0.0, 0.0, 1.0]),
-        ])
+        Matrix4::orthographic(p.top, p.left, p.bottom, p.right, p.near, p.far)
     }
 }
 
@@ -64,12 +72,53 @@ pub struct Transform {
 }
 
 impl Transform {
+    /// Compose a child transform (`rhs`, relative to `self`) into the space `self` is in.
+    ///
+    /// The child's position is scaled and rotated by `self` before being offset. Like any
+    /// translate/rotate/scale transform this can't represent skew, so the result is only exact
+    /// when `self` is scaled uniformly.
     #[inline]
     pub fn concat(&self, rhs: &Transform) -> Transform {
         Transform {
-            position: self.position + rhs.position,
+            position: self.transform_point(rhs.position),
             scale: self.scale * rhs.scale,
-            rotation: self.rotation * rhs.rotation,
+            rotation: (self.rotation * rhs.rotation).normalized(),
+        }
+    }
+
+    /// The transform that undoes `self`, so that `t.inversed().concat(&t)` is the identity.
+    ///
+    /// Same caveat as `concat`: exact for uniform scales.
+    #[inline]
+    pub fn inversed(&self) -> Transform {
+        let scale = Vector3::splat(1.0) / self.scale;
+        let rotation = self.rotation.conjugated();
+        Transform {
+            position: -(self.position.rotated(rotation) * scale),
+            scale,
+            rotation,
+        }
+    }
+
+    /// Scale, rotate and then translate a point.
+    #[inline]
+    pub fn transform_point(&self, p: Vector3) -> Vector3 {
+        (p * self.scale).rotated(self.rotation) + self.position
+    }
+
+    /// Blend from `self` (at 0.0) to `rhs` (at 1.0).
+    #[inline]
+    pub fn interpolated(&self, rhs: &Transform, alpha: f32) -> Transform {
+        // Take the shortest path around
+        let rotation = if self.rotation.0.dot(rhs.rotation.0) < 0.0 {
+            Quaternion(-rhs.rotation.0)
+        } else {
+            rhs.rotation
+        };
+        Transform {
+            position: self.position + (rhs.position - self.position) * alpha,
+            scale: self.scale + (rhs.scale - self.scale) * alpha,
+            rotation: self.rotation.slerp(rotation, alpha),
         }
     }
 }
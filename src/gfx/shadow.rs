@@ -0,0 +1,325 @@
+use crate::{
+    gfx::{Frustum, OrthographicProjection, PerspectiveProjection},
+    math::{Matrix4, Vector3},
+};
+
+/// Where each cascade ends along the view, from `near` to `far`.
+///
+/// `lambda` blends between evenly spaced (0.0) and logarithmically spaced (1.0) splits.
+/// Logarithmic splits spend more of the shadow maps near the camera.
+pub fn cascade_splits(near: f32, far: f32, cascades: usize, lambda: f32) -> Vec<f32> {
+    (1..=cascades)
+        .map(|i| {
+            let t = i as f32 / cascades as f32;
+            let logarithmic = near * (far / near).powf(t);
+            let uniform = near + (far - near) * t;
+            lambda * logarithmic + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
+
+/// How the view gets cut up into shadow cascades.
+#[derive(Debug, Copy, Clone)]
+pub struct CascadeSettings {
+    pub cascades: usize,
+    /// How far from the camera shadows reach (at most the far plane).
+    pub distance: f32,
+    /// See `cascade_splits`.
+    pub split_lambda: f32,
+    /// The width and height of each cascade's shadow map in texels.
+    pub resolution: u32,
+    /// How far past each cascade (towards the light) shadow casters are caught.
+    pub caster_distance: f32,
+}
+
+impl Default for CascadeSettings {
+    #[inline]
+    fn default() -> CascadeSettings {
+        CascadeSettings {
+            cascades: 4,
+            distance: 100.0,
+            split_lambda: 0.75,
+            resolution: 2048,
+            caster_distance: 100.0,
+        }
+    }
+}
+
+impl CascadeSettings {
+    /// Fit a cascade around each slice of the view from `position` looking at `at`, for a
+    /// light shining along `light_direction`.
+    pub fn fit(
+        &self,
+        projection: &PerspectiveProjection,
+        position: Vector3,
+        at: Vector3,
+        up: Vector3,
+        light_direction: Vector3,
+    ) -> Vec<ShadowCascade> {
+        let far = projection.far.min(self.distance);
+        let mut near = projection.near;
+        cascade_splits(near, far, self.cascades, self.split_lambda)
+            .into_iter()
+            .map(|split| {
+                let slice = PerspectiveProjection {
+                    near,
+                    far: split,
+                    ..*projection
+                };
+                near = split;
+                ShadowCascade::fit(&slice, position, at, up, light_direction, self)
+            })
+            .collect()
+    }
+}
+
+/// An orthographic view from a directional light covering one slice of the camera's view.
+#[derive(Debug, Copy, Clone)]
+pub struct ShadowCascade {
+    /// How far along the camera's view the cascade reaches.
+    pub split: f32,
+    /// Relative to a light at the origin looking along `direction`.
+    pub projection: OrthographicProjection,
+    pub direction: Vector3,
+    pub up: Vector3,
+}
+
+impl ShadowCascade {
+    /// Fit a cascade around a sphere bounding the view through `slice`.
+    ///
+    /// The sphere is the same size however the camera turns, so the texel size stays put, and
+    /// its center is snapped to whole texels. Together they keep the shadows from shimmering
+    /// as the camera moves or rotates, at the cost of some resolution.
+    pub fn fit(
+        slice: &PerspectiveProjection,
+        position: Vector3,
+        at: Vector3,
+        up: Vector3,
+        light_direction: Vector3,
+        settings: &CascadeSettings,
+    ) -> ShadowCascade {
+        let direction = light_direction.normalized();
+        // Any up will do as long as it isn't parallel to the light
+        let light_up = if direction.dot(Vector3::up()).abs() > 0.99 {
+            Vector3::forward()
+        } else {
+            Vector3::up()
+        };
+        let view = Matrix4::look_at(Vector3::splat(0.0), direction, light_up);
+
+        let corners = Frustum::new(slice, position, at, up).corners();
+        let center = corners
+            .iter()
+            .fold(Vector3::splat(0.0), |sum, &corner| sum + corner)
+            / corners.len() as f32;
+        let radius = corners
+            .iter()
+            .map(|&corner| (corner - center).length())
+            .fold(0.0, f32::max);
+        // Round up so floating point noise from turning can't change the size
+        let radius = (radius * 16.0).ceil() / 16.0;
+
+        let texel = 2.0 * radius / settings.resolution.max(1) as f32;
+        let center = view.transform_point(center);
+        let snap = |v: f32| {
+            if texel > 0.0 {
+                (v / texel).round() * texel
+            } else {
+                v
+            }
+        };
+        let (x, y) = (snap(center.x()), snap(center.y()));
+
+        ShadowCascade {
+            split: slice.far,
+            // The light looks down -z, so the nearest point has the biggest z
+            projection: OrthographicProjection {
+                left: x - radius,
+                right: x + radius,
+                bottom: y - radius,
+                top: y + radius,
+                near: -(center.z() + radius) - settings.caster_distance,
+                far: -(center.z() - radius),
+            },
+            direction,
+            up: light_up,
+        }
+    }
+
+    #[inline]
+    pub fn view_matrix(&self) -> Matrix4 {
+        Matrix4::look_at(Vector3::splat(0.0), self.direction, self.up)
+    }
+
+    #[inline]
+    pub fn view_projection_matrix(&self) -> Matrix4 {
+        &self.view_matrix() * &Matrix4::from(&self.projection)
+    }
+
+    /// For culling shadow casters.
+    #[inline]
+    pub fn frustum(&self) -> Frustum {
+        Frustum::orthographic(
+            &self.projection,
+            Vector3::splat(0.0),
+            self.direction,
+            self.up,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROJECTION: PerspectiveProjection = PerspectiveProjection {
+        fov: 1.0,
+        aspect_ratio: 1.5,
+        near: 0.1,
+        far: 1000.0,
+    };
+
+    fn approx(a: f32, b: f32, epsilon: f32) -> bool {
+        (a - b).abs() <= epsilon
+    }
+
+    #[test]
+    fn splits_cascades() {
+        let uniform = cascade_splits(1.0, 9.0, 4, 0.0);
+        assert_eq!(vec![3.0, 5.0, 7.0, 9.0], uniform);
+
+        let logarithmic = cascade_splits(1.0, 16.0, 4, 1.0);
+        for (split, expected) in logarithmic.iter().zip(&[2.0, 4.0, 8.0, 16.0]) {
+            assert!(approx(*split, *expected, 1e-5));
+        }
+
+        let practical = cascade_splits(0.1, 100.0, 4, 0.75);
+        assert!(approx(100.0, practical[3], 1e-3));
+        for pair in practical.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+    }
+
+    #[test]
+    fn fits_cascades_around_the_view() {
+        let position = Vector3::new(5.0, 2.0, -3.0);
+        let at = position + Vector3::new(1.0, -0.2, -1.0);
+        let light_direction = Vector3::new(0.3, -1.0, 0.2);
+        let settings = CascadeSettings {
+            resolution: 1024,
+            ..CascadeSettings::default()
+        };
+        let cascades = settings.fit(&PROJECTION, position, at, Vector3::up(), light_direction);
+        assert_eq!(settings.cascades, cascades.len());
+        assert!(approx(settings.distance, cascades[3].split, 1e-3));
+
+        let mut near = PROJECTION.near;
+        for cascade in &cascades {
+            let slice = PerspectiveProjection {
+                near,
+                far: cascade.split,
+                ..PROJECTION
+            };
+            near = cascade.split;
+
+            let view_projection = cascade.view_projection_matrix();
+            let (mut min_x, mut max_x) = (f32::INFINITY, f32::NEG_INFINITY);
+            let (mut min_y, mut max_y) = (f32::INFINITY, f32::NEG_INFINITY);
+            for corner in &Frustum::new(&slice, position, at, Vector3::up()).corners() {
+                let clip = view_projection.transform_point(*corner);
+                // Everything in the slice has to land in the shadow map
+                assert!(clip.x().abs() <= 1.0 + 1e-4, "{:?}", clip);
+                assert!(clip.y().abs() <= 1.0 + 1e-4, "{:?}", clip);
+                assert!(clip.z().abs() <= 1.0 + 1e-4, "{:?}", clip);
+                min_x = min_x.min(clip.x());
+                max_x = max_x.max(clip.x());
+                min_y = min_y.min(clip.y());
+                max_y = max_y.max(clip.y());
+            }
+            // ... and the map is no bigger than the slice's bounding sphere
+            let corners = Frustum::new(&slice, position, at, Vector3::up()).corners();
+            let center = corners
+                .iter()
+                .fold(Vector3::splat(0.0), |sum, &corner| sum + corner)
+                / 8.0;
+            let diameter = 2.0
+                * corners
+                    .iter()
+                    .map(|&corner| (corner - center).length())
+                    .fold(0.0, f32::max);
+            let projection = cascade.projection;
+            assert!(projection.right - projection.left <= diameter + 0.125);
+            assert!(projection.top - projection.bottom <= diameter + 0.125);
+            assert!(max_x - min_x > 0.0 && max_y - min_y > 0.0);
+        }
+    }
+
+    #[test]
+    fn stays_still_as_the_camera_turns_and_moves() {
+        let light_direction = Vector3::new(0.3, -1.0, 0.2);
+        let settings = CascadeSettings {
+            cascades: 1,
+            distance: 50.0,
+            resolution: 1024,
+            ..CascadeSettings::default()
+        };
+        let fit = |position: Vector3, look: Vector3| {
+            settings.fit(
+                &PROJECTION,
+                position,
+                position + look,
+                Vector3::up(),
+                light_direction,
+            )[0]
+            .projection
+        };
+
+        let position = Vector3::new(5.0, 2.0, -3.0);
+        let first = fit(position, Vector3::new(1.0, -0.2, -1.0));
+        let size = (first.right - first.left, first.top - first.bottom);
+        let texel = size.0 / settings.resolution as f32;
+        for i in 0..36 {
+            let angle = i as f32 * 0.17;
+            let look = Vector3::new(angle.cos(), 0.3 * angle.sin(), angle.sin());
+            let moved = position + Vector3::new(0.37, 0.0, -0.21) * i as f32;
+            for projection in &[fit(position, look), fit(moved, look)] {
+                // The same size whichever way the camera faces...
+                assert_eq!(
+                    size,
+                    (
+                        projection.right - projection.left,
+                        projection.top - projection.bottom
+                    )
+                );
+                // ... and only ever moved by whole texels
+                let texels = (projection.left - first.left) / texel;
+                assert!(approx(texels, texels.round(), 1e-2), "{}", texels);
+                let texels = (projection.bottom - first.bottom) / texel;
+                assert!(approx(texels, texels.round(), 1e-2), "{}", texels);
+            }
+        }
+    }
+
+    #[test]
+    fn catches_casters_towards_the_light() {
+        let position = Vector3::splat(0.0);
+        let at = Vector3::new(0.0, 0.0, -1.0);
+        let light_direction = Vector3::new(0.0, -1.0, 0.1);
+        let settings = CascadeSettings {
+            cascades: 1,
+            distance: 10.0,
+            caster_distance: 50.0,
+            ..CascadeSettings::default()
+        };
+        let cascade = settings.fit(&PROJECTION, position, at, Vector3::up(), light_direction)[0];
+
+        let frustum = cascade.frustum();
+        // Above the view, between it and the light
+        let caster = Vector3::new(0.0, 40.0, -5.0);
+        assert!(frustum.point_inside(caster));
+        let clip = cascade.view_projection_matrix().transform_point(caster);
+        assert!(clip.z() >= -1.0 && clip.z() <= 1.0);
+        // Too far above
+        assert!(!frustum.point_inside(Vector3::new(0.0, 80.0, -5.0)));
+    }
+}
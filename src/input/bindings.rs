@@ -0,0 +1,246 @@
+use std::{fmt, str::FromStr};
+
+// Enums of plain unit variants that round trip through their names (case-insensitively),
+// which is what the bindings config file is written in.
+macro_rules! named_enum {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+        pub enum $name {
+            $($variant),*
+        }
+
+        impl $name {
+            pub const ALL: &'static [$name] = &[$($name::$variant),*];
+
+            #[inline]
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $($name::$variant => stringify!($variant)),*
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            #[inline]
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<$name, String> {
+                $name::ALL
+                    .iter()
+                    .find(|value| value.name().eq_ignore_ascii_case(s))
+                    .copied()
+                    .ok_or_else(|| format!("Unknown {} \"{}\"", stringify!($name), s))
+            }
+        }
+    };
+}
+
+named_enum!(
+    /// Keyboard keys, by what is printed on them (not where they are).
+    Key {
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9,
+        F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+        Up, Down, Left, Right,
+        Space, Enter, Escape, Tab, Backspace,
+        LShift, RShift, LCtrl, RCtrl, LAlt, RAlt,
+    }
+);
+
+named_enum!(MouseButton {
+    Left,
+    Middle,
+    Right,
+    X1,
+    X2,
+});
+
+named_enum!(
+    /// Mouse movement. The wheel is in "clicks".
+    MouseAxis { X, Y, Wheel }
+);
+
+named_enum!(
+    /// Buttons of an Xbox style controller.
+    GamepadButton {
+        A,
+        B,
+        X,
+        Y,
+        Back,
+        Guide,
+        Start,
+        LeftStick,
+        RightStick,
+        LeftShoulder,
+        RightShoulder,
+        DPadUp,
+        DPadDown,
+        DPadLeft,
+        DPadRight,
+    }
+);
+
+named_enum!(
+    /// Sticks go from -1 to 1 (down is positive y), triggers from 0 to 1.
+    GamepadAxis {
+        LeftX,
+        LeftY,
+        RightX,
+        RightY,
+        TriggerLeft,
+        TriggerRight,
+    }
+);
+
+/// Something that is either up or down.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Binding {
+    Key(Key),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+/// Something that contributes to the value of an axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AxisBinding {
+    /// Adds `scale` while held.
+    Button { binding: Binding, scale: f32 },
+    /// Adds the movement this frame times `scale`.
+    Mouse { axis: MouseAxis, scale: f32 },
+    /// Adds the axis position times `scale`. Anything within `dead_zone` of rest counts as 0.
+    Gamepad {
+        axis: GamepadAxis,
+        scale: f32,
+        dead_zone: f32,
+    },
+}
+
+pub const DEFAULT_DEAD_ZONE: f32 = 0.15;
+
+/// A platform independent input event, as fed to `Input::handle`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InputEvent {
+    Down(Binding),
+    Up(Binding),
+    MouseMotion { dx: f32, dy: f32 },
+    MouseWheel(f32),
+    GamepadAxis(GamepadAxis, f32),
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "key:{}", key),
+            Binding::Mouse(button) => write!(f, "mouse:{}", button),
+            Binding::Gamepad(button) => write!(f, "gamepad:{}", button),
+        }
+    }
+}
+
+/// Parses bindings written like `key:W`, `mouse:Left` or `gamepad:A`.
+impl FromStr for Binding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Binding, String> {
+        let (source, name) = split_source(s)?;
+        match source {
+            "key" => Ok(Binding::Key(name.parse()?)),
+            "mouse" => Ok(Binding::Mouse(name.parse()?)),
+            "gamepad" => Ok(Binding::Gamepad(name.parse()?)),
+            _ => Err(format!("Unknown binding source \"{}\"", source)),
+        }
+    }
+}
+
+impl fmt::Display for AxisBinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AxisBinding::Button { binding, scale } => write!(f, "{} scale={}", binding, scale),
+            AxisBinding::Mouse { axis, scale } => write!(f, "mouse_axis:{} scale={}", axis, scale),
+            AxisBinding::Gamepad {
+                axis,
+                scale,
+                dead_zone,
+            } => write!(
+                f,
+                "gamepad_axis:{} scale={} dead_zone={}",
+                axis, scale, dead_zone
+            ),
+        }
+    }
+}
+
+/// Parses axis bindings written like a `Binding` or `mouse_axis:X` or `gamepad_axis:LeftX`,
+/// followed by optional `scale=<f32>` (default 1) and (for gamepad axes) `dead_zone=<f32>`.
+impl FromStr for AxisBinding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AxisBinding, String> {
+        let mut tokens = s.split_whitespace();
+        let source = tokens.next().ok_or("Missing axis binding")?;
+        let mut scale = 1.0;
+        let mut dead_zone = None;
+        for option in tokens {
+            let mut parts = option.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => {
+                    return Err(format!(
+                        "Expected <option>=<value> but found \"{}\"",
+                        option
+                    ))
+                }
+            };
+            let value: f32 = value
+                .parse()
+                .map_err(|_| format!("Expected a number for {} but found \"{}\"", key, value))?;
+            match key {
+                "scale" => scale = value,
+                "dead_zone" => dead_zone = Some(value),
+                _ => return Err(format!("Unknown axis binding option \"{}\"", key)),
+            }
+        }
+
+        let (kind, name) = split_source(source)?;
+        let binding = match kind {
+            "mouse_axis" => AxisBinding::Mouse {
+                axis: name.parse()?,
+                scale,
+            },
+            "gamepad_axis" => AxisBinding::Gamepad {
+                axis: name.parse()?,
+                scale,
+                dead_zone: dead_zone.unwrap_or(DEFAULT_DEAD_ZONE),
+            },
+            _ => AxisBinding::Button {
+                binding: source.parse()?,
+                scale,
+            },
+        };
+        match binding {
+            AxisBinding::Gamepad { .. } => {}
+            _ if dead_zone.is_some() => {
+                return Err(format!("Only gamepad axes have a dead_zone (\"{}\")", s))
+            }
+            _ => {}
+        }
+        Ok(binding)
+    }
+}
+
+#[inline]
+fn split_source(s: &str) -> Result<(&str, &str), String> {
+    let mut parts = s.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(source), Some(name)) => Ok((source.trim(), name.trim())),
+        _ => Err(format!("Expected <source>:<name> but found \"{}\"", s)),
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use crate::{
+    input::{AxisBinding, Binding},
+    util,
+};
+
+/// Named actions (things that are pressed) and axes (things with a value) along with whatever
+/// they are bound to.
+///
+/// Maps are usually read from a config file with lines like:
+///
+/// ```text
+/// # Comments start with a hash
+/// action jump = key:Space, gamepad:A
+/// axis move_forward = key:W, key:S scale=-1, gamepad_axis:LeftY scale=-1 dead_zone=0.2
+/// axis look_yaw = mouse_axis:X scale=0.002
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct InputMap {
+    actions: BTreeMap<String, Vec<Binding>>,
+    axes: BTreeMap<String, Vec<AxisBinding>>,
+}
+
+impl InputMap {
+    #[inline]
+    pub fn new() -> InputMap {
+        InputMap::default()
+    }
+
+    /// Add another way to trigger an action. Binding the same thing twice does nothing.
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        let bindings = self.actions.entry(action.to_string()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    /// Returns false if the binding wasn't bound to the action.
+    pub fn unbind(&mut self, action: &str, binding: Binding) -> bool {
+        match self.actions.get_mut(action) {
+            Some(bindings) => {
+                let len = bindings.len();
+                bindings.retain(|b| *b != binding);
+                len != bindings.len()
+            }
+            None => false,
+        }
+    }
+
+    #[inline]
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions.get(action).map_or(&[], Vec::as_slice)
+    }
+
+    #[inline]
+    pub fn bind_axis(&mut self, axis: &str, binding: AxisBinding) {
+        self.axes.entry(axis.to_string()).or_default().push(binding);
+    }
+
+    #[inline]
+    pub fn axis_bindings(&self, axis: &str) -> &[AxisBinding] {
+        self.axes.get(axis).map_or(&[], Vec::as_slice)
+    }
+
+    /// Forget everything bound to an action or axis with this name (e.g. before rebinding it).
+    #[inline]
+    pub fn clear(&mut self, name: &str) {
+        self.actions.remove(name);
+        self.axes.remove(name);
+    }
+
+    #[inline]
+    pub fn actions(&self) -> impl Iterator<Item = &str> {
+        self.actions.keys().map(String::as_str)
+    }
+
+    #[inline]
+    pub fn axes(&self) -> impl Iterator<Item = &str> {
+        self.axes.keys().map(String::as_str)
+    }
+
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<InputMap> {
+        InputMap::read(util::buf_open(path)?)
+    }
+
+    pub fn read<R: BufRead>(reader: R) -> io::Result<InputMap> {
+        let mut map = InputMap::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            map.read_line(line)
+                .map_err(|err| util::invalid_data(format!("Line {}: {}", i + 1, err)))?;
+        }
+        Ok(map)
+    }
+
+    fn read_line(&mut self, line: &str) -> Result<(), String> {
+        let mut parts = line.splitn(2, '=');
+        let (declaration, bindings) = match (parts.next(), parts.next()) {
+            (Some(declaration), Some(bindings)) => (declaration, bindings),
+            _ => {
+                return Err(format!(
+                    "Expected <kind> <name> = <bindings> but found \"{}\"",
+                    line
+                ))
+            }
+        };
+        let mut declaration = declaration.split_whitespace();
+        let (kind, name) = match (declaration.next(), declaration.next(), declaration.next()) {
+            (Some(kind), Some(name), None) => (kind, name),
+            _ => return Err(format!("Expected <kind> <name> but found \"{}\"", line)),
+        };
+        let bindings = bindings
+            .split(',')
+            .map(str::trim)
+            .filter(|binding| !binding.is_empty());
+        match kind {
+            "action" => {
+                self.actions.entry(name.to_string()).or_default();
+                for binding in bindings {
+                    self.bind(name, binding.parse()?);
+                }
+            }
+            "axis" => {
+                self.axes.entry(name.to_string()).or_default();
+                for binding in bindings {
+                    self.bind_axis(name, binding.parse()?);
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown kind \"{}\" (expected action or axis)",
+                    kind
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the map back out in the format `read` expects.
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (name, bindings) in &self.actions {
+            write!(writer, "action {} =", name)?;
+            write_list(writer, bindings)?;
+        }
+        for (name, bindings) in &self.axes {
+            write!(writer, "axis {} =", name)?;
+            write_list(writer, bindings)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_list<W: Write, T: std::fmt::Display>(writer: &mut W, items: &[T]) -> io::Result<()> {
+    for (i, item) in items.iter().enumerate() {
+        let separator = if i == 0 { " " } else { ", " };
+        write!(writer, "{}{}", separator, item)?;
+    }
+    writeln!(writer)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::{GamepadAxis, Key, MouseAxis, MouseButton, DEFAULT_DEAD_ZONE};
+
+    const CONFIG: &str = "
+        # Movement
+        action jump = key:Space, gamepad:a   # names are case insensitive
+        action fire = mouse:Left
+        action unbound =
+
+        axis move_forward = key:W, key:S scale=-1, gamepad_axis:LeftY scale=-1 dead_zone=0.25
+        axis look_yaw = mouse_axis:X scale=0.002, gamepad_axis:RightX
+    ";
+
+    #[test]
+    fn reads_configs() {
+        let map = InputMap::read(CONFIG.as_bytes()).unwrap();
+        assert_eq!(
+            &[
+                Binding::Key(Key::Space),
+                Binding::Gamepad(crate::input::GamepadButton::A)
+            ],
+            map.bindings("jump")
+        );
+        assert_eq!(&[Binding::Mouse(MouseButton::Left)], map.bindings("fire"));
+        assert!(map.bindings("unbound").is_empty());
+        assert_eq!(
+            vec!["fire", "jump", "unbound"],
+            map.actions().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            &[
+                AxisBinding::Button {
+                    binding: Binding::Key(Key::W),
+                    scale: 1.0
+                },
+                AxisBinding::Button {
+                    binding: Binding::Key(Key::S),
+                    scale: -1.0
+                },
+                AxisBinding::Gamepad {
+                    axis: GamepadAxis::LeftY,
+                    scale: -1.0,
+                    dead_zone: 0.25
+                },
+            ],
+            map.axis_bindings("move_forward")
+        );
+        assert_eq!(
+            &[
+                AxisBinding::Mouse {
+                    axis: MouseAxis::X,
+                    scale: 0.002
+                },
+                AxisBinding::Gamepad {
+                    axis: GamepadAxis::RightX,
+                    scale: 1.0,
+                    dead_zone: DEFAULT_DEAD_ZONE
+                },
+            ],
+            map.axis_bindings("look_yaw")
+        );
+    }
+
+    #[test]
+    fn round_trips_configs() {
+        let mut map = InputMap::read(CONFIG.as_bytes()).unwrap();
+        map.clear("fire");
+        map.bind("fire", Binding::Key(Key::LCtrl));
+        assert!(map.unbind("jump", Binding::Key(Key::Space)));
+        assert!(!map.unbind("jump", Binding::Key(Key::Space)));
+
+        let mut written = Vec::new();
+        map.write(&mut written).unwrap();
+        assert_eq!(map, InputMap::read(written.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn rejects_bad_configs() {
+        let error = |config: &str| InputMap::read(config.as_bytes()).unwrap_err().to_string();
+        assert_eq!(
+            "Line 2: Unknown Key \"Wat\"",
+            error("action ok = key:A\naction jump = key:Wat")
+        );
+        assert!(error("jump = key:A").contains("Expected <kind> <name>"));
+        assert!(error("button jump = key:A").contains("Unknown kind"));
+        assert!(error("action jump key:A").contains("Expected <kind> <name> ="));
+        assert!(error("action jump = keyboard:A").contains("Unknown binding source"));
+        assert!(error("action jump = A").contains("Expected <source>:<name>"));
+        assert!(error("axis x = key:A scale=lots").contains("Expected a number for scale"));
+        assert!(error("axis x = key:A dead_zone=0.1").contains("Only gamepad axes"));
+    }
+}
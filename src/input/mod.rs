@@ -0,0 +1,7 @@
+mod bindings;
+mod map;
+mod state;
+
+pub use bindings::*;
+pub use map::*;
+pub use state::*;
@@ -0,0 +1,270 @@
+use std::collections::HashSet;
+
+use crate::{
+    input::{AxisBinding, Binding, GamepadAxis, InputEvent, InputMap, MouseAxis},
+    math::{self, Vector2},
+};
+
+/// The state of every input as of the current frame, queried through an `InputMap`.
+///
+/// Feed it every event with `handle`, query it, then call `end_frame` once the frame is done
+/// with it. Edges (`pressed`/`released`) and mouse movement cover everything that happened
+/// since the last `end_frame`, so a tap that starts and ends within one frame isn't lost.
+#[derive(Debug, Default)]
+pub struct Input {
+    map: InputMap,
+    down: HashSet<Binding>,
+    // What was down at the end of the last frame
+    previous_down: HashSet<Binding>,
+    // Everything that went down during this frame, even if it has gone back up since
+    went_down: HashSet<Binding>,
+    gamepad_axes: [f32; 6],
+    mouse_delta: Vector2,
+    mouse_wheel: f32,
+}
+
+impl Input {
+    #[inline]
+    pub fn new(map: InputMap) -> Input {
+        Input {
+            map,
+            ..Input::default()
+        }
+    }
+
+    #[inline]
+    pub fn map(&self) -> &InputMap {
+        &self.map
+    }
+
+    /// Rebind things. Takes effect immediately.
+    #[inline]
+    pub fn map_mut(&mut self) -> &mut InputMap {
+        &mut self.map
+    }
+
+    pub fn handle(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::Down(binding) => {
+                // Key repeats aren't new presses
+                if self.down.insert(binding) {
+                    self.went_down.insert(binding);
+                }
+            }
+            InputEvent::Up(binding) => {
+                self.down.remove(&binding);
+            }
+            InputEvent::MouseMotion { dx, dy } => self.mouse_delta += Vector2::new(dx, dy),
+            InputEvent::MouseWheel(amount) => self.mouse_wheel += amount,
+            InputEvent::GamepadAxis(axis, value) => {
+                self.gamepad_axes[axis as usize] = math::clamp(value, -1.0, 1.0)
+            }
+        }
+    }
+
+    /// Let go of everything (e.g. when the window loses focus and won't see the keys come up).
+    #[inline]
+    pub fn release_all(&mut self) {
+        self.down.clear();
+        self.gamepad_axes = [0.0; 6];
+    }
+
+    /// Roll over to the next frame, clearing edges and mouse movement.
+    #[inline]
+    pub fn end_frame(&mut self) {
+        self.previous_down.clone_from(&self.down);
+        self.went_down.clear();
+        self.mouse_delta = Vector2::default();
+        self.mouse_wheel = 0.0;
+    }
+
+    #[inline]
+    pub fn is_down(&self, binding: Binding) -> bool {
+        self.down.contains(&binding)
+    }
+
+    /// Whether anything bound to the action is down.
+    #[inline]
+    pub fn held(&self, action: &str) -> bool {
+        self.any(action, &self.down)
+    }
+
+    /// Whether the action started this frame.
+    #[inline]
+    pub fn pressed(&self, action: &str) -> bool {
+        !self.any(action, &self.previous_down) && self.any(action, &self.went_down)
+    }
+
+    /// Whether the action stopped this frame.
+    #[inline]
+    pub fn released(&self, action: &str) -> bool {
+        (self.any(action, &self.previous_down) || self.any(action, &self.went_down))
+            && !self.held(action)
+    }
+
+    /// The value of an axis this frame.
+    ///
+    /// Buttons and gamepad axes are summed and clamped to \[-1, 1\]. Mouse movement is added
+    /// on after that (unclamped) since it's a distance rather than a position.
+    pub fn axis(&self, axis: &str) -> f32 {
+        let mut position = 0.0;
+        let mut movement = 0.0;
+        for binding in self.map.axis_bindings(axis) {
+            match *binding {
+                AxisBinding::Button { binding, scale } => {
+                    if self.is_down(binding) {
+                        position += scale;
+                    }
+                }
+                AxisBinding::Gamepad {
+                    axis,
+                    scale,
+                    dead_zone,
+                } => position += apply_dead_zone(self.gamepad_axis(axis), dead_zone) * scale,
+                AxisBinding::Mouse { axis, scale } => {
+                    let delta = match axis {
+                        MouseAxis::X => self.mouse_delta.x(),
+                        MouseAxis::Y => self.mouse_delta.y(),
+                        MouseAxis::Wheel => self.mouse_wheel,
+                    };
+                    movement += delta * scale;
+                }
+            }
+        }
+        math::clamp(position, -1.0, 1.0) + movement
+    }
+
+    /// The raw position of a gamepad axis, without any dead zone.
+    #[inline]
+    pub fn gamepad_axis(&self, axis: GamepadAxis) -> f32 {
+        self.gamepad_axes[axis as usize]
+    }
+
+    /// How far the mouse moved this frame.
+    #[inline]
+    pub fn mouse_delta(&self) -> Vector2 {
+        self.mouse_delta
+    }
+
+    #[inline]
+    pub fn mouse_wheel(&self) -> f32 {
+        self.mouse_wheel
+    }
+
+    #[inline]
+    fn any(&self, action: &str, set: &HashSet<Binding>) -> bool {
+        self.map
+            .bindings(action)
+            .iter()
+            .any(|binding| set.contains(binding))
+    }
+}
+
+/// Zero inside the dead zone, rescaled so the output still smoothly covers \[0, 1\] outside it.
+#[inline]
+fn apply_dead_zone(value: f32, dead_zone: f32) -> f32 {
+    let magnitude = value.abs();
+    if magnitude <= dead_zone {
+        0.0
+    } else {
+        value.signum() * (magnitude - dead_zone) / (1.0 - dead_zone)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::{GamepadButton, Key};
+
+    fn input() -> Input {
+        let map = InputMap::read(
+            "
+            action jump = key:Space, gamepad:A
+            axis move = key:D, key:A scale=-1, gamepad_axis:LeftX dead_zone=0.2
+            axis look = mouse_axis:X scale=0.5, mouse_axis:Wheel scale=10
+            "
+            .as_bytes(),
+        )
+        .unwrap();
+        Input::new(map)
+    }
+
+    const SPACE: Binding = Binding::Key(Key::Space);
+    const A: Binding = Binding::Gamepad(GamepadButton::A);
+
+    #[test]
+    fn detects_edges() {
+        let mut input = input();
+        assert!(!input.held("jump") && !input.pressed("jump") && !input.released("jump"));
+
+        input.handle(InputEvent::Down(SPACE));
+        assert!(input.held("jump") && input.pressed("jump") && !input.released("jump"));
+        input.end_frame();
+
+        // Key repeats and other bindings of a held action don't press it again
+        input.handle(InputEvent::Down(SPACE));
+        input.handle(InputEvent::Down(A));
+        assert!(input.held("jump") && !input.pressed("jump"));
+        input.end_frame();
+
+        // Still held by the gamepad
+        input.handle(InputEvent::Up(SPACE));
+        assert!(input.held("jump") && !input.released("jump"));
+        input.end_frame();
+
+        input.handle(InputEvent::Up(A));
+        assert!(!input.held("jump") && !input.pressed("jump") && input.released("jump"));
+        input.end_frame();
+        assert!(!input.released("jump"));
+
+        // Unknown actions are never triggered
+        input.handle(InputEvent::Down(SPACE));
+        assert!(!input.held("nope") && !input.pressed("nope"));
+    }
+
+    #[test]
+    fn catches_taps_within_a_frame() {
+        let mut input = input();
+        input.handle(InputEvent::Down(SPACE));
+        input.handle(InputEvent::Up(SPACE));
+        assert!(!input.held("jump") && input.pressed("jump") && input.released("jump"));
+        input.end_frame();
+        assert!(!input.pressed("jump") && !input.released("jump"));
+    }
+
+    #[test]
+    fn combines_axes() {
+        let mut input = input();
+        input.handle(InputEvent::Down(Binding::Key(Key::A)));
+        assert_eq!(-1.0, input.axis("move"));
+        input.handle(InputEvent::Down(Binding::Key(Key::D)));
+        assert_eq!(0.0, input.axis("move"));
+        input.handle(InputEvent::GamepadAxis(GamepadAxis::LeftX, 0.6));
+        assert!((input.axis("move") - 0.5).abs() < 1e-6);
+        input.handle(InputEvent::Up(Binding::Key(Key::A)));
+        assert_eq!(1.0, input.axis("move"), "should clamp");
+
+        input.handle(InputEvent::Up(Binding::Key(Key::D)));
+        input.handle(InputEvent::GamepadAxis(GamepadAxis::LeftX, -0.15));
+        assert_eq!(0.0, input.axis("move"), "should be inside the dead zone");
+        assert_eq!(-0.15, input.gamepad_axis(GamepadAxis::LeftX));
+
+        input.release_all();
+        assert_eq!(0.0, input.gamepad_axis(GamepadAxis::LeftX));
+    }
+
+    #[test]
+    fn accumulates_mouse_movement() {
+        let mut input = input();
+        input.handle(InputEvent::MouseMotion { dx: 3.0, dy: 1.0 });
+        input.handle(InputEvent::MouseMotion { dx: 5.0, dy: -2.0 });
+        input.handle(InputEvent::MouseWheel(1.0));
+        assert_eq!(Vector2::new(8.0, -1.0), input.mouse_delta());
+        assert_eq!(14.0, input.axis("look"));
+
+        input.end_frame();
+        assert_eq!(Vector2::default(), input.mouse_delta());
+        assert_eq!(0.0, input.mouse_wheel());
+        assert_eq!(0.0, input.axis("look"));
+    }
+}
@@ -2,5 +2,6 @@ pub mod collections;
 pub mod frontend;
 pub mod game;
 pub mod gfx;
+pub mod input;
 pub mod math;
 pub mod util;
@@ -110,10 +110,12 @@ impl Matrix4 {
         ])
     }
 
+    /// Take OpenGL clip space to wgpu's by moving z from \[-1, 1\] to \[0, 1\]. Both have +x
+    /// right and +y up, so those are left alone.
     #[inline]
     pub const fn vulkan_projection_correct() -> Matrix4 {
         Matrix4([
-            Vector4([-1.0, 0.0, 0.0, 0.0]),
+            Vector4([1.0, 0.0, 0.0, 0.0]),
             Vector4([0.0, 1.0, 0.0, 0.0]),
             Vector4([0.0, 0.0, 0.5, 0.0]),
             Vector4([0.0, 0.0, 0.5, 1.0]),
@@ -212,6 +214,19 @@ impl Matrix4 {
         ])
     }
 
+    /// Multiply a row vector by the matrix.
+    #[inline]
+    pub fn transform(&self, v: Vector4) -> Vector4 {
+        self.0[0] * v.x() + self.0[1] * v.y() + self.0[2] * v.z() + self.0[3] * v.w()
+    }
+
+    /// Transform a point (treated as a row vector), including the perspective divide.
+    #[inline]
+    pub fn transform_point(&self, p: Vector3) -> Vector3 {
+        let v = self.transform(p.widened(1.0));
+        v.narrowed() / v.w()
+    }
+
     #[inline]
     pub fn to_bytes(&self) -> &[u8] {
         bytemuck::bytes_of(self)
@@ -222,10 +237,11 @@ impl From<Quaternion> for Matrix4 {
     #[inline]
     #[rustfmt::skip]
     fn from(q: Quaternion) -> Matrix4 {
+        // Each row is where the quaternion takes the corresponding unit axis
         Matrix4([
-            Vector4([2.0 * (q.0[0] * q.0[2] - q.0[3] * q.0[1]), 2.0 * (q.0[1] * q.0[2] + q.0[3] * q.0[0]), 1.0 - 2.0 * (q.0[0] * q.0[0] + q.0[1] * q.0[1]), 0.0]),
-            Vector4([1.0 - 2.0 * (q.0[1] * q.0[1] + q.0[2] * q.0[2]), 2.0 * (q.0[0] * q.0[1] - q.0[3] * q.0[2]), 2.0 * (q.0[0] * q.0[2] + q.0[3] * q.0[1]), 0.0]),
-            Vector4([2.0 * (q.0[0] * q.0[1] + q.0[3] * q.0[2]), 1.0 - 2.0 * (q.0[0] * q.0[0] + q.0[2] * q.0[2]), 2.0 * (q.0[1] * q.0[2] - q.0[3] * q.0[0]), 0.0]),
+            Vector4([1.0 - 2.0 * (q.0[1] * q.0[1] + q.0[2] * q.0[2]), 2.0 * (q.0[0] * q.0[1] + q.0[3] * q.0[2]), 2.0 * (q.0[0] * q.0[2] - q.0[3] * q.0[1]), 0.0]),
+            Vector4([2.0 * (q.0[0] * q.0[1] - q.0[3] * q.0[2]), 1.0 - 2.0 * (q.0[0] * q.0[0] + q.0[2] * q.0[2]), 2.0 * (q.0[1] * q.0[2] + q.0[3] * q.0[0]), 0.0]),
+            Vector4([2.0 * (q.0[0] * q.0[2] + q.0[3] * q.0[1]), 2.0 * (q.0[1] * q.0[2] - q.0[3] * q.0[0]), 1.0 - 2.0 * (q.0[0] * q.0[0] + q.0[1] * q.0[1]), 0.0]),
             Vector4([0.0, 0.0, 0.0, 1.0]),
         ])
     }
@@ -263,3 +279,72 @@ impl Mul<&Matrix4> for &Matrix4 {
         ret
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn assert_rows(expected: [[f32; 3]; 3], actual: Matrix4) {
+        for (row, expected) in expected.iter().enumerate() {
+            for (col, expected) in expected.iter().enumerate() {
+                assert!(
+                    (actual[row][col] - expected).abs() < 1e-6,
+                    "{:?} != {:?}",
+                    expected,
+                    actual
+                );
+            }
+            assert_eq!(0.0, actual[row][3]);
+        }
+        assert_eq!([0.0, 0.0, 0.0, 1.0], actual[3].0);
+    }
+
+    #[test]
+    fn corrects_depth_only() {
+        let correct = Matrix4::vulkan_projection_correct();
+        let corrected = correct.transform(Vector4::new(0.5, -0.25, -1.0, 1.0));
+        assert_eq!([0.5, -0.25, 0.0, 1.0], corrected.0);
+        let corrected = correct.transform(Vector4::new(-0.5, 0.25, 1.0, 1.0));
+        assert_eq!([-0.5, 0.25, 1.0, 1.0], corrected.0);
+    }
+
+    #[test]
+    fn identity_quaternion() {
+        assert_rows(
+            [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            Quaternion::identity().into(),
+        );
+    }
+
+    #[test]
+    fn quarter_turns() {
+        assert_rows(
+            [[1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]],
+            Quaternion::from_angle_right(FRAC_PI_2).into(),
+        );
+        assert_rows(
+            [[0.0, 0.0, -1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+            Quaternion::from_angle_up(FRAC_PI_2).into(),
+        );
+        assert_rows(
+            [[0.0, 1.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+            Quaternion::from_angle_forward(FRAC_PI_2).into(),
+        );
+    }
+
+    #[test]
+    fn rows_are_rotated_axes() {
+        let q = Quaternion::from_axis_angle(Vector3::new(1.0, 2.0, -3.0).normalized(), 0.7);
+        let m = Matrix4::from(q);
+        for (row, axis) in [Vector3::right(), Vector3::up(), Vector3::forward()]
+            .iter()
+            .enumerate()
+        {
+            let rotated = axis.rotated(q);
+            for col in 0..3 {
+                assert!((m[row][col] - rotated[col]).abs() < 1e-6);
+            }
+        }
+    }
+}
@@ -1,10 +1,12 @@
 mod matrix;
 mod quaternion;
+mod ray;
 mod triangle;
 mod vector;
 
 pub use matrix::*;
 pub use quaternion::*;
+pub use ray::*;
 pub use triangle::*;
 pub use vector::*;
 
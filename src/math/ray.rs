@@ -0,0 +1,21 @@
+use crate::math::Vector3;
+
+#[derive(Default, Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Vector3,
+    /// Should be normalized.
+    pub direction: Vector3,
+}
+
+impl Ray {
+    #[inline]
+    pub fn new(origin: Vector3, direction: Vector3) -> Ray {
+        Ray { origin, direction }
+    }
+
+    /// The point `t` units along the ray.
+    #[inline]
+    pub fn at(&self, t: f32) -> Vector3 {
+        self.origin + self.direction * t
+    }
+}
@@ -368,6 +368,18 @@ impl Mul for Vector3 {
     }
 }
 
+impl Div for Vector3 {
+    type Output = Vector3;
+    #[inline]
+    fn div(self, rhs: Vector3) -> Vector3 {
+        Vector3([
+            self.0[0] / rhs.0[0],
+            self.0[1] / rhs.0[1],
+            self.0[2] / rhs.0[2],
+        ])
+    }
+}
+
 impl Neg for Vector3 {
     type Output = Vector3;
     #[inline]
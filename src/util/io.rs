@@ -2,7 +2,7 @@ use crate::util::BoxedError;
 use std::{
     fmt::Display,
     fs::File,
-    io::{self, BufRead, BufReader, ErrorKind, Read, Seek},
+    io::{self, BufRead, BufReader, ErrorKind, Read, Seek, Write},
     path::Path,
     str::FromStr,
 };
@@ -71,7 +71,8 @@ pub fn parse<F: FromStr<Err = E>, E: Into<BoxedError>>(s: &str) -> io::Result<F>
 /// ```
 /// use dth::util;
 /// // fails with: "I was expecting an i32: <underlying FromStr error>"
-/// let x: i32 = util::parse_diagnostic("q", "I was expecting an i32")?;
+/// let x: std::io::Result<i32> = util::parse_diagnostic("q", &"I was expecting an i32");
+/// assert!(x.is_err());
 /// ```
 #[inline]
 pub fn parse_diagnostic<F: FromStr<Err = E>, E: Into<BoxedError>, D: Display>(
@@ -82,6 +83,16 @@ pub fn parse_diagnostic<F: FromStr<Err = E>, E: Into<BoxedError>, D: Display>(
         .map_err(|err: F::Err| invalid_data(format!("{}: {}", diagnostic, err.into())))
 }
 
+#[inline]
+pub fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    if reader.read(&mut bytes)? != bytes.len() {
+        io_err(ErrorKind::UnexpectedEof, "Could not read enough bytes")
+    } else {
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
 #[inline]
 pub fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
     let mut bytes = [0u8; 4];
@@ -111,3 +122,23 @@ pub fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
         Ok(u8::from_le_bytes(bytes))
     }
 }
+
+#[inline]
+pub fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+#[inline]
+pub fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+#[inline]
+pub fn write_u16<W: Write>(writer: &mut W, value: u16) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+#[inline]
+pub fn write_u8<W: Write>(writer: &mut W, value: u8) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}